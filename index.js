@@ -1,11 +1,13 @@
 console.log("ERROR");
 
-async function search(prompt) {
+async function search(prompt, offset = 0) {
   const resultsDiv = document.getElementById("results");
 
-  resultsDiv.innerHTML = "";
+  if (offset === 0) {
+    resultsDiv.innerHTML = "";
+  }
 
-  const response = await fetch("/api/search", {
+  const response = await fetch("/api/search?offset=" + offset, {
     method: "POST",
     headers: {
       "Content-Type": "text/plain",
@@ -13,16 +15,124 @@ async function search(prompt) {
     body: prompt,
   });
 
-  const json = await response.json();
+  const { total_hits, took_ms, results, did_you_mean } = await response.json();
+
+  if (offset === 0) {
+    resultsDiv.innerHTML = "";
+
+    const summary = document.createElement("div");
+    summary.className = "summary";
+    summary.appendChild(
+      document.createTextNode(`${total_hits} documents matched in ${took_ms} ms`)
+    );
+    resultsDiv.appendChild(summary);
 
-  resultsDiv.innerHTML = "";
+    if (did_you_mean) {
+      resultsDiv.appendChild(renderDidYouMean(did_you_mean));
+    }
+  } else {
+    resultsDiv.querySelector(".more")?.remove();
+  }
 
-  for ([path, rank] of json) {
+  for (const { path, snippets } of results) {
     let item = document.createElement("div");
     item.appendChild(document.createTextNode(path));
     item.appendChild(document.createElement("br"));
+
+    for (const snippet of snippets) {
+      item.appendChild(renderSnippet(snippet));
+    }
+
     resultsDiv.appendChild(item);
   }
+
+  const next = offset + results.length;
+
+  if (results.length > 0 && next < total_hits) {
+    resultsDiv.appendChild(renderMore(prompt, next));
+  }
+}
+
+// Button loading the next page of results below the current ones
+function renderMore(prompt, offset) {
+  const div = document.createElement("div");
+  div.className = "more";
+
+  const button = document.createElement("button");
+  button.appendChild(document.createTextNode("More results"));
+  button.addEventListener("click", () => search(prompt, offset));
+
+  div.appendChild(button);
+
+  return div;
+}
+
+// Completions of the query being typed, shown under the input
+async function suggest(prompt) {
+  const response = await fetch(
+    "/api/suggest?q=" + encodeURIComponent(prompt)
+  );
+
+  const { suggestions } = await response.json();
+
+  // a later keystroke may have changed the query meanwhile
+  if (document.getElementById("query").value !== prompt) {
+    return;
+  }
+
+  const list = document.getElementById("suggestions");
+  list.innerHTML = "";
+
+  for (const { text } of suggestions) {
+    const option = document.createElement("option");
+    option.value = text;
+    list.appendChild(option);
+  }
+}
+
+// Link searching for the corrected query
+function renderDidYouMean(correction) {
+  const div = document.createElement("div");
+  div.className = "did-you-mean";
+  div.appendChild(document.createTextNode("Did you mean: "));
+
+  const link = document.createElement("a");
+  link.href = "#";
+  link.appendChild(document.createTextNode(correction));
+  link.addEventListener("click", (e) => {
+    e.preventDefault();
+    document.getElementById("query").value = correction;
+    search(correction);
+  });
+
+  div.appendChild(link);
+
+  return div;
+}
+
+// Wraps the highlighted parts of the snippet text in <mark>
+function renderSnippet({ text, highlights }) {
+  const chars = Array.from(text);
+  const snippetDiv = document.createElement("div");
+  snippetDiv.className = "snippet";
+
+  let last = 0;
+
+  for (const [start, end] of highlights) {
+    snippetDiv.appendChild(
+      document.createTextNode(chars.slice(last, start).join(""))
+    );
+
+    const mark = document.createElement("mark");
+    mark.appendChild(document.createTextNode(chars.slice(start, end).join("")));
+    snippetDiv.appendChild(mark);
+
+    last = end;
+  }
+
+  snippetDiv.appendChild(document.createTextNode(chars.slice(last).join("")));
+
+  return snippetDiv;
 }
 
 window.onload = () => {
@@ -35,4 +145,10 @@ window.onload = () => {
       currentSearch.then(() => search(query.value));
     }
   });
+
+  query.addEventListener("input", () => {
+    if (query.value.trim() !== "") {
+      suggest(query.value);
+    }
+  });
 };
@@ -0,0 +1,231 @@
+/// Levenshtein automaton of a term, accepts the words within `max_distance` edits of it
+/// Insertions, deletions, substitutions and transpositions of adjacent characters
+/// all count as one edit (optimal string alignment distance)
+///
+/// A state is the row of edit distances between the word read so far
+/// and every prefix of the term, the state of a word is computed from
+/// the states of its prefixes so words sharing a prefix share their states
+#[derive(Debug)]
+pub struct LevenshteinAutomaton {
+    term: Vec<char>,
+    max_distance: usize,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(term: &str, max_distance: usize) -> Self {
+        Self {
+            term: term.chars().collect(),
+            max_distance,
+        }
+    }
+
+    /// State of the empty word
+    fn start(&self) -> Vec<usize> {
+        (0..=self.term.len()).collect()
+    }
+
+    /// State after reading the last character of `word`,
+    /// `states` holds the states of all the shorter prefixes of `word`
+    fn step(&self, states: &[Vec<usize>], word: &[char]) -> Vec<usize> {
+        let i = word.len();
+        let x = word[i - 1];
+        let row = &states[i - 1];
+
+        let mut next = Vec::with_capacity(row.len());
+        next.push(i);
+
+        for j in 1..row.len() {
+            let substitution = row[j - 1] + usize::from(self.term[j - 1] != x);
+            let mut distance = substitution.min(row[j] + 1).min(next[j - 1] + 1);
+
+            if i > 1 && j > 1 && x == self.term[j - 2] && word[i - 2] == self.term[j - 1] {
+                distance = distance.min(states[i - 2][j - 2] + 1);
+            }
+
+            next.push(distance);
+        }
+
+        next
+    }
+
+    /// Whether some word starting with the word of that state can still be accepted
+    fn can_match(&self, state: &[usize]) -> bool {
+        state.iter().min().is_some_and(|d| *d <= self.max_distance)
+    }
+
+    /// Returns the words of a sorted vocabulary accepted by the automaton with their distance
+    /// `seek` returns the first word of the vocabulary that is not smaller than its argument,
+    /// once a prefix cannot match anymore all the words starting with it are skipped at once
+    pub fn matches<'a, F>(&self, mut seek: F) -> Vec<(&'a str, usize)>
+    where
+        F: FnMut(&str) -> Option<&'a str>,
+    {
+        let mut matches = Vec::new();
+
+        // states of the prefixes of the previous word
+        let mut states = vec![self.start()];
+        let mut previous = Vec::new();
+        // the next word is the first one that is not smaller
+        let mut from = String::new();
+
+        while let Some(word) = seek(&from) {
+            let chars = word.chars().collect::<Vec<_>>();
+
+            let common = previous
+                .iter()
+                .zip(&chars)
+                .take_while(|(a, b)| a == b)
+                .count()
+                .min(states.len() - 1);
+
+            states.truncate(common + 1);
+
+            // length of the prefix of the word that cannot match anymore
+            let mut dead = None;
+
+            for i in common..chars.len() {
+                let state = self.step(&states, &chars[..=i]);
+
+                if !self.can_match(&state) {
+                    dead = Some(i + 1);
+                    break;
+                }
+
+                states.push(state);
+            }
+
+            from = match dead {
+                Some(len) => match skip_prefix(&chars[..len]) {
+                    Some(from) => from,
+                    None => break,
+                },
+                None => {
+                    let distance = states[states.len() - 1][self.term.len()];
+
+                    if distance <= self.max_distance {
+                        matches.push((word, distance));
+                    }
+
+                    // the smallest word greater than this one
+                    format!("{word}\0")
+                }
+            };
+
+            previous = chars;
+        }
+
+        matches
+    }
+}
+
+/// Smallest string greater than all the strings starting with `prefix`,
+/// `None` when there is none
+fn skip_prefix(prefix: &[char]) -> Option<String> {
+    let mut prefix = prefix.to_vec();
+
+    while let Some(last) = prefix.pop() {
+        // the surrogates are not characters
+        if let Some(next) = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32) {
+            prefix.push(next);
+            return Some(prefix.into_iter().collect());
+        }
+    }
+
+    None
+}
+
+/// Edit distance tolerated when a term is not in the index at all,
+/// short terms have too many neighbours to be corrected
+pub fn auto_distance(term: &str) -> usize {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Matches of the automaton in the sorted `vocabulary`, and the number of seeks
+    fn matches<'a>(
+        term: &str,
+        max_distance: usize,
+        vocabulary: &[&'a str],
+    ) -> (Vec<(&'a str, usize)>, usize) {
+        let mut seeks = 0;
+
+        let matches = LevenshteinAutomaton::new(term, max_distance).matches(|from| {
+            seeks += 1;
+            let i = vocabulary.partition_point(|word| *word < from);
+            vocabulary.get(i).copied()
+        });
+
+        (matches, seeks)
+    }
+
+    #[test]
+    fn edits_count_once() {
+        let vocabulary = [
+            "hsader", "sader", "shader", "shaders", "shadow", "shder", "shzder",
+        ];
+
+        assert_eq!(
+            matches("shader", 1, &vocabulary).0,
+            [
+                ("hsader", 1),
+                ("sader", 1),
+                ("shader", 0),
+                ("shaders", 1),
+                ("shder", 1),
+                ("shzder", 1),
+            ]
+        );
+        assert_eq!(matches("shader", 0, &vocabulary).0, [("shader", 0)]);
+        assert_eq!(
+            matches("shader", 2, &["shadow", "vertex"]).0,
+            [("shadow", 2)]
+        );
+    }
+
+    #[test]
+    fn transpositions_are_one_edit() {
+        assert_eq!(
+            matches("vertex", 1, &["vertxe", "vetrex", "vretex"]).0,
+            [("vertxe", 1), ("vetrex", 1), ("vretex", 1)]
+        );
+        // two swaps are two edits
+        assert_eq!(matches("vertex", 1, &["evtrex"]).0, []);
+        assert_eq!(matches("vertex", 2, &["evtrex"]).0, [("evtrex", 2)]);
+    }
+
+    #[test]
+    fn dead_prefixes_are_skipped() {
+        let mut vocabulary = (0..100).map(|i| format!("xa{i:02}")).collect::<Vec<_>>();
+        vocabulary.extend(["shade", "shader", "shaders", "shadow"].map(String::from));
+        vocabulary.sort();
+
+        let vocabulary = vocabulary.iter().map(String::as_str).collect::<Vec<_>>();
+        let (found, seeks) = matches("shader", 1, &vocabulary);
+
+        assert_eq!(found, [("shade", 1), ("shader", 0), ("shaders", 1)]);
+        // `shadow` and the words starting with `xa` are each skipped in one seek
+        assert!(seeks < 10, "{seeks} seeks");
+    }
+
+    #[test]
+    fn skip_prefix_is_past_every_word_of_the_prefix() {
+        assert_eq!(skip_prefix(&['a', 'b']), Some("ac".to_string()));
+        assert_eq!(skip_prefix(&['a', char::MAX]), Some("b".to_string()));
+        assert_eq!(skip_prefix(&['\u{d7ff}']), Some("\u{e000}".to_string()));
+        assert_eq!(skip_prefix(&[char::MAX]), None);
+    }
+
+    #[test]
+    fn short_terms_are_not_corrected() {
+        assert_eq!(auto_distance("gl"), 0);
+        assert_eq!(auto_distance("vetex"), 1);
+        assert_eq!(auto_distance("shdaer"), 2);
+    }
+}
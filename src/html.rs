@@ -0,0 +1,465 @@
+use crate::parser::ParsedDocument;
+
+// elements whose content is never shown
+const HIDDEN_ELEMENTS: &[&str] = &["script", "style", "template"];
+
+// elements that start a new line of text, the others do not separate words
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "caption",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "img",
+    "input",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "option",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+fn is_heading(name: &str) -> bool {
+    matches!(name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+}
+
+/// Text of an HTML page as it is being read
+#[derive(Debug, Default)]
+struct Page {
+    title: String,
+    // visible text
+    body: String,
+    headings: Vec<String>,
+    // text of the heading being read
+    heading: Option<String>,
+}
+
+impl Page {
+    fn push(&mut self, x: char) {
+        self.body.push(x);
+
+        if let Some(heading) = &mut self.heading {
+            heading.push(x);
+        }
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.body.push_str(text);
+
+        if let Some(heading) = &mut self.heading {
+            heading.push_str(text);
+        }
+    }
+
+    fn start_element(&mut self, name: &str) {
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.push(' ');
+        }
+
+        if is_heading(name) {
+            self.end_heading();
+            self.heading = Some(String::new());
+        }
+    }
+
+    fn end_element(&mut self, name: &str) {
+        if is_heading(name) {
+            self.end_heading();
+        }
+
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.push(' ');
+        }
+    }
+
+    fn end_heading(&mut self) {
+        if let Some(heading) = self.heading.take() {
+            let heading = collapse_whitespace(&heading);
+
+            if !heading.is_empty() {
+                self.headings.push(heading);
+            }
+        }
+    }
+}
+
+/// Forgiving HTML tokenizer, in the spirit of the HTML5 one
+///
+/// Unclosed and mismatched tags, unknown entities and stray `<` never fail,
+/// they are read as text or ignored
+struct Tokenizer<'a> {
+    content: &'a [char],
+}
+
+impl<'a> Tokenizer<'a> {
+    fn starts_with_ignore_case(&self, prefix: &str) -> bool {
+        let mut chars = self.content.iter();
+
+        prefix
+            .chars()
+            .all(|p| chars.next().is_some_and(|x| x.eq_ignore_ascii_case(&p)))
+    }
+
+    fn chop(&mut self, n: usize) -> &'a [char] {
+        let n = n.min(self.content.len());
+        let chopped = &self.content[0..n];
+        self.content = &self.content[n..];
+
+        chopped
+    }
+
+    fn chop_while<P>(&mut self, mut predicate: P) -> &'a [char]
+    where
+        P: FnMut(&char) -> bool,
+    {
+        let mut n = 0;
+        while n < self.content.len() && predicate(&self.content[n]) {
+            n += 1;
+        }
+
+        self.chop(n)
+    }
+
+    /// Chops everything up to `end`, and `end` itself if it is found
+    fn chop_until(&mut self, end: &str) -> &'a [char] {
+        let end = end.chars().collect::<Vec<_>>();
+
+        let n = self
+            .content
+            .windows(end.len())
+            .position(|w| w.iter().zip(&end).all(|(x, y)| x.eq_ignore_ascii_case(y)))
+            .unwrap_or(self.content.len());
+
+        let chopped = self.chop(n);
+        self.chop(end.len());
+
+        chopped
+    }
+
+    /// Reads a tag right after its `<` or `</`,
+    /// returns its lowercase name and whether it closes itself (`<br/>`)
+    fn tag(&mut self) -> (String, bool) {
+        let name = self
+            .chop_while(|x| x.is_alphanumeric() || matches!(x, '-' | ':' | '_'))
+            .iter()
+            .collect::<String>()
+            .to_lowercase();
+
+        let mut self_closing = false;
+
+        // attributes, their quoted values may contain `>`
+        while let Some(x) = self.content.first() {
+            match x {
+                '>' => {
+                    self.chop(1);
+                    break;
+                }
+                '"' | '\'' => {
+                    let quote = self.chop(1)[0];
+                    self.chop_while(|x| *x != quote);
+                    self.chop(1);
+                }
+                '/' => {
+                    self.chop(1);
+                    self_closing = self.content.first() == Some(&'>');
+                }
+                _ => {
+                    self.chop(1);
+                }
+            }
+        }
+
+        (name, self_closing)
+    }
+
+    /// Reads a character reference right after its `&`
+    /// Unknown references are kept as they are written
+    fn entity(&mut self) -> String {
+        let n = self
+            .content
+            .iter()
+            .take(32)
+            .position(|x| !(x.is_alphanumeric() || *x == '#'))
+            .unwrap_or(self.content.len().min(32));
+
+        let name = self.content[0..n].iter().collect::<String>();
+
+        let Some(decoded) = decode_entity(&name) else {
+            return "&".to_string();
+        };
+
+        self.chop(n);
+
+        if self.content.first() == Some(&';') {
+            self.chop(1);
+        }
+
+        decoded.to_string()
+    }
+
+    /// Text of an element holding no tags (`title`), with its entities decoded
+    fn raw_text(&mut self, name: &str) -> String {
+        let raw = self.chop_until(&format!("</{name}"));
+        self.chop_until(">");
+
+        decode_entities(raw)
+    }
+}
+
+/// Decodes the name of a character reference, `amp` or `#38` or `#x26`
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+
+        return char::from_u32(code);
+    }
+
+    let x = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" | "ensp" | "emsp" | "thinsp" => ' ',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "bull" => '•',
+        "middot" => '·',
+        "deg" => '°',
+        "times" => '×',
+        "euro" => '€',
+        "pound" => '£',
+        "sect" => '§',
+        "para" => '¶',
+        "aacute" => 'á',
+        "agrave" => 'à',
+        "acirc" => 'â',
+        "auml" => 'ä',
+        "eacute" => 'é',
+        "egrave" => 'è',
+        "ecirc" => 'ê',
+        "euml" => 'ë',
+        "iacute" => 'í',
+        "icirc" => 'î',
+        "iuml" => 'ï',
+        "oacute" => 'ó',
+        "ocirc" => 'ô',
+        "ouml" => 'ö',
+        "uacute" => 'ú',
+        "ugrave" => 'ù',
+        "ucirc" => 'û',
+        "uuml" => 'ü',
+        "ccedil" => 'ç',
+        "ntilde" => 'ñ',
+        "szlig" => 'ß',
+        "Eacute" => 'É',
+        "Auml" => 'Ä',
+        "Ouml" => 'Ö',
+        "Uuml" => 'Ü',
+        _ => return None,
+    };
+
+    Some(x)
+}
+
+fn decode_entities(raw: &[char]) -> String {
+    let mut tokenizer = Tokenizer { content: raw };
+    let mut text = String::new();
+
+    while let Some(x) = tokenizer.content.first() {
+        if *x == '&' {
+            tokenizer.chop(1);
+            text.push_str(&tokenizer.entity());
+        } else {
+            text.push(*x);
+            tokenizer.chop(1);
+        }
+    }
+
+    text
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Extracts the visible text of an HTML page, with its title and headings as metadata
+pub fn parse_html(source: &str) -> ParsedDocument {
+    let content = source.chars().collect::<Vec<_>>();
+    let mut tokenizer = Tokenizer { content: &content };
+    let mut page = Page::default();
+
+    while let Some(x) = tokenizer.content.first() {
+        match x {
+            '<' if tokenizer.starts_with_ignore_case("<!--") => {
+                tokenizer.chop_until("-->");
+            }
+            '<' if tokenizer.starts_with_ignore_case("<![CDATA[") => {
+                tokenizer.chop(9);
+                let text = tokenizer.chop_until("]]>").iter().collect::<String>();
+                page.push_str(&text);
+            }
+            // doctype and processing instructions
+            '<' if tokenizer
+                .content
+                .get(1)
+                .is_some_and(|x| matches!(x, '!' | '?')) =>
+            {
+                tokenizer.chop_until(">");
+            }
+            '<' if tokenizer.content.get(1) == Some(&'/') => {
+                tokenizer.chop(2);
+                let (name, _) = tokenizer.tag();
+                page.end_element(&name);
+            }
+            '<' if tokenizer.content.get(1).is_some_and(|x| x.is_alphabetic()) => {
+                tokenizer.chop(1);
+                let (name, self_closing) = tokenizer.tag();
+
+                if self_closing {
+                    page.start_element(&name);
+                    page.end_element(&name);
+                } else if HIDDEN_ELEMENTS.contains(&name.as_str()) {
+                    tokenizer.chop_until(&format!("</{name}"));
+                    tokenizer.chop_until(">");
+                } else if name == "title" {
+                    let title = tokenizer.raw_text(&name);
+
+                    if page.title.is_empty() {
+                        page.title = collapse_whitespace(&title);
+                    }
+                } else {
+                    page.start_element(&name);
+                }
+            }
+            '&' => {
+                tokenizer.chop(1);
+                let text = tokenizer.entity();
+                page.push_str(&text);
+            }
+            x => {
+                page.push(*x);
+                tokenizer.chop(1);
+            }
+        }
+    }
+
+    page.end_heading();
+
+    let mut document = ParsedDocument::new(page.body);
+
+    if !page.title.is_empty() {
+        document.add_metadata(ParsedDocument::TITLE, page.title);
+    }
+
+    for heading in page.headings {
+        document.add_metadata(ParsedDocument::HEADINGS, heading);
+    }
+
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(source: &str) -> String {
+        collapse_whitespace(&parse_html(source).text)
+    }
+
+    fn metadata(source: &str, key: &str) -> Vec<String> {
+        parse_html(source).metadata.remove(key).unwrap_or_default()
+    }
+
+    #[test]
+    fn title_headings_and_visible_text() {
+        let source = "<!DOCTYPE html><html><head><title> Vertex \n Shaders </title>\
+            <style>p { color: red }</style><script>if (a < b) {}</script></head>\
+            <body><h1>Shaders</h1><p>Compile <b>them</b></p><!-- <p>hidden</p> -->\
+            <h2 class=\"x\">Linking <em>programs</em></h2><p>Last</p></body></html>";
+
+        assert_eq!(text(source), "Shaders Compile them Linking programs Last");
+        assert_eq!(metadata(source, ParsedDocument::TITLE), ["Vertex Shaders"]);
+        assert_eq!(
+            metadata(source, ParsedDocument::HEADINGS),
+            ["Shaders", "Linking programs"]
+        );
+    }
+
+    #[test]
+    fn block_elements_separate_words() {
+        assert_eq!(text("<p>vertex</p><p>shader</p>"), "vertex shader");
+        assert_eq!(text("a<br/>b<br>c"), "a b c");
+        assert_eq!(text("<b>bo</b>ld"), "bold");
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            text("AT&amp;T &#38; &#x26; caf&eacute; &lt;tag&gt; &nbsp;x"),
+            "AT&T & & café <tag> x"
+        );
+        // unknown and unterminated references are kept as they are written
+        assert_eq!(text("a &bogus; b & c &"), "a &bogus; b & c &");
+        assert_eq!(text("<title>R&amp;D</title>"), "");
+        assert_eq!(metadata("<title>R&amp;D</title>", "title"), ["R&D"]);
+    }
+
+    #[test]
+    fn malformed_markup_is_read_as_text() {
+        assert_eq!(text("a < b and c<d"), "a < b and c");
+        assert_eq!(text("<p title='1 > 0'>text"), "text");
+        assert_eq!(text("<div><span>unclosed"), "unclosed");
+        assert_eq!(text("</p>stray</div>"), "stray");
+        assert_eq!(text("<![CDATA[x < y]]> z"), "x < y z");
+        assert_eq!(text("<script>never closed"), "");
+        assert_eq!(text("<!-- never closed"), "");
+    }
+
+    #[test]
+    fn unclosed_heading_ends_with_the_page() {
+        assert_eq!(metadata("<h3>Last <i>one</i>", "headings"), ["Last one"]);
+        assert_eq!(metadata("<h1> </h1>", "headings"), Vec::<String>::new());
+    }
+}
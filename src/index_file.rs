@@ -0,0 +1,506 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::lexer::LexerOptions;
+use crate::model::{Boosts, Doc, Field, FieldTerms, FileStamp, Model, Scorer, Spellings};
+use crate::segments::SegmentStore;
+
+// Binary index layout, integers are little endian:
+//
+// | magic "LSRI" | format version: u32 | for every section: length u64, crc32 u32 |
+// | documents | vocabulary | postings                                                |
+//
+// Every section is compressed on its own with deflate and holds a bincode encoding of:
+// * documents: the settings of the model and every document without its terms,
+//   but with how it spells them
+// * vocabulary: every term of the index, sorted
+// * postings: for every term of the vocabulary, in the same order, the rank of the
+//   documents containing it in the documents section and the positions of the term
+//   in every field holding it
+const MAGIC: &[u8; 4] = b"LSRI";
+const SECTIONS: usize = 3;
+const HEADER_LEN: usize = 4 + 4 + SECTIONS * (8 + 4);
+
+/// Version of the binary format, bumped every time its layout changes
+/// Indexes written with another version cannot be read and have to be rebuilt
+///
+/// * 1: the whole model in a single payload, and the first layouts of the sections
+/// * 2: the sections with the stamp and hash, the field counts and the lexer options
+/// * 3: the spellings of the documents
+pub const FORMAT_VERSION: u32 = 3;
+
+/// First section of an index file, the terms of the documents are in the postings
+#[derive(Deserialize, Serialize)]
+struct DocumentsSection {
+    scorer: Scorer,
+    boosts: Boosts,
+    lexer: LexerOptions,
+    docs: Vec<SavedDoc>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SavedDoc {
+    path: PathBuf,
+    // number of terms of every field, in the order of `Field::ALL`
+    counts: [usize; 4],
+    stamp: FileStamp,
+    hash: u32,
+    // terms spelled differently in the document, see `Doc::spellings`
+    spellings: Spellings,
+}
+
+// documents containing a term, by rank, with the positions of the term in their fields
+type SavedPostings = Vec<(u32, Vec<(Field, Vec<u32>)>)>;
+
+fn compress<T: Serialize>(value: &T, index_path: &Path) -> Result<Vec<u8>, ()> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+
+    bincode::serialize_into(&mut encoder, value).map_err(|err| {
+        eprintln!("ERROR: could not serialize index into file {index_path:?}: {err}");
+    })?;
+
+    encoder.finish().map_err(|err| {
+        eprintln!("ERROR: could not compress index {index_path:?}: {err}");
+    })
+}
+
+fn decompress<T: DeserializeOwned>(section: &[u8], index_path: &Path) -> Result<T, ()> {
+    bincode::deserialize_from(DeflateDecoder::new(section)).map_err(|err| {
+        eprintln!("ERROR: could not parse index file {index_path:?}: {err}");
+    })
+}
+
+/// Encodes the model in the binary format, `index_path` is only used in the errors
+fn encode_model(model: &Model, index_path: &Path) -> Result<Vec<u8>, ()> {
+    let mut ranks = HashMap::new();
+    let mut docs = Vec::with_capacity(model.docs.len());
+
+    for (id, path, doc) in model.docs.iter() {
+        ranks.insert(id, docs.len() as u32);
+        docs.push(SavedDoc {
+            path: path.to_path_buf(),
+            counts: doc.info().counts,
+            stamp: doc.stamp(),
+            hash: doc.hash(),
+            spellings: doc.spellings().clone(),
+        });
+    }
+
+    let mut vocabulary = model.index.keys().collect::<Vec<_>>();
+    vocabulary.sort();
+
+    let postings = vocabulary
+        .iter()
+        .map(|term| {
+            let mut postings = model.index[*term]
+                .keys()
+                .filter_map(|id| {
+                    let doc = model.docs.doc(*id)?;
+                    let fields = Field::ALL
+                        .into_iter()
+                        .filter_map(|field| {
+                            let positions = doc.field(field)?.positions.get(*term)?;
+                            Some((field, positions.iter().map(|p| *p as u32).collect()))
+                        })
+                        .collect();
+
+                    Some((ranks[id], fields))
+                })
+                .collect::<SavedPostings>();
+
+            postings.sort_by_key(|(rank, _)| *rank);
+            postings
+        })
+        .collect::<Vec<_>>();
+
+    let sections = [
+        compress(
+            &DocumentsSection {
+                scorer: model.scorer,
+                boosts: model.boosts,
+                lexer: model.lexer.clone(),
+                docs,
+            },
+            index_path,
+        )?,
+        compress(&vocabulary, index_path)?,
+        compress(&postings, index_path)?,
+    ];
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + sections.iter().map(Vec::len).sum::<usize>());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+    for section in &sections {
+        bytes.extend_from_slice(&(section.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&crc32fast::hash(section).to_le_bytes());
+    }
+
+    for section in &sections {
+        bytes.extend_from_slice(section);
+    }
+
+    Ok(bytes)
+}
+
+/// Decodes a binary index, checking its version and the checksum of every section
+fn decode_model(bytes: &[u8], index_path: &Path) -> Result<Model, ()> {
+    if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+        eprintln!("ERROR: {index_path:?} is not an index file");
+        return Err(());
+    }
+
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+
+    if version != FORMAT_VERSION {
+        eprintln!(
+            "ERROR: {index_path:?} has index format version {version}, expected {FORMAT_VERSION}"
+        );
+        return Err(());
+    }
+
+    let mut sections = Vec::with_capacity(SECTIONS);
+    let mut offset = HEADER_LEN;
+
+    for i in 0..SECTIONS {
+        let entry = &bytes[8 + i * 12..8 + (i + 1) * 12];
+        let length = u64::from_le_bytes(entry[0..8].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+
+        let section = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .filter(|section| crc32fast::hash(section) == checksum)
+            .ok_or_else(|| {
+                eprintln!("ERROR: {index_path:?} is corrupted");
+            })?;
+
+        sections.push(section);
+        offset += length;
+    }
+
+    if offset != bytes.len() {
+        eprintln!("ERROR: {index_path:?} is corrupted");
+        return Err(());
+    }
+
+    let documents: DocumentsSection = decompress(sections[0], index_path)?;
+    let vocabulary: Vec<String> = decompress(sections[1], index_path)?;
+    let postings: Vec<SavedPostings> = decompress(sections[2], index_path)?;
+
+    if vocabulary.len() != postings.len() {
+        eprintln!("ERROR: {index_path:?} is corrupted");
+        return Err(());
+    }
+
+    let mut fields = vec![BTreeMap::<Field, FieldTerms>::new(); documents.docs.len()];
+
+    for (term, postings) in vocabulary.into_iter().zip(postings) {
+        for (rank, term_fields) in postings {
+            let Some(doc_fields) = fields.get_mut(rank as usize) else {
+                eprintln!("ERROR: {index_path:?} is corrupted");
+                return Err(());
+            };
+
+            for (field, positions) in term_fields {
+                let terms = doc_fields.entry(field).or_default();
+                let positions = positions
+                    .into_iter()
+                    .map(|p| p as usize)
+                    .collect::<Vec<_>>();
+
+                terms.tf.insert(term.clone(), positions.len());
+                terms.positions.insert(term.clone(), positions);
+            }
+        }
+    }
+
+    let mut model = Model::new(documents.scorer, documents.boosts, documents.lexer);
+
+    for (doc, mut fields) in documents.docs.into_iter().zip(fields) {
+        let SavedDoc {
+            path,
+            counts,
+            stamp,
+            hash,
+            spellings,
+        } = doc;
+
+        for (field, terms) in &mut fields {
+            terms.count = counts[*field as usize];
+        }
+
+        model.insert_document(path, Doc::from_fields(fields, spellings, stamp, hash));
+    }
+
+    Ok(model)
+}
+
+/// Writes the file next to its destination and renames it once it is on the disk,
+/// so that a crash never leaves half a file behind
+pub fn write_file(file_path: &Path, bytes: &[u8]) -> Result<(), ()> {
+    let temp_path = file_path.with_extension("tmp");
+
+    let file = File::create(&temp_path).map_err(|err| {
+        eprintln!("ERROR: could not create file {temp_path:?}: {err}");
+    })?;
+
+    let mut writer = BufWriter::new(file);
+
+    writer
+        .write_all(bytes)
+        .and_then(|()| writer.flush())
+        .and_then(|()| writer.get_ref().sync_all())
+        .map_err(|err| {
+            eprintln!("ERROR: could not write file {temp_path:?}: {err}");
+        })?;
+
+    fs::rename(&temp_path, file_path).map_err(|err| {
+        eprintln!("ERROR: could not move {temp_path:?} to {file_path:?}: {err}");
+    })?;
+
+    sync_dir(file_path)
+}
+
+/// Flushes the folder holding `file_path` to the disk, so that a file renamed
+/// in it stays renamed after a crash
+pub fn sync_dir(file_path: &Path) -> Result<(), ()> {
+    let dir_path = match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(dir_path)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|err| {
+            eprintln!("ERROR: could not flush folder {dir_path:?}: {err}");
+        })
+}
+
+/// Save the model to a binary index file
+pub fn save_model(model: &Model, index_path: &Path) -> Result<(), ()> {
+    println!("Saving {index_path:?}...");
+
+    write_file(index_path, &encode_model(model, index_path)?)
+}
+
+/// Reads an index file, binary indexes and the older JSON ones are both accepted
+/// as well as the segmented index folders of `serve`
+pub fn load_model(index_path: &Path) -> Result<Model, ()> {
+    if index_path.is_dir() {
+        return SegmentStore::open(index_path).map(|(_, model)| model);
+    }
+
+    let mut bytes = Vec::new();
+
+    File::open(index_path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|err| {
+            eprintln!("ERROR: could not read index file {index_path:?}: {err}");
+        })?;
+
+    if bytes.starts_with(MAGIC) {
+        return decode_model(&bytes, index_path);
+    }
+
+    let mut model: Model = serde_json::from_slice(&bytes).map_err(|err| {
+        eprintln!("ERROR: could not parse index file {index_path:?}: {err}");
+    })?;
+
+    model.rebuild_index();
+
+    Ok(model)
+}
+
+/// Save the model to a JSON file, mostly useful for debugging
+pub fn save_model_as_json(model: &Model, json_path: &Path) -> Result<(), ()> {
+    println!("Saving {json_path:?}...");
+
+    let json_file = File::create(json_path).map_err(|err| {
+        eprintln!("ERROR: could not create index file {json_path:?}: {err}");
+    })?;
+
+    serde_json::to_writer(BufWriter::new(json_file), &model).map_err(|err| {
+        eprintln!("ERROR: could not serialze index into file {json_path:?}: {err}");
+    })?;
+
+    Ok(())
+}
+
+/// Reads a model saved with `save_model_as_json`
+pub fn load_model_from_json(json_path: &Path) -> Result<Model, ()> {
+    let json_file = File::open(json_path).map_err(|err| {
+        eprintln!("ERROR: could not open index file {json_path:?}: {err}");
+    })?;
+
+    let mut model: Model = serde_json::from_reader(BufReader::new(json_file)).map_err(|err| {
+        eprintln!("ERROR: could not parse index file {json_path:?}: {err}");
+    })?;
+
+    model.rebuild_index();
+
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser::ParsedDocument;
+    use crate::query;
+
+    const STAMP: FileStamp = FileStamp {
+        last_modified: 0,
+        size: 0,
+    };
+
+    fn model() -> Model {
+        let mut model = Model::new(
+            Scorer::Bm25 { k1: 1.5, b: 0.5 },
+            Boosts::default(),
+            LexerOptions {
+                code: true,
+                ..LexerOptions::default()
+            },
+        );
+
+        for (path, text) in [
+            ("/d/1", "vertex shader reading the vertex buffer"),
+            ("/d/2", "fragment shader"),
+            ("/d/3", "index buffer"),
+        ] {
+            let parsed = ParsedDocument::new(text.to_string());
+            model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
+        }
+
+        model
+    }
+
+    fn search(model: &Model, query: &str) -> Vec<(PathBuf, f32)> {
+        let chars = query.chars().collect::<Vec<_>>();
+        let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+
+        // documents of the same score come in any order
+        let mut results = model.search(&query, model.scorer);
+        results.sort_by(|(a, x), (b, y)| y.total_cmp(x).then_with(|| a.cmp(b)));
+
+        results
+    }
+
+    fn encode(model: &Model) -> Vec<u8> {
+        encode_model(model, Path::new("test.index")).unwrap()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Model, ()> {
+        decode_model(bytes, Path::new("test.index"))
+    }
+
+    #[test]
+    fn models_round_trip() {
+        let model = model();
+        let decoded = decode(&encode(&model)).unwrap();
+
+        assert_eq!(decoded.scorer, model.scorer);
+        assert_eq!(decoded.boosts, model.boosts);
+        assert_eq!(decoded.lexer, model.lexer);
+        assert_eq!(decoded.df, model.df);
+        assert_eq!(decoded.field_counts, model.field_counts);
+
+        for query in [
+            "vertex",
+            "shader -fragment",
+            "\"index buffer\"",
+            "buffer OR shader",
+        ] {
+            assert_eq!(search(&decoded, query), search(&model, query));
+        }
+
+        // positions are saved, phrases still match
+        assert_eq!(search(&decoded, "\"vertex buffer\"~2").len(), 1);
+    }
+
+    #[test]
+    fn spellings_round_trip() {
+        let mut model = Model::default();
+
+        for (path, text) in [
+            ("/d/1", "Shaders compile"),
+            ("/d/2", "shaders link"),
+            ("/d/3", "a shader"),
+        ] {
+            let parsed = ParsedDocument::new(text.to_string());
+            model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
+        }
+
+        let decoded = decode(&encode(&model)).unwrap();
+        assert_eq!(decoded.spellings, model.spellings);
+
+        // the surface forms, not the stems
+        for (term, spelling) in [("shader", "shaders"), ("compil", "compile")] {
+            assert_eq!(model.spelling(term), spelling);
+            assert_eq!(decoded.spelling(term), spelling);
+        }
+
+        let query = "shdaers compiel".chars().collect::<Vec<_>>();
+        assert_eq!(
+            decoded.did_you_mean(&query),
+            Some("shaders compile".to_string())
+        );
+        assert_eq!(decoded.did_you_mean(&query), model.did_you_mean(&query));
+    }
+
+    #[test]
+    fn empty_models_round_trip() {
+        let decoded = decode(&encode(&Model::default())).unwrap();
+
+        assert!(decoded.docs.is_empty());
+        assert!(decoded.index.is_empty());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut bytes = encode(&model());
+        bytes[0..4].copy_from_slice(b"{\"do");
+
+        assert!(decode(&bytes).is_err());
+        assert!(decode(b"LSRI").is_err());
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = encode(&model());
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn corrupted_sections_are_rejected() {
+        let bytes = encode(&model());
+
+        // one byte flipped in every section in turn
+        let mut offset = HEADER_LEN;
+
+        for i in 0..SECTIONS {
+            let entry = &bytes[8 + i * 12..16 + i * 12];
+            let length = u64::from_le_bytes(entry.try_into().unwrap()) as usize;
+
+            let mut corrupted = bytes.clone();
+            corrupted[offset + length / 2] ^= 0xff;
+            assert!(decode(&corrupted).is_err(), "section {i}");
+
+            offset += length;
+        }
+
+        // truncated or with trailing bytes
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(&[bytes.as_slice(), &[0]].concat()).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::lexer::LexerOptions;
+use crate::model::{content_hash, Doc, FileStamp, Model};
+use crate::parser::ParserRegistry;
+use crate::sniff;
+
+/// File waiting to be parsed by a worker
+#[derive(Debug)]
+struct Job {
+    file_path: PathBuf,
+    stamp: FileStamp,
+    // hash of the content when the file was indexed before
+    indexed_hash: Option<u32>,
+}
+
+/// Outcome of a job
+#[derive(Debug)]
+enum Parsed {
+    Doc(PathBuf, Doc),
+    // the file was touched but its content is the same
+    Unchanged(PathBuf, FileStamp),
+    // no parser can read the file
+    Rejected(PathBuf, FileStamp),
+}
+
+/// Number of workers used when none is asked for, one per available core
+pub fn default_jobs() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Dot files and folders are never indexed, this includes the index itself
+pub fn is_dot_file(file_path: &Path) -> bool {
+    file_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|s| s.starts_with("."))
+        .unwrap_or(false)
+}
+
+/// Parses and tokenizes the queued files until the queue is closed
+fn parse_files(
+    jobs: &Mutex<Receiver<Job>>,
+    docs: Sender<Parsed>,
+    parsers: &ParserRegistry,
+    lexer: &LexerOptions,
+) {
+    loop {
+        // the lock is released as soon as a job is received
+        let job = jobs.lock().unwrap().recv();
+
+        let Ok(Job {
+            file_path,
+            stamp,
+            indexed_hash,
+        }) = job
+        else {
+            return;
+        };
+
+        // only the first bytes are read until a parser is found
+        let head = match sniff::read_head(&file_path) {
+            Ok(head) => head,
+            Err(err) => {
+                eprintln!("ERROR: could not read file {file_path:?}: {err}");
+                continue;
+            }
+        };
+
+        // binary and unknown files are expected in a folder, they are not errors
+        let Some(parser) = parsers.detect(&file_path, &head) else {
+            println!("Ignoring {file_path:?} as it is not a supported document");
+
+            if docs.send(Parsed::Rejected(file_path, stamp)).is_err() {
+                return;
+            }
+
+            continue;
+        };
+
+        let hash = match content_hash(&file_path) {
+            Ok(hash) => hash,
+            Err(err) => {
+                eprintln!("ERROR: could not read file {file_path:?}: {err}");
+                continue;
+            }
+        };
+
+        if indexed_hash == Some(hash) {
+            if docs.send(Parsed::Unchanged(file_path, stamp)).is_err() {
+                return;
+            }
+
+            continue;
+        }
+
+        println!("Indexing {:?}... ", &file_path);
+
+        let parsed = match parser.parse(&file_path) {
+            Ok(parsed) => parsed,
+            Err(()) => {
+                println!("Err");
+                continue;
+            }
+        };
+
+        let doc = Doc::new(&file_path, &parsed, stamp, hash, lexer);
+
+        if docs.send(Parsed::Doc(file_path, doc)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Indexes a directory recursively
+///
+/// The directory is walked on its own thread while `n_jobs` workers parse and tokenize
+/// the files concurrently. The model is only locked to merge each tokenized document,
+/// so searches are not blocked while the files are parsed.
+/// Returns the number of documents (re)indexed. When the walk fails part of the way,
+/// the error holds the number of documents indexed until then, they stay in the model.
+pub fn add_folder_to_model(
+    dir_path: &Path,
+    model: Arc<Mutex<Model>>,
+    parsers: &ParserRegistry,
+    n_jobs: usize,
+) -> Result<usize, usize> {
+    let (job_sender, job_receiver) = mpsc::channel::<Job>();
+    let job_receiver = Mutex::new(job_receiver);
+    let (doc_sender, doc_receiver) = mpsc::channel::<Parsed>();
+    let lexer = &model.lock().unwrap().lexer.clone();
+
+    thread::scope(|scope| {
+        for _ in 0..n_jobs.max(1) {
+            let job_receiver = &job_receiver;
+            let doc_sender = doc_sender.clone();
+
+            scope.spawn(move || parse_files(job_receiver, doc_sender, parsers, lexer));
+        }
+
+        // the merge below ends once every worker has dropped its sender
+        drop(doc_sender);
+
+        let walker = {
+            let model = &model;
+            scope.spawn(move || queue_folder(dir_path, model, parsers, &job_sender))
+        };
+
+        let mut processed = 0;
+
+        for parsed in doc_receiver {
+            match parsed {
+                Parsed::Doc(file_path, doc) => {
+                    model.lock().unwrap().insert_document(file_path, doc);
+                    processed += 1;
+                }
+                Parsed::Unchanged(file_path, stamp) => {
+                    model.lock().unwrap().touch_document(&file_path, stamp);
+                }
+                Parsed::Rejected(file_path, stamp) => {
+                    model.lock().unwrap().rejected.insert(file_path, stamp);
+                }
+            }
+        }
+
+        match walker.join().expect("the walker does not panic") {
+            Ok(()) => Ok(processed),
+            Err(()) => Err(processed),
+        }
+    })
+}
+
+/// Removes the documents whose file does not exist anymore
+/// or is not inside of the indexed `root` folder, and forgets such rejected files
+///
+/// The files are checked without holding the lock, which is only taken
+/// to list the documents and then to remove the missing ones.
+/// Returns the number of documents removed.
+pub fn prune_folder(root: &Path, model: &Mutex<Model>) -> usize {
+    let (paths, rejected) = {
+        let model = model.lock().unwrap();
+
+        let paths = model.paths().map(Path::to_path_buf).collect::<Vec<_>>();
+        let rejected = model.rejected.keys().cloned().collect::<Vec<_>>();
+
+        (paths, rejected)
+    };
+
+    let is_missing = |path: &PathBuf| !path.starts_with(root) || !path.is_file();
+
+    let pruned = paths.into_iter().filter(is_missing).collect::<Vec<_>>();
+    let forgotten = rejected.into_iter().filter(is_missing).collect::<Vec<_>>();
+
+    let mut model = model.lock().unwrap();
+
+    for path in &forgotten {
+        model.rejected.remove(path);
+    }
+
+    for path in &pruned {
+        println!("Pruning {path:?}...");
+        model.remove_document(path);
+    }
+
+    pruned.len()
+}
+
+/// Walks a directory recursively and queues the files that require reindexing
+/// The files that are obviously not documents and the ones rejected before are skipped
+fn queue_folder(
+    dir_path: &Path,
+    model: &Mutex<Model>,
+    parsers: &ParserRegistry,
+    jobs: &Sender<Job>,
+) -> Result<(), ()> {
+    let dir = fs::read_dir(dir_path).map_err(|err| {
+        eprintln!("ERROR: could not open directory {dir_path:?} for indexing : {err}");
+    })?;
+
+    'next_file: for file in dir {
+        // 'next_file for naming the loop
+        let file = file.map_err(|err| {
+            eprintln!("ERROR: could not read next file in directory {dir_path:?}: {err}");
+        })?;
+
+        let file_path = file.path();
+
+        // Skip if dot file
+        if is_dot_file(&file_path) {
+            continue 'next_file;
+        }
+
+        let file_type = file.file_type().map_err(|err| {
+            eprintln!("ERROR: couldnot determine file type for {file_path:?}: {err}");
+        })?;
+
+        if file_type.is_dir() {
+            queue_folder(&file_path, model, parsers, jobs)?;
+            continue 'next_file;
+        }
+
+        if parsers.skips(&file_path) {
+            println!("Ignoring {file_path:?} as it is not a supported document");
+            continue 'next_file;
+        }
+
+        let stamp = file
+            .metadata()
+            .and_then(|metadata| FileStamp::from_metadata(&metadata))
+            .map_err(|err| {
+                eprintln!("ERROR: could not get the metadata of the file {file_path:?}: {err}");
+            })?;
+
+        // `None` when the file is up to date, otherwise the hash it was indexed with if any
+        let indexed_hash = {
+            let model = model.lock().unwrap();
+
+            if model.is_rejected(&file_path, stamp) {
+                continue 'next_file;
+            }
+
+            if model.requires_reindexing(&file_path, stamp) {
+                Some(model.indexed_hash(&file_path))
+            } else {
+                None
+            }
+        };
+
+        if let Some(indexed_hash) = indexed_hash {
+            // the workers are gone only if the indexing is being torn down
+            if jobs
+                .send(Job {
+                    file_path,
+                    stamp,
+                    indexed_hash,
+                })
+                .is_err()
+            {
+                return Err(());
+            }
+        } else {
+            println!(r#"Ignoring {file_path:?} as it is already indexed"#);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::parser::ParsedDocument;
+
+    const STAMP: FileStamp = FileStamp {
+        last_modified: 0,
+        size: 0,
+    };
+
+    /// Empty folder of the test, removed when it is dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir_path = std::env::temp_dir().join(format!("indexer-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir_path);
+            fs::create_dir_all(&dir_path).unwrap();
+
+            Self(dir_path)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf {
+            let file_path = self.0.join(name);
+            fs::write(&file_path, content).unwrap();
+
+            file_path
+        }
+
+        /// Nests folders in `name` until their path is too long to be opened
+        fn nest_too_deep(&self, name: &str) {
+            let level = "d".repeat(200);
+            let chain = |root: PathBuf| {
+                let deepest = (0..12).fold(root, |path, _| path.join(&level));
+                fs::create_dir_all(&deepest).unwrap();
+                deepest
+            };
+
+            // every path created stays short enough, only moving one chain
+            // into the other makes the deepest folders too long
+            let inner = chain(self.0.join("inner"));
+            fs::write(inner.join("lost.txt"), "never indexed").unwrap();
+
+            let outer = chain(self.0.join(name));
+            fs::rename(self.0.join("inner"), outer.join("inner")).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn deleted_files_are_pruned_and_changed_ones_requeued() {
+        let dir = TempDir::new("prune");
+        let deleted = dir.write("deleted.txt", "vertex shader");
+        let changed = dir.write("changed.txt", "vertex buffer");
+        dir.write("kept.txt", "index buffer");
+
+        let model = Arc::new(Mutex::new(Model::default()));
+        let indexed =
+            add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2);
+        assert_eq!(indexed, Ok(3));
+
+        // nothing changed on disk
+        assert_eq!(
+            add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2),
+            Ok(0)
+        );
+        assert_eq!(prune_folder(&dir.0, &model), 0);
+
+        fs::remove_file(&deleted).unwrap();
+        dir.write("changed.txt", "vertex buffer binding");
+
+        assert_eq!(prune_folder(&dir.0, &model), 1);
+        assert_eq!(
+            add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2),
+            Ok(1)
+        );
+
+        let model = model.lock().unwrap();
+        let mut paths = model.docs.paths().collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, [changed.as_path(), dir.0.join("kept.txt").as_path()]);
+    }
+
+    #[test]
+    fn touched_files_with_the_same_content_are_not_parsed_again() {
+        let dir = TempDir::new("touch");
+        let file_path = dir.write("a.txt", "vertex shader");
+
+        let model = Arc::new(Mutex::new(Model::default()));
+        let indexed =
+            add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2);
+        assert_eq!(indexed, Ok(1));
+
+        // only the modification time changes
+        let file = fs::File::options().write(true).open(&file_path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .unwrap();
+        let stamp = FileStamp::from_metadata(&file.metadata().unwrap()).unwrap();
+        assert!(model.lock().unwrap().requires_reindexing(&file_path, stamp));
+
+        assert_eq!(
+            add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2),
+            Ok(0)
+        );
+
+        // the new time is recorded so the file is not hashed again
+        assert!(!model.lock().unwrap().requires_reindexing(&file_path, stamp));
+    }
+
+    #[test]
+    fn a_walk_error_reports_the_documents_indexed_until_then() {
+        let dir = TempDir::new("walk-error");
+        dir.write("a.txt", "vertex shader");
+        dir.write("b.txt", "vertex buffer");
+        dir.nest_too_deep("deep");
+
+        let model = Arc::new(Mutex::new(Model::default()));
+        let result = add_folder_to_model(&dir.0, Arc::clone(&model), &ParserRegistry::default(), 2);
+
+        // the files walked before the folder that cannot be opened stay indexed
+        let model = model.lock().unwrap();
+        assert_eq!(result, Err(model.docs.len()));
+        assert!(model.docs.paths().all(|path| path.parent() == Some(&dir.0)));
+    }
+
+    #[test]
+    fn a_missing_folder_indexes_nothing() {
+        let dir = TempDir::new("missing");
+        let model = Arc::new(Mutex::new(Model::default()));
+
+        let result =
+            add_folder_to_model(&dir.0.join("missing"), model, &ParserRegistry::default(), 2);
+        assert_eq!(result, Err(0));
+    }
+
+    #[test]
+    fn documents_outside_of_the_root_are_pruned() {
+        let dir = TempDir::new("outside");
+        let file_path = dir.write("a.txt", "vertex shader");
+
+        let model = Mutex::new(Model::default());
+        model.lock().unwrap().add_document(
+            file_path,
+            STAMP,
+            0,
+            &ParsedDocument::new("a".to_string()),
+        );
+
+        assert_eq!(prune_folder(&dir.0.join("other"), &model), 1);
+        assert!(model.lock().unwrap().docs.is_empty());
+    }
+}
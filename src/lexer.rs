@@ -1,19 +1,148 @@
+use serde::{Deserialize, Serialize};
+
 use crate::snowball;
+use crate::stopwords::Language;
+
+/// A term along with where it was found in the content
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub term: String,
+    // character offsets of the token in the content, `end` is exclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Splits a word at its case changes, `glWaitSync` into `gl`, `Wait` and `Sync`
+/// Runs of capitals are kept together, `XMLHttpRequest` gives `XML`, `Http` and `Request`
+pub fn split_camel_case(word: &str) -> Vec<&str> {
+    let chars = word.char_indices().collect::<Vec<_>>();
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for i in 1..chars.len() {
+        let (offset, x) = chars[i];
+        let previous = chars[i - 1].1;
+        let next = chars.get(i + 1).map(|(_, x)| *x);
+
+        let boundary = (!previous.is_uppercase() && previous.is_alphanumeric() && x.is_uppercase())
+            || (previous.is_uppercase()
+                && x.is_uppercase()
+                && next.is_some_and(|n| n.is_lowercase()));
+
+        if boundary {
+            parts.push(&word[start..offset]);
+            start = offset;
+        }
+    }
+
+    if start < word.len() {
+        parts.push(&word[start..]);
+    }
+
+    parts
+}
+
+/// How the content is split into terms, documents and queries have to be
+/// tokenized the same way so the options are saved with the index
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct LexerOptions {
+    /// Identifiers such as `glActiveShaderProgram` or `GL_MAX_DRAW_BUFFERS` are kept whole,
+    /// lowercased but not stemmed, and followed by their camelCase and snake_case parts
+    pub code: bool,
+    /// Words of the built-in list of that language are left out, positions stay contiguous
+    #[serde(default)]
+    pub stopwords: Option<Language>,
+    /// Other words left out along with the built-in list, lowercase and sorted
+    #[serde(default)]
+    pub extra_stopwords: Vec<String>,
+    /// Version of the tokenization the index was built with, 0 for indexes older than
+    /// the versioning, their documents are reindexed when it is not `LexerOptions::VERSION`
+    #[serde(default)]
+    pub version: u32,
+}
+
+impl Default for LexerOptions {
+    fn default() -> Self {
+        Self {
+            code: false,
+            stopwords: None,
+            extra_stopwords: Vec::new(),
+            version: LexerOptions::VERSION,
+        }
+    }
+}
+
+impl LexerOptions {
+    /// Bumped every time the same options split the content differently
+    pub const VERSION: u32 = 1;
+
+    /// Options of the indexes saved without lexer options, their documents are
+    /// reindexed with the current version
+    pub fn unversioned() -> Self {
+        Self {
+            version: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the lowercase `word` is left out
+    pub fn is_stopword(&self, word: &str) -> bool {
+        self.stopwords
+            .is_some_and(|language| language.is_stopword(word))
+            || self
+                .extra_stopwords
+                .binary_search_by(|stopword| stopword.as_str().cmp(word))
+                .is_ok()
+    }
+}
 
 // Lexer should contain the parsed document, doesn't modify
 #[derive(Debug)]
 pub struct Lexer<'a> {
     content: &'a [char],
+    // number of characters already chopped from the content
+    offset: usize,
+    options: &'a LexerOptions,
+    // whether identifiers are lexed, off for their parts
+    code: bool,
+    // parts of the last identifier not emitted yet, in reverse order
+    parts: Vec<String>,
+    // offset of the last token, the parts of an identifier share its span
+    start: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+        const DEFAULT: &LexerOptions = &LexerOptions {
+            code: false,
+            stopwords: None,
+            extra_stopwords: Vec::new(),
+            version: LexerOptions::VERSION,
+        };
+
+        Self::with_options(content, DEFAULT)
+    }
+
+    pub fn with_options(content: &'a [char], options: &'a LexerOptions) -> Self {
+        Self {
+            content,
+            offset: 0,
+            options,
+            code: options.code,
+            parts: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Iterates over the tokens along with their offsets in the content
+    pub fn tokens(mut self) -> impl Iterator<Item = Token> + 'a {
+        std::iter::from_fn(move || self.next_spanned_token())
     }
 
     fn chop(&mut self, n: usize) -> &'a [char] {
         let token = &self.content[0..n];
         self.content = &self.content[n..];
+        self.offset += n;
 
         token
     }
@@ -27,48 +156,119 @@ impl<'a> Lexer<'a> {
             n += 1;
         }
 
-        return self.chop(n);
+        self.chop(n)
+    }
+
+    fn next_spanned_token(&mut self) -> Option<Token> {
+        let term = self.next_token()?;
+
+        Some(Token {
+            term,
+            start: self.start,
+            end: self.offset,
+        })
     }
 
     fn next_token(&mut self) -> Option<String> {
-        // trim whitespaces from left
-        self.trim_left();
+        if let Some(part) = self.parts.pop() {
+            return Some(part);
+        }
+
+        // stopwords are skipped
+        loop {
+            // trim whitespaces and punctuation from left
+            self.trim_left();
+            self.start = self.offset;
 
-        if self.content.len() == 0 {
+            if self.content.is_empty() {
+                return None;
+            }
+
+            if self.code && (self.content[0].is_alphabetic() || self.content[0] == '_') {
+                let identifier = self
+                    .chop_while(|x| x.is_alphanumeric() || *x == '_')
+                    .iter()
+                    .collect::<String>();
+
+                // a single word is lexed as it would be outside of code
+                if identifier.chars().all(|x| x.is_alphabetic())
+                    && split_camel_case(&identifier).len() == 1
+                {
+                    match self.word(&identifier) {
+                        Some(term) => return Some(term),
+                        None => continue,
+                    }
+                }
+
+                match self.identifier(&identifier) {
+                    Some(term) => return Some(term),
+                    None => continue,
+                }
+            }
+
+            // Lex alphabetic words
+            if self.content[0].is_alphabetic() {
+                let word = self
+                    .chop_while(|x| x.is_alphabetic())
+                    .iter()
+                    .collect::<String>();
+
+                match self.word(&word) {
+                    Some(term) => return Some(term),
+                    None => continue,
+                }
+            }
+
+            //lex numbers
+            return Some(self.chop_while(|x| x.is_numeric()).iter().collect());
+        }
+    }
+
+    /// Lowercases and stems a word, returns `None` for stopwords
+    fn word(&self, word: &str) -> Option<String> {
+        if self.options.is_stopword(&word.to_lowercase()) {
             return None;
         }
 
-        // Lex alphabetic words
-        if self.content[0].is_alphabetic() {
-            let term = self
-                .chop_while(|x| x.is_alphabetic())
-                .iter()
-                .map(|x| x.to_ascii_lowercase())
-                .collect::<String>();
+        let term = word.to_ascii_lowercase();
 
-            // stemming of the term directly in lexer itself
-            let mut env = snowball::SnowballEnv::create(&term);
-            snowball::algorithms::english_stemmer::stem(&mut env);
-            let stemmed = env.get_current().to_string();
+        // stemming of the term directly in lexer itself
+        let mut env = snowball::SnowballEnv::create(&term);
+        snowball::algorithms::english_stemmer::stem(&mut env);
 
-            return Some(stemmed);
-        }
+        Some(env.get_current().to_string())
+    }
 
-        //lex numbers
-        if self.content[0].is_numeric() {
-            return Some(self.chop_while(|x| x.is_numeric()).iter().collect());
+    /// Returns the whole identifier and keeps its parts for the next tokens
+    /// Returns `None` when it has no parts, such as `__`
+    /// The parts are lexed as words, leaving out the stopwords
+    fn identifier(&mut self, identifier: &str) -> Option<String> {
+        let mut parts = identifier
+            .split('_')
+            .flat_map(split_camel_case)
+            .flat_map(|part| {
+                let part = part.chars().collect::<Vec<_>>();
+                let mut lexer = Lexer::with_options(&part, self.options);
+                lexer.code = false;
+                lexer.collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        if parts.is_empty() {
+            return None;
         }
 
-        // Unhandled tokens
-        // proceed to next token for next iteration
-        //
-        Some(self.chop(1).iter().collect())
+        parts.reverse();
+        self.parts = parts;
+
+        Some(identifier.to_lowercase())
     }
 
+    /// Skips whitespace and punctuation, only the underscores of identifiers are kept
     fn trim_left(&mut self) {
-        while self.content.len() > 0 && self.content[0].is_whitespace() {
-            self.content = &self.content[1..];
-        }
+        let code = self.code;
+
+        self.chop_while(|x| !(x.is_alphanumeric() || (code && *x == '_')));
     }
 }
 
@@ -81,3 +281,106 @@ impl<'a> Iterator for Lexer<'a> {
         self.next_token()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(text: &str, options: &LexerOptions) -> Vec<String> {
+        let chars = text.chars().collect::<Vec<_>>();
+        Lexer::with_options(&chars, options).collect()
+    }
+
+    const CODE: &LexerOptions = &LexerOptions {
+        code: true,
+        stopwords: None,
+        extra_stopwords: Vec::new(),
+        version: LexerOptions::VERSION,
+    };
+
+    #[test]
+    fn camel_case() {
+        assert_eq!(split_camel_case("glWaitSync"), ["gl", "Wait", "Sync"]);
+        assert_eq!(
+            split_camel_case("XMLHttpRequest"),
+            ["XML", "Http", "Request"]
+        );
+        assert_eq!(split_camel_case("getHTML"), ["get", "HTML"]);
+        assert_eq!(split_camel_case("Vec3Array"), ["Vec3", "Array"]);
+        assert_eq!(split_camel_case("GL"), ["GL"]);
+        assert_eq!(split_camel_case("shader"), ["shader"]);
+        assert!(split_camel_case("").is_empty());
+    }
+
+    #[test]
+    fn words_are_lowercased_and_stemmed() {
+        let options = LexerOptions::default();
+
+        assert_eq!(
+            terms("Compiling the Shaders, 2 times!", &options),
+            ["compil", "the", "shader", "2", "time"]
+        );
+        // identifiers are split outside of code
+        assert_eq!(terms("gl_max_draw", &options), ["gl", "max", "draw"]);
+    }
+
+    #[test]
+    fn identifiers_are_followed_by_their_parts() {
+        assert_eq!(
+            terms("glWaitSync(GL_MAX_DRAW_BUFFERS)", CODE),
+            [
+                "glwaitsync",
+                "gl",
+                "wait",
+                "sync",
+                "gl_max_draw_buffers",
+                "gl",
+                "max",
+                "draw",
+                "buffer"
+            ]
+        );
+        // plain words are stemmed as outside of code
+        assert_eq!(terms("Shaders vec3", CODE), ["shader", "vec3", "vec", "3"]);
+        // nothing but underscores
+        assert_eq!(terms("__ x", CODE), ["x"]);
+    }
+
+    #[test]
+    fn parts_share_the_span_of_their_identifier() {
+        let chars = "call glWaitSync".chars().collect::<Vec<_>>();
+        let spans = Lexer::with_options(&chars, CODE)
+            .tokens()
+            .map(|token| (token.term, token.start, token.end))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            spans,
+            [
+                ("call".to_string(), 0, 4),
+                ("glwaitsync".to_string(), 5, 15),
+                ("gl".to_string(), 5, 15),
+                ("wait".to_string(), 5, 15),
+                ("sync".to_string(), 5, 15),
+            ]
+        );
+    }
+
+    #[test]
+    fn stopwords_are_left_out() {
+        let options = LexerOptions {
+            stopwords: Some(Language::English),
+            extra_stopwords: vec!["gl".into(), "shader".into()],
+            ..CODE.clone()
+        };
+
+        // the built-in list and the extra words, in any case, also as identifier parts
+        assert_eq!(
+            terms("The Shader of glDrawArrays", &options),
+            ["gldrawarrays", "draw", "array"]
+        );
+        assert!(options.is_stopword("the"));
+        assert!(options.is_stopword("shader"));
+        assert!(!options.is_stopword("vertex"));
+    }
+}
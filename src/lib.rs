@@ -0,0 +1,56 @@
+//! Local search engine, indexes the documents of a folder and searches them
+//!
+//! The binary is a thin command line around these modules, which can also be
+//! embedded in another program. Formats other than the built-in ones are
+//! indexed by registering a [`parser::DocumentParser`] for them:
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use std::sync::{Arc, Mutex};
+//!
+//! use search_engine::indexer::add_folder_to_model;
+//! use search_engine::model::Model;
+//! use search_engine::parser::{DocumentParser, ParsedDocument, ParserRegistry};
+//!
+//! struct WikiParser;
+//!
+//! impl DocumentParser for WikiParser {
+//!     fn extensions(&self) -> &[&str] {
+//!         &["wiki"]
+//!     }
+//!
+//!     fn parse(&self, file_path: &Path) -> Result<ParsedDocument, ()> {
+//!         let text = std::fs::read_to_string(file_path).map_err(|_| ())?;
+//!         Ok(ParsedDocument::new(text.replace("==", " ")))
+//!     }
+//! }
+//!
+//! let mut parsers = ParserRegistry::default();
+//! parsers.register(WikiParser);
+//!
+//! let model = Arc::new(Mutex::new(Model::default()));
+//! add_folder_to_model(Path::new("docs"), Arc::clone(&model), &parsers, 4).unwrap();
+//! ```
+
+// errors are reported to stderr where they happen, callers only need to know that it failed
+#![allow(clippy::result_unit_err)]
+
+pub mod fuzzy;
+pub mod html;
+pub mod index_file;
+pub mod indexer;
+pub mod lexer;
+pub mod model;
+pub mod parser;
+pub mod query;
+pub mod segment_file;
+pub mod segments;
+pub mod server;
+pub mod sniff;
+pub mod snippet;
+// generated by the Snowball compiler, kept as it is
+#[allow(clippy::all)]
+pub mod snowball;
+pub mod stopwords;
+pub mod suggest;
+pub mod watcher;
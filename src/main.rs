@@ -1,230 +1,428 @@
-use model::Model;
-use serde_json;
+use search_engine::lexer::LexerOptions;
+use search_engine::model::{Boosts, Field, Model, Scorer, SearchPage};
+use search_engine::stopwords::{self, Language};
 
-use std::io::{BufReader, BufWriter};
+use std::io;
 use std::{fs, thread};
 
+use std::path::Path;
 use std::process::ExitCode;
 use std::sync::{Arc, Mutex};
-use std::{fs::File, path::Path};
 
-use xml::common::{Position, TextPosition};
-use xml::reader::{EventReader, XmlEvent};
+use search_engine::index_file::{load_model, load_model_from_json, save_model, save_model_as_json};
+use search_engine::indexer::{self, add_folder_to_model, prune_folder};
+use search_engine::parser::ParserRegistry;
+use search_engine::segments::SegmentStore;
+use search_engine::watcher::FolderWatcher;
+use search_engine::{query, server};
 
-mod lexer;
-mod model;
-mod server;
-mod snowball;
-
-// Parse an xml file and returns string containing only relevant characters
-fn parse_xml_file(file_path: &Path) -> Result<String, ()> {
-    let file = File::open(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path:?}: {err}",);
-    })?;
+fn usage(program: &str) {
+    eprintln!("Usage :{program} [SUBCOMMAND] [OPTIONS]");
+    eprintln!("Subcommands:");
+    eprintln!("     index <folder> [index-file]  index the <folder> and save the index to [index-file] (default index.idx)");
+    eprintln!("     search <index-file> [query]  search the index, queries are read from stdin line by line when no query is provided");
+    eprintln!(
+        "     serve <folder>  [address]             starts local http server with web interfaces, the index follows the changes to the <folder>"
+    );
+    eprintln!("     export <index-file> <json-file>  save the index as JSON for debugging");
+    eprintln!("     import <json-file> <index-file>  convert an index saved as JSON back to the binary format");
+    eprintln!("Options for index and serve:");
+    eprintln!("     --scorer <tfidf|bm25>  default ranking function of the index");
+    eprintln!("     --k1 <value>           BM25 term frequency saturation (default 1.2)");
+    eprintln!("     --b <value>            BM25 document length normalization (default 0.75)");
+    eprintln!(
+        "     --jobs <n>             number of files parsed in parallel (default one per core)"
+    );
+    eprintln!("     --boost <field>=<value>  weight of a field (title, headings, body or file), may be repeated");
+    eprintln!("     --lexer <text|code>    `code` also splits identifiers such as glActiveShaderProgram into their words");
+    eprintln!("     --stopwords <language|none>  leave out the stopwords of english, french, german or spanish (default none)");
+    eprintln!("     --stopwords-file <file>  also leave out the words of <file>, one per line");
+    eprintln!("     --all-text             also index the text files of unknown extensions (source code, logs...) as plain text");
+    eprintln!("Options for serve:");
+    eprintln!("     --force                ignore the saved index and index every file again");
+    eprintln!("Options for search:");
+    eprintln!("     --scorer, --k1, --b    ranking function, defaults to the one of the index");
+    eprintln!(
+        "     --boost <field>=<value>  weight of a field, defaults to the boosts of the index"
+    );
+    eprintln!("     --limit <n>            number of results printed per query (default 10)");
+    eprintln!("     --json                 print every query and its results as a JSON line");
+    eprintln!("     --stdin                read the queries from stdin");
+    eprintln!("     --                     ends the options, the arguments after it are taken as they are");
+}
 
-    let er = EventReader::new(BufReader::new(file));
+/// Separates the `--scorer`, `--k1` and `--b` flags from the positional arguments
+/// Returns the positional arguments and the scorer if one was asked for
+/// The arguments after `--` are kept as they are along with the `--`, see [`operands`]
+fn parse_scorer_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Vec<String>, Option<Scorer>), ()> {
+    let mut positional = Vec::new();
+    let mut name = None;
+    let mut k1 = None;
+    let mut b = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                positional.push(arg);
+                positional.extend(args.by_ref());
+            }
+            "--scorer" | "--k1" | "--b" => {
+                let value = args.next().ok_or_else(|| {
+                    eprintln!("ERROR: no value is provided for {arg}");
+                })?;
 
-    let mut content = String::new();
+                if arg == "--scorer" {
+                    name = Some(value);
+                    continue;
+                }
 
-    for event in er.into_iter() {
-        let event = event.map_err(|err| {
-            let TextPosition { row, column } = err.position();
-            let msg = err.msg();
-            // prints the location where error was stated
-            eprintln!(
-                "{file_path}:{row}:{column}: ERROR: {msg}",
-                file_path = file_path.display()
-            );
-        })?;
+                let value = value.parse::<f32>().map_err(|err| {
+                    eprintln!("ERROR: invalid value {value} for {arg}: {err}");
+                })?;
 
-        if let XmlEvent::Characters(text) = event {
-            content.push_str(&text);
-            content.push_str(" ");
+                if arg == "--k1" {
+                    k1 = Some(value);
+                } else {
+                    b = Some(value);
+                }
+            }
+            _ => positional.push(arg),
         }
     }
 
-    Ok(content)
+    if name.is_none() && (k1.is_some() || b.is_some()) {
+        name = Some("bm25".to_string());
+    }
+
+    let scorer = match name {
+        Some(name) => Some(Scorer::from_name(&name, k1, b)?),
+        None => None,
+    };
+
+    Ok((positional, scorer))
 }
 
-// parse an md or txt file
-fn parse_txt_file(file_path: &Path) -> Result<String, ()> {
-    fs::read_to_string(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path:?}: {err}");
-    })
+/// Removes `--jobs <n>` from the arguments
+/// Returns the number of indexing workers, one per core by default
+fn parse_jobs_arg(args: &mut Vec<String>) -> Result<usize, ()> {
+    let Some(value) = take_arg_value(args, "--jobs")? else {
+        return Ok(indexer::default_jobs());
+    };
+
+    match value.parse::<usize>() {
+        Ok(jobs) if jobs > 0 => Ok(jobs),
+        _ => {
+            eprintln!("ERROR: invalid value {value} for --jobs, expected a positive number");
+            Err(())
+        }
+    }
 }
 
-// parse s pdf document
-fn parse_pdf_file(file_path: &Path) -> Result<String, ()> {
-    use poppler::Document;
-    use std::io::Read;
+/// Removes the flag `name` and its value from the arguments
+/// Returns the value if the flag is present
+fn take_arg_value(args: &mut Vec<String>, name: &str) -> Result<Option<String>, ()> {
+    let end = options_end(args);
 
-    let mut content = Vec::new();
+    let Some(i) = args[..end].iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
 
-    File::open(file_path)
-        .and_then(|mut file| file.read_to_end(&mut content))
-        .map_err(|err| {
-            eprintln!("ERROR: could not read file {file_path:?}: {err}");
-        })?;
+    args.remove(i);
 
-    let pdf = Document::from_data(&mut content, None).map_err(|err| {
-        eprintln!("ERROR: could not read file {file_path:?}: {err}");
-    })?;
+    if i + 1 >= end {
+        eprintln!("ERROR: no value is provided for {name}");
+        return Err(());
+    }
+
+    Ok(Some(args.remove(i)))
+}
+
+/// `--lexer`, `--stopwords` and `--stopwords-file` options, they override the lexer options of the index
+#[derive(Debug, Default)]
+struct LexerArgs {
+    code: Option<bool>,
+    stopwords: Option<Option<Language>>,
+    extra_stopwords: Option<Vec<String>>,
+}
 
-    let mut result = String::new();
+impl LexerArgs {
+    /// Returns the options of the current version of the lexer
+    /// The index is reindexed when they differ from its options, see `LexerOptions::version`
+    fn apply(&self, mut options: LexerOptions) -> LexerOptions {
+        if let Some(code) = self.code {
+            options.code = code;
+        }
 
-    let n = pdf.n_pages();
+        if let Some(stopwords) = self.stopwords {
+            options.stopwords = stopwords;
 
-    for i in 0..n {
-        let page = pdf.page(i).expect(&format!(
-            "{i} is within the bounds of the range of the page"
-        ));
+            // `--stopwords none` leaves out no word at all
+            if stopwords.is_none() {
+                options.extra_stopwords.clear();
+            }
+        }
 
-        if let Some(content) = page.text() {
-            result.push_str(&content);
-            result.push(' ');
+        if let Some(extra_stopwords) = &self.extra_stopwords {
+            options.extra_stopwords = extra_stopwords.clone();
         }
+
+        options.version = LexerOptions::VERSION;
+
+        options
     }
+}
 
-    Ok(result)
+/// Removes `--lexer <text|code>`, `--stopwords <language|none>` and
+/// `--stopwords-file <file>` from the arguments
+fn parse_lexer_args(args: &mut Vec<String>) -> Result<LexerArgs, ()> {
+    let code = match take_arg_value(args, "--lexer")?.as_deref() {
+        None => None,
+        Some("text") => Some(false),
+        Some("code") => Some(true),
+        Some(name) => {
+            eprintln!("ERROR: unknown lexer {name}, expected `text` or `code`");
+            return Err(());
+        }
+    };
+
+    let stopwords = match take_arg_value(args, "--stopwords")?.as_deref() {
+        None => None,
+        Some("none") => Some(None),
+        Some(name) => match Language::from_name(name) {
+            Some(language) => Some(Some(language)),
+            None => {
+                eprintln!("ERROR: unknown stopwords {name}, expected english, french, german, spanish or none");
+                return Err(());
+            }
+        },
+    };
+
+    let extra_stopwords = match take_arg_value(args, "--stopwords-file")? {
+        Some(file_path) => Some(stopwords::read_stopwords(Path::new(&file_path))?),
+        None => None,
+    };
+
+    Ok(LexerArgs {
+        code,
+        stopwords,
+        extra_stopwords,
+    })
 }
 
-/// Check file extension and parses it accordingly
-/// Currrently working with `xml`, `html`, `md`, `txt` files only
-fn parse_file_by_extension(file_path: &Path) -> Result<String, ()> {
-    let extension = file_path
-        .extension()
-        .ok_or_else(|| {
-            eprintln!("ERROR: can't detect file type for {file_path:?}");
-        })?
-        .to_string_lossy();
-
-    match extension.as_ref() {
-        "xhtml" | "xml" | "html" => parse_xml_file(file_path),
-        "txt" | "md" => parse_txt_file(file_path),
-        "pdf" => parse_pdf_file(file_path),
-        _ => {
-            eprintln!("ERROR: unsupported file type {file_path:?}");
-            Err(())
+/// Removes every `--boost <field>=<value>` from the arguments
+/// Returns the boosts in the order they were given
+fn parse_boost_args(args: &mut Vec<String>) -> Result<Vec<(Field, f32)>, ()> {
+    let mut boosts = Vec::new();
+
+    while let Some(i) = args[..options_end(args)]
+        .iter()
+        .position(|arg| arg == "--boost")
+    {
+        let end = options_end(args);
+        args.remove(i);
+
+        if i + 1 >= end {
+            eprintln!("ERROR: no value is provided for --boost");
+            return Err(());
+        }
+
+        let value = args.remove(i);
+
+        let boost = value.split_once('=').and_then(|(name, boost)| {
+            let field = Field::from_name(name)?;
+            let boost = boost.parse::<f32>().ok().filter(|b| *b >= 0.0)?;
+
+            Some((field, boost))
+        });
+
+        match boost {
+            Some(boost) => boosts.push(boost),
+            None => {
+                eprintln!("ERROR: invalid value {value} for --boost, expected <field>=<value> with a field among title, headings, body or file");
+                return Err(());
+            }
         }
     }
+
+    Ok(boosts)
 }
 
-fn usage(program: &str) {
-    eprintln!("Usage :{program} [SUBCOMMAND] [OPTIONS]");
-    eprintln!("Subcommands:");
-    eprintln!("     index <folder> index the <folder> and save the index to index.json");
-    eprintln!("     search <index-file> check how many documents are indexed in the file");
-    eprintln!(
-        "     serve <folder>  [address]             starts local http server with web interfaces"
-    );
+/// Removes every occurrence of the flag `name` from the arguments
+/// Returns whether it was present
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let end = options_end(args);
+    let present = args[..end].iter().any(|arg| arg == name);
+
+    let operands = args.split_off(end);
+    args.retain(|arg| arg != name);
+    args.extend(operands);
+
+    present
 }
 
-/// Save `TermFreqIndex` to a json file
-fn save_model_as_json(model: &Model, index_path: &Path) -> Result<(), ()> {
-    println!("Saving {index_path:?}...");
+/// Position of the `--` ending the options, the length of the arguments without one
+fn options_end(args: &[String]) -> usize {
+    args.iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len())
+}
 
-    let index_file = File::create(index_path).map_err(|err| {
-        eprintln!("ERROR: could not create index file {index_path:?}: {err}");
-    })?;
+/// The positional arguments once the options are taken out, without the `--` ending them
+/// so that a query or a path starting with `--` can follow it
+fn operands(mut args: Vec<String>) -> std::vec::IntoIter<String> {
+    if let Some(i) = args.iter().position(|arg| arg == "--") {
+        args.remove(i);
+    }
 
-    serde_json::to_writer(BufWriter::new(index_file), &model).map_err(|err| {
-        eprintln!("ERROR: could not serialze index into file {index_path:?}: {err}");
-    })?;
+    args.into_iter()
+}
 
-    Ok(())
+/// Options of the `search` subcommand
+#[derive(Debug)]
+struct SearchOptions {
+    // defaults to the scorer of the index
+    scorer: Option<Scorer>,
+    // override the boosts of the index
+    boosts: Vec<(Field, f32)>,
+    limit: usize,
+    json: bool,
 }
 
-/// Reads the created index and prints number of files an index contains
-fn check_index(index_path: &str) -> Result<(), ()> {
-    println!("Reading {index_path} index file...");
+/// Runs a single query against the model and prints the ranked results
+fn print_search_results(model: &Model, query: &str, options: &SearchOptions) -> Result<(), ()> {
+    let chars = query.chars().collect::<Vec<_>>();
+
+    let page = match query::parse(&chars, &model.lexer) {
+        Ok(Some(query)) => model.search_page(
+            &query,
+            options.scorer.unwrap_or(model.scorer),
+            0,
+            options.limit,
+        ),
+        Ok(None) => SearchPage::default(),
+        Err(err) => {
+            eprintln!("ERROR: invalid query {query:?}: {err}");
+            return Err(());
+        }
+    };
+
+    let total = page.total_hits;
+    let results = page.results.into_iter();
+    let did_you_mean = model.did_you_mean(&chars);
+
+    if options.json {
+        let results = results
+            .map(|(path, rank)| serde_json::json!({ "path": path, "rank": rank }))
+            .collect::<Vec<_>>();
+
+        println!(
+            "{}",
+            serde_json::json!({
+                "query": query,
+                "total": total,
+                "results": results,
+                "did_you_mean": did_you_mean,
+            })
+        );
+
+        return Ok(());
+    }
 
-    let index_file = File::open(index_path).map_err(|err| {
-        eprintln!("ERROR: could not open index file {index_path}: {err}");
-    })?;
+    for (path, rank) in results {
+        println!("{rank:>10.4}  {path}", path = path.display());
+    }
 
-    let model: Model = serde_json::from_reader(index_file).map_err(|err| {
-        eprintln!("ERROR: could not parse index file {index_path}: {err}");
-    })?;
+    eprintln!("{total} documents matched {query:?}");
 
-    println!(
-        "{index_path} contains {count} files",
-        count = model.docs.len()
-    );
+    if let Some(did_you_mean) = did_you_mean {
+        eprintln!("Did you mean: {did_you_mean}");
+    }
 
     Ok(())
 }
 
-/// Indexes a directory recursively
-fn add_folder_to_model(
-    dir_path: &Path,
-    model: Arc<Mutex<Model>>,
-    processed: &mut usize,
-) -> Result<(), ()> {
-    let dir = fs::read_dir(dir_path).map_err(|err| {
-        eprintln!("ERROR: could not open directory {dir_path:?} for indexing : {err}");
-    })?;
-
-    'next_file: for file in dir {
-        // 'next_file for naming the loop
-        let file = file.map_err(|err| {
-            eprintln!("ERROR: could not read next file in directory {dir_path:?}: {err}");
-        })?;
-
-        let file_path = file.path();
+/// Searches the index for the query, or for every line of stdin when no query is given
+fn search_index(index_path: &Path, query: Option<&str>, options: &SearchOptions) -> Result<(), ()> {
+    let mut model = load_model(index_path)?;
 
-        // Skip if dot file
-        let dot_file = file_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .map(|s| s.starts_with("."))
-            .unwrap_or(false);
+    for (field, boost) in &options.boosts {
+        model.boosts.set(*field, *boost);
+    }
 
-        if dot_file {
-            continue 'next_file;
-        }
+    if let Some(query) = query {
+        return print_search_results(&model, query, options);
+    }
 
-        let file_type = file.file_type().map_err(|err| {
-            eprintln!("ERROR: couldnot determine file type for {file_path:?}: {err}");
+    for line in io::stdin().lines() {
+        let line = line.map_err(|err| {
+            eprintln!("ERROR: could not read query from stdin: {err}");
         })?;
 
-        let last_modified = file
-            .metadata()
-            .map_err(|err| {
-                eprintln!("ERROR: could not get the metadata of the file {file_path:?}: {err}");
-            })?
-            .modified()
-            .map_err(|err| {
-                eprintln!(
-                    "ERROR: could not get the last modified data for the file {file_path:?}: {err}"
-                );
-            })?;
-
-        if file_type.is_dir() {
-            add_folder_to_model(&file_path, Arc::clone(&model), processed)?;
-            continue 'next_file;
+        if line.trim().is_empty() {
+            continue;
         }
 
-        let mut model = model.lock().unwrap();
-
-        if model.requires_reindexing(&file_path, last_modified) {
-            println!("Indexing {:?}... ", &file_path);
+        // an invalid query is already reported, carry on with the next one
+        let _ = print_search_results(&model, &line, options);
+    }
 
-            let content = match parse_file_by_extension(&file_path) {
-                Ok(content) => content.chars().collect::<Vec<_>>(),
-                Err(()) => {
-                    println!("Err");
-                    continue 'next_file;
-                }
-            };
+    Ok(())
+}
 
-            model.add_document(file_path, last_modified, &content);
+/// Removes the index saved in any of its formats, for `serve --force`
+fn remove_saved_index(index_path: &Path, legacy_index_path: &Path) {
+    let _ = fs::remove_file(legacy_index_path);
+    let _ = fs::remove_file(index_path);
+    let _ = fs::remove_dir_all(index_path);
+}
 
-            *processed += 1;
-        } else {
-            println!(r#"Ignoring {file_path:?} as it is already indexed"#);
+/// Moves an index saved as a single file, binary or JSON, into a folder of segments
+/// The old files are only removed once the segments are written, an index that
+/// cannot be read is removed and rebuilt from the folder
+fn migrate_index(index_path: &Path, legacy_index_path: &Path) -> Result<(), ()> {
+    let model = if index_path.is_file() {
+        println!("Migrating {index_path:?} to segments...");
+        load_model(index_path)
+    } else {
+        println!("Migrating {legacy_index_path:?} to segments...");
+        load_model_from_json(legacy_index_path)
+    };
+
+    // written next to the index, the folder takes its place once complete
+    let temp_path = index_path.with_extension("migrating");
+    let _ = fs::remove_dir_all(&temp_path);
+
+    match model {
+        Ok(mut model) => {
+            let written =
+                SegmentStore::open(&temp_path).and_then(|(store, _)| store.rewrite(&mut model));
+
+            if written.is_err() {
+                let _ = fs::remove_dir_all(&temp_path);
+                return Err(());
+            }
+        }
+        Err(()) => {
+            eprintln!("WARNING: could not load the index, reindexing from scratch");
         }
     }
 
+    if index_path.is_file() {
+        fs::remove_file(index_path).map_err(|err| {
+            eprintln!("ERROR: could not remove {index_path:?}: {err}");
+        })?;
+    }
+
+    if temp_path.is_dir() {
+        fs::rename(&temp_path, index_path).map_err(|err| {
+            eprintln!("ERROR: could not move {temp_path:?} to {index_path:?}: {err}");
+        })?;
+    }
+
+    let _ = fs::remove_file(legacy_index_path);
+
     Ok(())
 }
 
@@ -241,78 +439,231 @@ fn entry() -> Result<(), ()> {
 
     match subcommand.as_str() {
         "index" => {
+            let (mut positional, scorer) = parse_scorer_args(args)?;
+            let jobs = parse_jobs_arg(&mut positional)?;
+            let boosts = parse_boost_args(&mut positional)?;
+            let lexer = parse_lexer_args(&mut positional)?;
+            let all_text = take_flag(&mut positional, "--all-text");
+            let mut args = operands(positional);
+
             let dir_path = args.next().ok_or_else(|| {
                 usage(&program);
                 eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
             })?;
 
-            let model = Arc::new(Mutex::new(Default::default()));
-            let mut processed = 0;
+            let mut model = Model::new(
+                scorer.unwrap_or_default(),
+                Boosts::default(),
+                lexer.apply(LexerOptions::default()),
+            );
+
+            for (field, boost) in boosts {
+                model.boosts.set(field, boost);
+            }
+
+            let model = Arc::new(Mutex::new(model));
 
-            add_folder_to_model(Path::new(&dir_path), Arc::clone(&model), &mut processed)?;
+            let index_path = args.next().unwrap_or("index.idx".to_string());
+
+            let mut parsers = ParserRegistry::default();
+            parsers.set_text_fallback(all_text);
+
+            add_folder_to_model(Path::new(&dir_path), Arc::clone(&model), &parsers, jobs)
+                .map_err(|_| ())?;
 
             let model = model.lock().unwrap();
 
-            save_model_as_json(&model, Path::new("index.json"))?;
+            save_model(&model, Path::new(&index_path))?;
         }
-        "search" => {
+        "export" => {
+            let index_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {subcommand}");
+            })?;
+
+            let json_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to the JSON file is provided for {subcommand}");
+            })?;
+
+            let mut model = load_model(Path::new(&index_path))?;
+            model.load_segments();
+
+            save_model_as_json(&model, Path::new(&json_path))?;
+        }
+        "import" => {
+            let json_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to the JSON file is provided for {subcommand}");
+            })?;
+
             let index_path = args.next().ok_or_else(|| {
                 usage(&program);
                 eprintln!("ERROR: no path to index is provided for {subcommand}");
             })?;
 
-            check_index(&index_path)?;
+            let model = load_model_from_json(Path::new(&json_path))?;
+
+            save_model(&model, Path::new(&index_path))?;
+        }
+        "search" => {
+            let (mut positional, scorer) = parse_scorer_args(args)?;
+            let boosts = parse_boost_args(&mut positional)?;
+
+            let mut limit = 10;
+            let mut json = false;
+            let mut stdin = false;
+            let mut words = Vec::new();
+
+            let mut args = positional.into_iter();
+
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    // the query may contain words starting with `--` after it
+                    "--" => words.extend(args.by_ref()),
+                    "--limit" => {
+                        let value = args.next().ok_or_else(|| {
+                            eprintln!("ERROR: no value is provided for {arg}");
+                        })?;
+
+                        limit = value.parse().map_err(|err| {
+                            eprintln!("ERROR: invalid value {value} for {arg}: {err}");
+                        })?;
+                    }
+                    "--json" => json = true,
+                    "--stdin" => stdin = true,
+                    _ => words.push(arg),
+                }
+            }
+
+            let mut words = words.into_iter();
+
+            let index_path = words.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no path to index is provided for {subcommand}");
+            })?;
+
+            let query = words.collect::<Vec<_>>().join(" ");
+
+            let query = if stdin || query.is_empty() {
+                None
+            } else {
+                Some(query)
+            };
+
+            let index_path = Path::new(&index_path);
+
+            let options = SearchOptions {
+                scorer,
+                boosts,
+                limit,
+                json,
+            };
+
+            search_index(index_path, query.as_deref(), &options)?;
         }
         "serve" => {
             // Start an HTTP server where we can see the indexing
             //
 
+            let (mut positional, scorer) = parse_scorer_args(args)?;
+            let jobs = parse_jobs_arg(&mut positional)?;
+            let boosts = parse_boost_args(&mut positional)?;
+            let lexer = parse_lexer_args(&mut positional)?;
+
+            let force = take_flag(&mut positional, "--force");
+            let all_text = take_flag(&mut positional, "--all-text");
+
+            let mut args = operands(positional);
+
             let dir_path = args.next().ok_or_else(|| {
                 usage(&program);
                 eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
             })?;
 
-            let mut index_path = Path::new(&dir_path).to_path_buf();
+            let index_path = Path::new(&dir_path).join(".index");
+            // indexes used to be saved as a single file, first as JSON and then in
+            // the binary format, they are migrated to a folder of segments
+            let legacy_index_path = Path::new(&dir_path).join(".index.json");
 
-            index_path.push(".index.json");
+            if force {
+                println!("Removing the saved index, reindexing {dir_path} from scratch");
+                remove_saved_index(&index_path, &legacy_index_path);
+            }
 
-            let exists = index_path.try_exists().map_err(|err| {
-                eprintln!(
-                    "ERROR: could not check for existence for the index {index_path:?}: {err}"
-                );
-            })?;
+            if index_path.is_file() || legacy_index_path.is_file() {
+                migrate_index(&index_path, &legacy_index_path)?;
+            }
 
-            let model: Arc<Mutex<Model>>;
-            if exists {
-                let index_file = File::open(&index_path).map_err(|err| {
-                    eprintln!("ERROR: could not open {index_path:?} {err}");
-                })?;
+            let (store, mut model) = match SegmentStore::open(&index_path) {
+                Ok(opened) => opened,
+                Err(()) => {
+                    // the index can always be rebuilt from the folder
+                    eprintln!(
+                        "WARNING: could not load the index, reindexing {dir_path} from scratch"
+                    );
+                    let _ = fs::remove_dir_all(&index_path);
+                    SegmentStore::open(&index_path)?
+                }
+            };
 
-                model = Arc::new(Mutex::new(serde_json::from_reader(index_file).map_err(
-                    |err| {
-                        eprintln!("ERROR: could not parse index file {index_path:?} {err}");
-                    },
-                )?));
-            } else {
-                model = Arc::new(Mutex::new(Default::default()));
+            if let Some(scorer) = scorer {
+                model.scorer = scorer;
+            }
+
+            for (field, boost) in boosts {
+                model.boosts.set(field, boost);
+            }
+
+            let lexer = lexer.apply(model.lexer.clone());
+
+            if lexer != model.lexer {
+                if model.doc_count() > 0 {
+                    println!("The lexer changed, reindexing {dir_path} from scratch");
+                }
+
+                model.set_lexer(lexer);
+                store.rewrite(&mut model)?;
+            }
+
+            let mut parsers = ParserRegistry::default();
+
+            if all_text {
+                parsers.set_text_fallback(true);
+                // the text files rejected without the option are read this time
+                model.rejected.clear();
             }
 
+            let model = Arc::new(Mutex::new(model));
+            let parsers = Arc::new(parsers);
+
             // New scope
             // so that `model` exists in different scope
             {
                 let model = Arc::clone(&model);
+                let parsers = Arc::clone(&parsers);
 
                 thread::spawn(move || {
-                    let mut processed = 0;
-                    let _ = add_folder_to_model(
-                        Path::new(&dir_path),
-                        Arc::clone(&model),
-                        &mut processed,
-                    );
+                    let dir_path = Path::new(&dir_path);
+
+                    // started before indexing so that no change is missed in between
+                    let watcher = FolderWatcher::new(dir_path, Arc::clone(&parsers), jobs);
+
+                    let _ = add_folder_to_model(dir_path, Arc::clone(&model), &parsers, jobs);
+
+                    let pruned = prune_folder(dir_path, &model);
+
+                    if pruned > 0 {
+                        println!("Pruned {pruned} documents that are not in {dir_path:?} anymore");
+                    }
+
+                    // only the documents indexed or pruned above are written
+                    let _ = store.flush(&model);
 
-                    if processed > 0 {
-                        let model = model.lock().unwrap();
-                        save_model_as_json(&model, &index_path).unwrap();
+                    if let Ok(watcher) = watcher {
+                        let _ = watcher.run(model, |model| {
+                            let _ = store.flush(model);
+                        });
                     }
                 });
             }
@@ -320,7 +671,7 @@ fn entry() -> Result<(), ()> {
 
             let address = args.next().unwrap_or("127.0.0.1:8000".to_string());
 
-            server::start(&address, model)?;
+            server::start(&address, model, parsers)?;
         }
         _ => {
             usage(&program);
@@ -338,3 +689,70 @@ fn main() -> ExitCode {
         Err(_) => ExitCode::FAILURE,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn forcing_reindexes_every_file() {
+        let dir_path = std::env::temp_dir().join(format!("main-{}-force", process::id()));
+        let _ = fs::remove_dir_all(&dir_path);
+        fs::create_dir_all(&dir_path).unwrap();
+        fs::write(dir_path.join("a.txt"), "vertex shader").unwrap();
+        fs::write(dir_path.join("b.txt"), "fragment shader").unwrap();
+
+        let index_path = dir_path.join(".index");
+        let legacy_index_path = dir_path.join(".index.json");
+        let parsers = ParserRegistry::default();
+
+        let index = || {
+            let (store, model) = SegmentStore::open(&index_path).unwrap();
+            let model = Arc::new(Mutex::new(model));
+            let indexed = add_folder_to_model(&dir_path, Arc::clone(&model), &parsers, 2);
+            store.flush(&model).unwrap();
+
+            indexed
+        };
+
+        assert_eq!(index(), Ok(2));
+        // the files did not change
+        assert_eq!(index(), Ok(0));
+
+        remove_saved_index(&index_path, &legacy_index_path);
+        assert_eq!(index(), Ok(2));
+
+        let _ = fs::remove_dir_all(&dir_path);
+    }
+
+    #[test]
+    fn options_end_at_double_dash() {
+        let (mut positional, scorer) =
+            parse_scorer_args(args(&["idx", "--b", "0.5", "--", "--b", "--force"]).into_iter())
+                .unwrap();
+
+        assert_eq!(
+            scorer,
+            Some(Scorer::from_name("bm25", None, Some(0.5)).unwrap())
+        );
+        assert_eq!(positional, args(&["idx", "--", "--b", "--force"]));
+
+        // the words after `--` are neither flags nor values
+        assert!(!take_flag(&mut positional, "--force"));
+        assert_eq!(take_arg_value(&mut positional, "--b"), Ok(None));
+        assert_eq!(
+            operands(positional).collect::<Vec<_>>(),
+            args(&["idx", "--b", "--force"])
+        );
+
+        // a flag cannot take `--` as its value
+        let mut positional = args(&["--jobs", "--", "4"]);
+        assert_eq!(take_arg_value(&mut positional, "--jobs"), Err(()));
+    }
+}
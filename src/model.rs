@@ -1,149 +1,2584 @@
 use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
-    time::SystemTime,
+    borrow::Cow,
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+    fs::{File, Metadata},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{lexer::Lexer, snowball};
+use crate::{
+    fuzzy::{auto_distance, LevenshteinAutomaton},
+    lexer::{split_camel_case, Lexer, LexerOptions},
+    parser::ParsedDocument,
+    query::{self, matches_wildcard, Query},
+    segment_file::{self, DocInfo, SegmentReader},
+};
+
+pub type TermFreq = HashMap<String, usize>; // frequency for a token
+pub type Positions = HashMap<String, Vec<usize>>; // sorted positions of a token in a document
+pub type DocFreq = BTreeMap<String, usize>; // frequency for a token in all the documents, sorted for wildcards
+pub type DocId = u32; // index of a document in `Documents`
+pub type Postings = HashMap<DocId, usize>; // frequency of a token in each document containing it
+pub type InvertedIndex = HashMap<String, Postings>; // postings list for every token
+pub type Spellings = HashMap<String, String>; // most common lowercase spelling of a stemmed token
+
+/// Part of a document, the terms of every field are counted separately
+/// so that a match in the title can weigh more than one in the body
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Field {
+    Title,
+    Headings,
+    Body,
+    /// Path and name of the file
+    Path,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::Title, Field::Headings, Field::Body, Field::Path];
+
+    /// Name of the field in queries (`title:shader`) and on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Headings => "headings",
+            Field::Body => "body",
+            Field::Path => "file",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+}
+
+/// Boost of every field, the frequency of a term is multiplied by the boost of its field
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Boosts {
+    pub title: f32,
+    pub headings: f32,
+    pub body: f32,
+    pub path: f32,
+}
+
+impl Default for Boosts {
+    fn default() -> Self {
+        Self {
+            title: 3.0,
+            headings: 2.0,
+            body: 1.0,
+            path: 2.0,
+        }
+    }
+}
+
+impl Boosts {
+    pub fn get(&self, field: Field) -> f32 {
+        match field {
+            Field::Title => self.title,
+            Field::Headings => self.headings,
+            Field::Body => self.body,
+            Field::Path => self.path,
+        }
+    }
+
+    pub fn set(&mut self, field: Field, boost: f32) {
+        match field {
+            Field::Title => self.title = boost,
+            Field::Headings => self.headings = boost,
+            Field::Body => self.body = boost,
+            Field::Path => self.path = boost,
+        }
+    }
+}
+
+/// Terms of one field of a document
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct FieldTerms {
+    pub tf: TermFreq,
+    pub positions: Positions,
+    // number of terms in the field
+    pub count: usize,
+}
+
+impl FieldTerms {
+    /// Tokenizes the content, counting how every term is spelled in `spellings`
+    fn new(
+        content: &[char],
+        options: &LexerOptions,
+        spellings: &mut HashMap<String, HashMap<String, usize>>,
+    ) -> Self {
+        let mut terms = Self::default();
+        let mut previous_start = None;
+
+        for token in Lexer::with_options(content, options).tokens() {
+            // the parts of an identifier share its span, which is only the spelling of the identifier
+            if previous_start != Some(token.start) {
+                let spelling = content[token.start..token.end]
+                    .iter()
+                    .collect::<String>()
+                    .to_lowercase();
+
+                *spellings
+                    .entry(token.term.clone())
+                    .or_default()
+                    .entry(spelling)
+                    .or_default() += 1;
+            }
+
+            previous_start = Some(token.start);
+            terms.push(token.term);
+        }
+
+        terms
+    }
+
+    /// Tokenizes the folders and the name of a file, `glWaitSync.xhtml`
+    /// gives `glwaitsync`, its camelCase parts `gl`, `wait` and `sync`, and `xhtml`
+    fn from_path(file_path: &Path) -> Self {
+        let mut terms = Self::default();
+
+        for component in file_path.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+
+            let name = name.to_string_lossy();
+
+            for word in name.split(|x: char| !x.is_alphanumeric()) {
+                let parts = split_camel_case(word);
+
+                // the whole word first so that it can be searched as it is written
+                let mut words = vec![word];
+
+                if parts.len() > 1 {
+                    words.extend(parts);
+                }
+
+                for word in words {
+                    let chars = word.chars().collect::<Vec<_>>();
+
+                    for t in Lexer::new(&chars) {
+                        terms.push(t);
+                    }
+                }
+            }
+        }
+
+        terms
+    }
+
+    fn push(&mut self, t: String) {
+        self.positions
+            .entry(t.clone())
+            .or_default()
+            .push(self.count);
+
+        if let Some(f) = self.tf.get_mut(&t) {
+            *f += 1;
+        } else {
+            self.tf.insert(t, 1);
+        }
+
+        self.count += 1;
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Doc {
+    // fields without any term are left out
+    // missing in indexes created before fields, such documents are reindexed
+    #[serde(default)]
+    fields: BTreeMap<Field, FieldTerms>,
+    // only the terms spelled differently, such as `shader` spelled `shaders`
+    #[serde(default)]
+    spellings: Spellings,
+    // Unix time in nanoseconds, `SystemTime` is serialized differently on every platform
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    last_modified: u64,
+    // missing in indexes created before content hashing, such documents are reindexed
+    #[serde(default)]
+    size: u64,
+    // crc32 of the content of the file
+    #[serde(default)]
+    hash: u32,
+}
+
+/// Modification time and size of a file, compared with the indexed ones to detect changes
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    // Unix time in nanoseconds
+    pub last_modified: u64,
+    pub size: u64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &Metadata) -> io::Result<Self> {
+        Ok(Self {
+            last_modified: unix_nanos(metadata.modified()?),
+            size: metadata.len(),
+        })
+    }
+}
+
+/// Converts a time to nanoseconds since the Unix epoch, times before it become `0`
+pub fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Hash of the content of a file, tells touched files from modified ones
+/// The file is read in chunks, the parsers read it again if it changed
+pub fn content_hash(file_path: &Path) -> io::Result<u32> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads `Doc.last_modified`, JSON indexes saved before it was
+/// stored as Unix nanoseconds contain a serialized `SystemTime` instead
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Nanos(u64),
+        SystemTime(SystemTime),
+    }
+
+    // the binary format cannot tell the variants apart, and never held a `SystemTime`
+    if !deserializer.is_human_readable() {
+        return u64::deserialize(deserializer);
+    }
+
+    match Timestamp::deserialize(deserializer)? {
+        Timestamp::Nanos(nanos) => Ok(nanos),
+        Timestamp::SystemTime(time) => Ok(unix_nanos(time)),
+    }
+}
+
+impl Doc {
+    /// Tokenizes every field of a document
+    /// This does not need the model, so documents can be prepared in parallel
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` the folders and the name of the file make up the `Path` field
+    /// * `parsed` text and metadata of the file, the title and headings come from the metadata
+    /// * `stamp` modification time and size of the file
+    /// * `hash` hash of the content of the file, see [`content_hash`]
+    /// * `options` how the title, headings and body are tokenized, see [`Model::lexer`]
+    pub fn new(
+        file_path: &Path,
+        parsed: &ParsedDocument,
+        stamp: FileStamp,
+        hash: u32,
+        options: &LexerOptions,
+    ) -> Self {
+        let metadata = |key: &str| {
+            parsed
+                .metadata
+                .get(key)
+                .map(|values| values.join(" "))
+                .unwrap_or_default()
+        };
+
+        let contents = [
+            (Field::Title, metadata(ParsedDocument::TITLE)),
+            (Field::Headings, metadata(ParsedDocument::HEADINGS)),
+            (Field::Body, parsed.text.clone()),
+        ];
+
+        let mut spellings = HashMap::new();
+
+        let fields = contents
+            .into_iter()
+            .map(|(field, content)| {
+                let content = content.chars().collect::<Vec<_>>();
+                (field, FieldTerms::new(&content, options, &mut spellings))
+            })
+            .chain([(Field::Path, FieldTerms::from_path(file_path))])
+            .filter(|(_, terms)| terms.count > 0)
+            .collect();
+
+        let spellings = spellings
+            .into_iter()
+            .filter_map(|(t, counts)| {
+                let (spelling, _) = counts
+                    .into_iter()
+                    .max_by_key(|(s, n)| (*n, Reverse(s.clone())))?;
+                (spelling != t).then_some((t, spelling))
+            })
+            .collect();
+
+        Self::from_fields(fields, spellings, stamp, hash)
+    }
+
+    /// Document whose terms were already found
+    /// `spellings` holds only the terms spelled differently, see [`Doc::spellings`]
+    pub fn from_fields(
+        fields: BTreeMap<Field, FieldTerms>,
+        spellings: Spellings,
+        stamp: FileStamp,
+        hash: u32,
+    ) -> Self {
+        Self {
+            fields,
+            spellings,
+            last_modified: stamp.last_modified,
+            size: stamp.size,
+            hash,
+        }
+    }
+
+    pub fn field(&self, field: Field) -> Option<&FieldTerms> {
+        self.fields.get(&field)
+    }
+
+    /// Most common spelling of the terms spelled differently
+    pub fn spellings(&self) -> &Spellings {
+        &self.spellings
+    }
+
+    /// Frequency of every term over all the fields
+    pub fn term_freqs(&self) -> TermFreq {
+        let mut tf = TermFreq::new();
+
+        for terms in self.fields.values() {
+            for (t, f) in &terms.tf {
+                *tf.entry(t.clone()).or_default() += f;
+            }
+        }
+
+        tf
+    }
+
+    /// Number of terms over all the fields
+    pub fn count(&self) -> usize {
+        self.fields.values().map(|terms| terms.count).sum()
+    }
+
+    /// Pairs of adjacent terms in the text fields, once for every time they occur
+    pub fn pairs(&self) -> Vec<(&str, &str)> {
+        let mut pairs = Vec::new();
+
+        for field in [Field::Title, Field::Headings, Field::Body] {
+            let Some(terms) = self.field(field) else {
+                continue;
+            };
+
+            let mut sequence = vec![None; terms.count];
+
+            for (term, positions) in &terms.positions {
+                for position in positions {
+                    if let Some(slot) = sequence.get_mut(*position) {
+                        *slot = Some(term.as_str());
+                    }
+                }
+            }
+
+            for pair in sequence.windows(2) {
+                if let [Some(a), Some(b)] = pair {
+                    pairs.push((*a, *b));
+                }
+            }
+        }
+
+        pairs
+    }
+
+    /// Modification time and size of the file when it was indexed
+    pub fn stamp(&self) -> FileStamp {
+        FileStamp {
+            last_modified: self.last_modified,
+            size: self.size,
+        }
+    }
+
+    /// Hash of the content of the file, see [`content_hash`]
+    pub fn hash(&self) -> u32 {
+        self.hash
+    }
+
+    /// Modification time, size, hash and field lengths, as kept in a segment
+    pub fn info(&self) -> DocInfo {
+        DocInfo {
+            last_modified: self.last_modified,
+            size: self.size,
+            hash: self.hash,
+            counts: Field::ALL.map(|field| self.field(field).map_or(0, |terms| terms.count)),
+        }
+    }
+}
+
+/// Documents of the model, the postings refer to them by a small id
+/// The ids of removed documents are given to the next documents added
+#[derive(Debug, Default)]
+pub struct Documents {
+    // path and document of every id, `None` for the removed ones not reused yet
+    slots: Vec<Option<(PathBuf, Doc)>>,
+    ids: HashMap<PathBuf, DocId>,
+    free: Vec<DocId>,
+}
+
+impl Documents {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn id(&self, file_path: &Path) -> Option<DocId> {
+        self.ids.get(file_path).cloned()
+    }
+
+    pub fn get(&self, file_path: &Path) -> Option<&Doc> {
+        self.doc(self.id(file_path)?)
+    }
+
+    fn get_mut(&mut self, file_path: &Path) -> Option<&mut Doc> {
+        let id = self.id(file_path)?;
+        let (_, doc) = self.slots[id as usize].as_mut()?;
+
+        Some(doc)
+    }
+
+    pub fn doc(&self, id: DocId) -> Option<&Doc> {
+        let (_, doc) = self.slots.get(id as usize)?.as_ref()?;
+
+        Some(doc)
+    }
+
+    pub fn path(&self, id: DocId) -> Option<&Path> {
+        let (path, _) = self.slots.get(id as usize)?.as_ref()?;
+
+        Some(path)
+    }
+
+    /// Adds a document that is not in the table yet and returns its id
+    fn insert(&mut self, file_path: PathBuf, doc: Doc) -> DocId {
+        let id = self.free.pop().unwrap_or(self.slots.len() as DocId);
+
+        if id as usize == self.slots.len() {
+            self.slots.push(None);
+        }
+
+        self.ids.insert(file_path.clone(), id);
+        self.slots[id as usize] = Some((file_path, doc));
+
+        id
+    }
+
+    fn remove(&mut self, file_path: &Path) -> Option<(DocId, Doc)> {
+        let id = self.ids.remove(file_path)?;
+        let (_, doc) = self.slots[id as usize].take()?;
+        self.free.push(id);
+
+        Some((id, doc))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (DocId, &Path, &Doc)> {
+        self.slots.iter().enumerate().filter_map(|(id, slot)| {
+            let (path, doc) = slot.as_ref()?;
+            Some((id as DocId, path.as_path(), doc))
+        })
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.iter().map(|(_, path, _)| path)
+    }
+}
+
+// saved as a map from the path to the document, the ids only live in memory
+impl Serialize for Documents {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+
+        for (_, path, doc) in self.iter() {
+            map.serialize_entry(path, doc)?;
+        }
+
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Documents {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut docs = Self::default();
+
+        for (path, doc) in HashMap::<PathBuf, Doc>::deserialize(deserializer)? {
+            docs.insert(path, doc);
+        }
+
+        Ok(docs)
+    }
+}
+
+type Matches = HashMap<DocId, f32>; // documents matching a query and their score
+
+/// Ranking function used to score documents against the query terms
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Scorer {
+    /// Plain TF-IDF, term frequency is normalized by the document length
+    #[default]
+    TfIdf,
+    /// Okapi BM25
+    /// * `k1` controls term frequency saturation
+    /// * `b` controls how much the document length normalizes the score
+    Bm25 { k1: f32, b: f32 },
+}
+
+impl Scorer {
+    pub const BM25_K1: f32 = 1.2;
+    pub const BM25_B: f32 = 0.75;
+
+    /// Creates a scorer from its name, `k1` and `b` are only used by BM25
+    /// and fall back to the usual defaults
+    pub fn from_name(name: &str, k1: Option<f32>, b: Option<f32>) -> Result<Self, ()> {
+        match name {
+            "tfidf" => Ok(Scorer::TfIdf),
+            "bm25" => Ok(Scorer::Bm25 {
+                k1: k1.unwrap_or(Self::BM25_K1),
+                b: b.unwrap_or(Self::BM25_B),
+            }),
+            _ => {
+                eprintln!("ERROR: unknown scorer {name}, expected `tfidf` or `bm25`");
+                Err(())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Model {
+    // documents indexed since the segments were written, all of them without segments
+    pub docs: Documents,
+    // saved with the documents but recomputed from them by `rebuild_index`, the
+    // documents of old indexes have no terms and must not count in it
+    pub df: DocFreq,
+    // default scorer of the index, used when a query does not pick one
+    #[serde(default)]
+    pub scorer: Scorer,
+    #[serde(default)]
+    pub boosts: Boosts,
+    // documents have to be reindexed when it changes, see `set_lexer`
+    #[serde(default = "LexerOptions::unversioned")]
+    pub lexer: LexerOptions,
+    // derived from `docs`, rebuilt with `rebuild_index` after loading
+    #[serde(skip)]
+    pub index: InvertedIndex,
+    // number of terms of every field in all the documents, used for the average field length
+    #[serde(skip)]
+    pub field_counts: HashMap<Field, usize>,
+    // number of documents in memory spelling a term in each way, other than the term itself
+    #[serde(skip)]
+    pub spellings: HashMap<String, HashMap<String, usize>>,
+    // documents added, updated or removed since the model was last saved as a segment
+    #[serde(skip)]
+    pub changed: HashSet<PathBuf>,
+    // bumped every time a document is added or removed, tells derived data it is out of date
+    #[serde(skip)]
+    pub generation: u64,
+    // segments of a `SegmentStore` mapped in memory, oldest first, see `set_segments`
+    #[serde(skip)]
+    segments: Vec<MappedSegment>,
+    // modification times of the documents of the segments touched since they were written
+    #[serde(skip)]
+    pub touched: HashMap<PathBuf, u64>,
+    // files that no parser could read, they are not read again until they change
+    #[serde(skip)]
+    pub rejected: HashMap<PathBuf, FileStamp>,
+}
+
+/// Results of a search, see [`Model::search_page`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchPage {
+    // number of documents matching the query, not only the ones of the page
+    pub total_hits: usize,
+    // best first
+    pub results: Vec<(PathBuf, f32)>,
+}
+
+/// Document matching a query, the better ranked ones are greater
+#[derive(Debug, PartialEq)]
+struct Hit<'a> {
+    rank: f32,
+    path: &'a Path,
+}
+
+impl Eq for Hit<'_> {}
+
+impl Ord for Hit<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.rank
+            .total_cmp(&other.rank)
+            .then_with(|| other.path.cmp(self.path))
+    }
+}
+
+impl PartialOrd for Hit<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Documents changed since the last segment was written, see [`Model::take_changes`]
+#[derive(Debug, Default)]
+pub struct Changes {
+    // current version of the documents added or updated
+    pub docs: Vec<(PathBuf, Doc)>,
+    // documents removed
+    pub deleted: Vec<PathBuf>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty() && self.deleted.is_empty()
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.docs.iter().map(|(path, _)| path).chain(&self.deleted)
+    }
+}
+
+/// Segment mapped in memory, along with which of its documents are still live
+#[derive(Debug)]
+struct MappedSegment {
+    reader: Arc<SegmentReader>,
+    // id of its first document in the model, the ids of the documents in memory come last
+    base: DocId,
+    // documents replaced or removed since the segment was written
+    dead: Vec<bool>,
+    // number of live documents and of their terms in every field
+    docs: usize,
+    field_counts: [usize; 4],
+}
+
+impl MappedSegment {
+    fn new(reader: Arc<SegmentReader>, base: DocId) -> Self {
+        Self {
+            base,
+            dead: vec![false; reader.doc_count() as usize],
+            docs: reader.doc_count() as usize,
+            field_counts: Field::ALL.map(|field| reader.field_count(field)),
+            reader,
+        }
+    }
+
+    fn is_live(&self, rank: u32) -> bool {
+        self.dead.get(rank as usize) == Some(&false)
+    }
+
+    /// Marks a document as replaced or removed, returns whether it was live
+    fn kill(&mut self, rank: u32) -> bool {
+        if !self.is_live(rank) {
+            return false;
+        }
+
+        self.dead[rank as usize] = true;
+        self.docs -= 1;
+
+        for (total, count) in self
+            .field_counts
+            .iter_mut()
+            .zip(self.reader.doc_info(rank).counts)
+        {
+            *total -= count;
+        }
+
+        true
+    }
+}
+
+/// Positions of a term in a field of a document
+#[derive(Debug, Clone, Copy)]
+enum FieldPositions<'a> {
+    Memory(&'a [usize]),
+    // little endian u32 in a mapped segment
+    Mapped(&'a [u8]),
+}
+
+impl<'a> FieldPositions<'a> {
+    fn len(self) -> usize {
+        match self {
+            FieldPositions::Memory(positions) => positions.len(),
+            FieldPositions::Mapped(bytes) => bytes.len() / 4,
+        }
+    }
+
+    fn get(self) -> Cow<'a, [usize]> {
+        match self {
+            FieldPositions::Memory(positions) => Cow::Borrowed(positions),
+            FieldPositions::Mapped(bytes) => Cow::Owned(segment_file::positions(bytes).collect()),
+        }
+    }
+}
+
+/// Occurrences of a term in a document
+#[derive(Debug, Clone)]
+struct Posting<'a> {
+    doc: DocId,
+    // in the order of `Field::ALL`, `None` when the field does not hold the term
+    fields: [Option<FieldPositions<'a>>; 4],
+}
+
+/// Postings of a term in the live documents of the segments and in memory
+#[derive(Debug, Default)]
+struct TermPostings<'a> {
+    postings: Vec<Posting<'a>>,
+    // index of the posting of every document
+    by_doc: HashMap<DocId, usize>,
+}
+
+impl<'a> TermPostings<'a> {
+    fn new(postings: Vec<Posting<'a>>) -> Self {
+        let by_doc = postings
+            .iter()
+            .enumerate()
+            .map(|(i, posting)| (posting.doc, i))
+            .collect();
+
+        Self { postings, by_doc }
+    }
+
+    fn get(&self, doc: DocId) -> Option<&Posting<'a>> {
+        self.by_doc.get(&doc).map(|i| &self.postings[*i])
+    }
+
+    /// Number of live documents containing the term
+    fn df(&self) -> usize {
+        self.postings.len()
+    }
+}
+
+/// Returns the TF for a term in a particular document
+/// `f` is the frequency of the term, weighted by the boosts of the fields it is found in,
+/// and `count` the number of terms of the document
+pub fn compute_tf(f: f32, count: usize) -> f32 {
+    let b = count as f32;
+
+    f / b
+}
+
+/// Computes IDF for a term
+/// # Arguments
+///
+/// * `n_docs` number of total documents in the index
+/// * `df` number of documents the term appears in
+pub fn compute_idf(n_docs: usize, df: usize) -> f32 {
+    let n = n_docs as f32;
+
+    let m = df.max(1) as f32;
+
+    (n / m).log10() // smaller values are turned negative due to log
+}
+
+/// Computes the BM25F score of a term in a particular document
+/// # Arguments
+///
+/// * `f` frequency of the term in every field, weighted by the boost of the field
+///   and normalized by its length (see [`Model::score_term`])
+/// * `n_docs` number of total documents in the index
+/// * `df` number of documents the term appears in
+/// * `k1` free parameter of BM25, controls the term frequency saturation
+pub fn compute_bm25(f: f32, n_docs: usize, df: usize, k1: f32) -> f32 {
+    let n = n_docs as f32;
+    let m = df as f32;
+
+    // the `+ 1` keeps idf positive for terms present in most of the documents
+    let idf = ((n - m + 0.5) / (m + 0.5) + 1.0).ln();
+
+    idf * (f * (k1 + 1.0)) / (f + k1)
+}
+
+/// Computes how spread out the occurrences of several terms are in a document
+/// Returns the width of the smallest window containing one position from every list,
+/// `0` means the positions are adjacent
+///
+/// # Arguments
+///
+/// * `positions` sorted positions of every term
+/// * `ordered` positions are offset by the index of their term, so that a phrase
+///   occurring exactly in order has a width of `0`
+pub fn compute_span(positions: &[&[usize]], ordered: bool) -> Option<usize> {
+    let n = positions.len();
+
+    if n == 0 || positions.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+
+    // shifts positions so that an exact phrase lines up on the same value
+    let shift = |i: usize, p: usize| if ordered { p + n - i } else { p };
+
+    // always advances the list holding the smallest position of the window
+    let mut cursors = vec![0; n];
+    let mut heap = BinaryHeap::new();
+    let mut max = 0;
+
+    for (i, p) in positions.iter().enumerate() {
+        let value = shift(i, p[0]);
+        max = max.max(value);
+        heap.push(Reverse((value, i)));
+    }
+
+    let mut best = usize::MAX;
+
+    while let Some(Reverse((min, i))) = heap.pop() {
+        best = best.min(max - min);
+
+        cursors[i] += 1;
+
+        let Some(p) = positions[i].get(cursors[i]) else {
+            break;
+        };
+
+        let value = shift(i, *p);
+        max = max.max(value);
+        heap.push(Reverse((value, i)));
+    }
+
+    // unordered windows of `n` distinct terms are at least `n - 1` wide
+    if !ordered {
+        best -= n - 1;
+    }
+
+    Some(best)
+}
+
+// wildcard and fuzzy queries are expanded to at most that many terms,
+// the closest and then the most frequent ones
+const MAX_EXPANSIONS: usize = 64;
+// the score of a fuzzy match is multiplied by that for every edit
+const FUZZY_DISCOUNT: f32 = 0.5;
+
+/// How the sub queries of a query are matched and scored
+#[derive(Debug, Clone, Copy)]
+struct Scope {
+    scorer: Scorer,
+    // only that field is searched, all of them when `None`
+    field: Option<Field>,
+    // terms missing from the index match the terms close to them, see `fuzzy::auto_distance`
+    fallback: bool,
+}
+
+impl Scope {
+    fn fields(self) -> impl Iterator<Item = Field> {
+        Field::ALL
+            .into_iter()
+            .filter(move |field| self.field.is_none() || self.field == Some(*field))
+    }
+}
+
+/// Splits the sub queries into the excluded ones (`NOT a`, `-a`),
+/// unwrapped from their negation, and the rest
+fn split_excluded(queries: &[Query]) -> (Vec<&Query>, Vec<&Query>) {
+    let mut excluded = Vec::new();
+    let mut rest = Vec::new();
+
+    for query in queries {
+        match query {
+            Query::Not(query) => excluded.push(query.as_ref()),
+            query => rest.push(query),
+        }
+    }
+
+    (excluded, rest)
+}
+
+impl Model {
+    /// Empty model scoring the documents with these settings
+    pub fn new(scorer: Scorer, boosts: Boosts, lexer: LexerOptions) -> Self {
+        Self {
+            scorer,
+            boosts,
+            lexer,
+            ..Default::default()
+        }
+    }
+
+    /// Rebuilds the inverted index and the document frequencies from the term
+    /// frequencies of every document
+    /// The index is not serialized, so this has to be called after loading a model
+    pub fn rebuild_index(&mut self) {
+        self.df.clear();
+        self.index.clear();
+        self.field_counts.clear();
+        self.spellings.clear();
+
+        for (id, _, doc) in self.docs.iter() {
+            for (field, terms) in &doc.fields {
+                *self.field_counts.entry(*field).or_default() += terms.count;
+            }
+
+            for (t, spelling) in &doc.spellings {
+                *self
+                    .spellings
+                    .entry(t.clone())
+                    .or_default()
+                    .entry(spelling.clone())
+                    .or_default() += 1;
+            }
+
+            for (t, f) in doc.term_freqs() {
+                *self.df.entry(t.clone()).or_default() += 1;
+                self.index.entry(t).or_default().insert(id, f);
+            }
+        }
+    }
+
+    /// Searches the segments of a store along with the documents in memory
+    ///
+    /// A document of a segment is hidden by the newer segments holding or deleting
+    /// its path, and by the documents in memory. The segments already set keep
+    /// what they know about their documents, only the new ones are checked.
+    pub fn set_segments(&mut self, readers: Vec<Arc<SegmentReader>>) {
+        let kept = self
+            .segments
+            .iter()
+            .zip(&readers)
+            .take_while(|(segment, reader)| Arc::ptr_eq(&segment.reader, reader))
+            .count();
+
+        self.segments.truncate(kept);
+
+        for reader in readers.into_iter().skip(kept) {
+            let base = self.memory_base();
+            self.segments.push(MappedSegment::new(reader, base));
+        }
+
+        for i in kept.max(1)..self.segments.len() {
+            let reader = Arc::clone(&self.segments[i].reader);
+
+            let paths = (0..reader.doc_count())
+                .map(|rank| reader.doc_path(rank))
+                .chain(reader.deleted());
+
+            for path in paths {
+                self.hide(path, i);
+            }
+        }
+
+        let paths = self
+            .docs
+            .paths()
+            .chain(self.changed.iter().map(PathBuf::as_path))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+
+        for path in &paths {
+            self.hide(path, self.segments.len());
+        }
+    }
+
+    /// Forgets the documents in memory once a segment holds them, see [`Model::set_segments`]
+    pub fn clear_memory(&mut self) {
+        self.docs = Documents::default();
+        self.df.clear();
+        self.index.clear();
+        self.field_counts.clear();
+        self.spellings.clear();
+        self.changed.clear();
+    }
+
+    /// Copies the documents changed since the last segment was written, to write
+    /// them without holding the model, see [`Model::replace_memory`]
+    pub fn take_changes(&mut self) -> Changes {
+        let mut changes = Changes::default();
+
+        for path in self.changed.drain() {
+            match self.docs.get(&path) {
+                Some(doc) => changes.docs.push((path, doc.clone())),
+                None => changes.deleted.push(path),
+            }
+        }
+
+        changes
+    }
+
+    /// Takes back the changes of a segment that could not be written
+    pub fn restore_changes(&mut self, changes: &Changes) {
+        self.changed.extend(changes.paths().cloned());
+    }
+
+    /// Searches the segments, the last of them holding the `changes`, instead of
+    /// the documents in memory
+    /// The documents changed again since [`Model::take_changes`] are newer
+    /// than the segment and stay in memory
+    pub fn replace_memory(&mut self, changes: &Changes, readers: Vec<Arc<SegmentReader>>) {
+        for (path, _) in &changes.docs {
+            if !self.changed.contains(path) {
+                self.forget_document(path);
+            }
+        }
+
+        self.set_segments(readers);
+    }
+
+    /// Moves the live documents of the segments in memory, for the formats holding
+    /// the whole model such as the JSON export
+    /// The segments only count the spellings of every term, they are given back to the
+    /// documents containing the term in turn, the most common spelling first
+    pub fn load_segments(&mut self) {
+        let mut loaded = BTreeMap::<(usize, u32), (BTreeMap<Field, FieldTerms>, Spellings)>::new();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let reader = &segment.reader;
+
+            for t in 0..reader.term_count() {
+                let entry = reader.term(t);
+
+                // spellings and the number of documents left to give them to
+                let mut spellings = entry.spellings().collect::<Vec<_>>();
+                spellings.sort_by_key(|(spelling, n)| (Reverse(*n), *spelling));
+
+                for posting in entry.postings() {
+                    if !segment.is_live(posting.doc) {
+                        continue;
+                    }
+
+                    let (fields, doc_spellings) = loaded.entry((i, posting.doc)).or_default();
+
+                    if let Some((spelling, n)) = spellings.iter_mut().find(|(_, n)| *n > 0) {
+                        doc_spellings.insert(entry.term.to_string(), spelling.to_string());
+                        *n -= 1;
+                    }
+
+                    for (field, positions) in Field::ALL.into_iter().zip(posting.fields) {
+                        let Some(positions) = positions else {
+                            continue;
+                        };
+
+                        let terms = fields.entry(field).or_default();
+                        let positions = segment_file::positions(positions).collect::<Vec<_>>();
+
+                        terms.count += positions.len();
+                        terms.tf.insert(entry.term.to_string(), positions.len());
+                        terms.positions.insert(entry.term.to_string(), positions);
+                    }
+                }
+            }
+
+            // documents without any term have no postings
+            for rank in 0..reader.doc_count() {
+                if segment.is_live(rank) {
+                    loaded.entry((i, rank)).or_default();
+                }
+            }
+        }
+
+        let loaded = loaded
+            .into_iter()
+            .map(|((i, rank), (fields, spellings))| {
+                let reader = &self.segments[i].reader;
+                let info = reader.doc_info(rank);
+                let stamp = FileStamp {
+                    last_modified: info.last_modified,
+                    size: info.size,
+                };
+
+                (
+                    reader.doc_path(rank).to_path_buf(),
+                    Doc::from_fields(fields, spellings, stamp, info.hash),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        self.segments.clear();
+
+        for (path, doc) in loaded {
+            self.insert_document(path, doc);
+        }
+    }
+
+    /// Id of the first document in memory, after the ones of the segments
+    fn memory_base(&self) -> DocId {
+        self.segments
+            .last()
+            .map(|segment| segment.base + segment.reader.doc_count())
+            .unwrap_or(0)
+    }
+
+    /// Segment holding the document of that id and its rank in the segment
+    fn segment_of(&self, id: DocId) -> Option<(&MappedSegment, u32)> {
+        // segments holding only tombstones end where they start
+        let i = self
+            .segments
+            .partition_point(|segment| segment.base + segment.reader.doc_count() <= id);
+        let segment = self.segments.get(i)?;
+
+        (segment.base <= id).then_some((segment, id - segment.base))
+    }
+
+    /// Path of the document of that id
+    fn doc_path(&self, id: DocId) -> Option<&Path> {
+        match self.segment_of(id) {
+            Some((segment, rank)) => Some(segment.reader.doc_path(rank)),
+            None => self.docs.path(id.checked_sub(self.memory_base())?),
+        }
+    }
+
+    /// Number of terms in every field of the document of that id, in the order of `Field::ALL`
+    fn field_lengths(&self, id: DocId) -> [usize; 4] {
+        match self.segment_of(id) {
+            Some((segment, rank)) => segment.reader.doc_info(rank).counts,
+            None => {
+                let doc = id
+                    .checked_sub(self.memory_base())
+                    .and_then(|id| self.docs.doc(id));
+
+                Field::ALL.map(|field| {
+                    doc.and_then(|doc| doc.field(field))
+                        .map_or(0, |terms| terms.count)
+                })
+            }
+        }
+    }
+
+    /// Ids and paths of the live documents of the segments and of the documents in memory
+    fn live_docs(&self) -> impl Iterator<Item = (DocId, &Path)> {
+        let memory_base = self.memory_base();
+
+        self.segments
+            .iter()
+            .flat_map(|segment| {
+                (0..segment.reader.doc_count())
+                    .filter(|rank| segment.is_live(*rank))
+                    .map(|rank| (segment.base + rank, segment.reader.doc_path(rank)))
+            })
+            .chain(
+                self.docs
+                    .iter()
+                    .map(move |(id, path, _)| (memory_base + id, path)),
+            )
+    }
+
+    /// Paths of all the documents of the model
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.live_docs().map(|(_, path)| path)
+    }
+
+    /// Number of documents of the model
+    pub fn doc_count(&self) -> usize {
+        self.docs.len()
+            + self
+                .segments
+                .iter()
+                .map(|segment| segment.docs)
+                .sum::<usize>()
+    }
+
+    /// Live version of a document in the segments, its segment and rank
+    fn find_segment_doc(&self, file_path: &Path) -> Option<(&MappedSegment, u32)> {
+        // the newest segment holding the path has the latest version
+        let (segment, rank) = self.segments.iter().rev().find_map(|segment| {
+            let rank = segment.reader.find_doc(file_path)?;
+            Some((segment, rank))
+        })?;
+
+        segment.is_live(rank).then_some((segment, rank))
+    }
+
+    /// Marks the documents of that path in the segments before `end` as replaced or removed,
+    /// returns whether one of them was live
+    fn hide(&mut self, file_path: &Path, end: usize) -> bool {
+        let mut hidden = false;
+
+        for segment in &mut self.segments[..end] {
+            if let Some(rank) = segment.reader.find_doc(file_path) {
+                hidden |= segment.kill(rank);
+            }
+        }
+
+        hidden
+    }
+
+    /// Remove a file from the model
+    /// and also decrements the model's `document frequency` for
+    /// all the terms accordingly
+    pub fn remove_document(&mut self, file_path: &Path) {
+        let hidden = self.hide(file_path, self.segments.len());
+
+        if self.forget_document(file_path) || hidden {
+            self.touched.remove(file_path);
+            self.changed.insert(file_path.to_path_buf());
+            self.generation += 1;
+        }
+    }
+
+    /// Removes a document from memory only, returns whether it was there
+    fn forget_document(&mut self, file_path: &Path) -> bool {
+        let Some((id, doc)) = self.docs.remove(file_path) else {
+            return false;
+        };
+
+        for (field, terms) in &doc.fields {
+            if let Some(count) = self.field_counts.get_mut(field) {
+                *count -= terms.count;
+            }
+        }
+
+        for (t, spelling) in &doc.spellings {
+            if let Some(counts) = self.spellings.get_mut(t) {
+                if let Some(n) = counts.get_mut(spelling) {
+                    *n -= 1;
+
+                    if *n == 0 {
+                        counts.remove(spelling);
+                    }
+                }
+
+                if counts.is_empty() {
+                    self.spellings.remove(t);
+                }
+            }
+        }
+
+        for t in doc.term_freqs().keys() {
+            if let Some(f) = self.df.get_mut(t) {
+                *f -= 1;
+
+                if *f == 0 {
+                    self.df.remove(t);
+                }
+            }
+
+            if let Some(postings) = self.index.get_mut(t) {
+                postings.remove(&id);
+
+                if postings.is_empty() {
+                    self.index.remove(t);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Changes how the documents are tokenized
+    /// All the documents are removed so that they are indexed again, the segments
+    /// of a store have to be replaced as well, see `SegmentStore::rewrite`
+    pub fn set_lexer(&mut self, options: LexerOptions) {
+        if self.lexer == options {
+            return;
+        }
+
+        self.clear_memory();
+        self.segments.clear();
+        self.touched.clear();
+        self.generation += 1;
+
+        self.lexer = options;
+    }
+
+    /// A document/file requires reindexing
+    /// * If it is not present in the index
+    /// * Or the modification time or the size of the file changed since it was indexed,
+    ///   a modification time going backwards counts too (restored from a backup)
+    ///
+    /// The content of such files may still be the same, compare [`Model::indexed_hash`]
+    /// with the hash of the content before parsing them again
+    pub fn requires_reindexing(&self, file_path: &Path, stamp: FileStamp) -> bool {
+        if let Some(doc) = self.docs.get(file_path) {
+            // every document has at least the terms of its path
+            let missing_fields = doc.fields.is_empty();
+
+            return missing_fields
+                || doc.last_modified != stamp.last_modified
+                || doc.size != stamp.size;
+        }
+
+        let Some((segment, rank)) = self.find_segment_doc(file_path) else {
+            return true;
+        };
+
+        let info = segment.reader.doc_info(rank);
+        let last_modified = self
+            .touched
+            .get(file_path)
+            .cloned()
+            .unwrap_or(info.last_modified);
+
+        last_modified != stamp.last_modified || info.size != stamp.size
+    }
+
+    /// Hash of the content of the file when it was indexed
+    pub fn indexed_hash(&self, file_path: &Path) -> Option<u32> {
+        match self.docs.get(file_path) {
+            Some(doc) => Some(doc.hash),
+            None => {
+                let (segment, rank) = self.find_segment_doc(file_path)?;
+                Some(segment.reader.doc_info(rank).hash)
+            }
+        }
+    }
+
+    /// Records the new modification time of a file whose content did not change
+    pub fn touch_document(&mut self, file_path: &Path, stamp: FileStamp) {
+        if let Some(doc) = self.docs.get_mut(file_path) {
+            doc.last_modified = stamp.last_modified;
+            self.changed.insert(file_path.to_path_buf());
+        } else if self.find_segment_doc(file_path).is_some() {
+            // the segment cannot change, the time is saved with the list of segments
+            self.touched
+                .insert(file_path.to_path_buf(), stamp.last_modified);
+        }
+    }
+
+    /// Whether no parser could read the file when it last had that stamp
+    pub fn is_rejected(&self, file_path: &Path, stamp: FileStamp) -> bool {
+        self.rejected.get(file_path) == Some(&stamp)
+    }
+
+    /// Average number of terms in a field of a document
+    pub fn avg_count(&self, field: Field) -> f32 {
+        let n_docs = self.doc_count();
+
+        if n_docs == 0 {
+            return 0.0;
+        }
+
+        let count = self.field_counts.get(&field).cloned().unwrap_or(0)
+            + self
+                .segments
+                .iter()
+                .map(|segment| segment.field_counts[field as usize])
+                .sum::<usize>();
+
+        count as f32 / n_docs as f32
+    }
+
+    /// Number of documents containing `term`
+    /// The segments still count the documents replaced or removed since they were written
+    pub fn doc_freq(&self, term: &str) -> usize {
+        self.df.get(term).cloned().unwrap_or(0)
+            + self
+                .segments
+                .iter()
+                .filter_map(|segment| segment.reader.find_term(term))
+                .map(|entry| entry.df)
+                .sum::<usize>()
+    }
+
+    /// Terms of the index starting with `prefix` along with their document frequency,
+    /// a range of the sorted vocabulary of the memory and of every segment
+    /// Like [`Model::doc_freq`], the frequencies count the documents replaced in the segments
+    pub fn terms_with_prefix(&self, prefix: &str) -> BTreeMap<&str, usize> {
+        let mut terms = BTreeMap::new();
+
+        for (t, f) in self
+            .df
+            .range(prefix.to_string()..)
+            .take_while(|(t, _)| t.starts_with(prefix))
+        {
+            *terms.entry(t.as_str()).or_default() += f;
+        }
+
+        for segment in &self.segments {
+            for entry in segment
+                .reader
+                .terms_from(prefix)
+                .take_while(|entry| entry.term.starts_with(prefix))
+            {
+                *terms.entry(entry.term).or_default() += entry.df;
+            }
+        }
+
+        terms
+    }
+
+    /// Terms of the index matching the wildcard `pattern`, at most [`MAX_EXPANSIONS`]
+    /// The terms starting with the characters before the first wildcard are a range
+    /// of the sorted vocabulary, only those are matched against the whole pattern
+    /// The words are stemmed, so the pattern is also matched against the spellings of the terms,
+    /// `texture*` finds `textur` through `textures`
+    pub fn expand_wildcard(&self, pattern: &str) -> Vec<&str> {
+        let prefix = pattern.split(['*', '?']).next().unwrap_or_default();
+
+        let mut terms = self
+            .terms_with_prefix(prefix)
+            .into_iter()
+            .filter(|(t, _)| matches_wildcard(pattern, t))
+            .collect::<BTreeMap<_, _>>();
+
+        for (term, spellings) in &self.spellings {
+            if spellings
+                .keys()
+                .any(|s| s.starts_with(prefix) && matches_wildcard(pattern, s))
+            {
+                terms.entry(term).or_insert_with(|| self.doc_freq(term));
+            }
+        }
+
+        for segment in &self.segments {
+            for (_, entry) in segment
+                .reader
+                .spellings_from(prefix)
+                .take_while(|(s, _)| s.starts_with(prefix))
+                .filter(|(s, _)| matches_wildcard(pattern, s))
+            {
+                terms
+                    .entry(entry.term)
+                    .or_insert_with(|| self.doc_freq(entry.term));
+            }
+        }
+
+        let mut terms = terms.into_iter().collect::<Vec<_>>();
+
+        if terms.len() > MAX_EXPANSIONS {
+            terms.sort_by_key(|(_, f)| Reverse(*f));
+            terms.truncate(MAX_EXPANSIONS);
+        }
+
+        terms.into_iter().map(|(t, _)| t).collect()
+    }
+
+    /// Terms of the index within `max_distance` edits of `term` along with their distance,
+    /// at most [`MAX_EXPANSIONS`]
+    pub fn expand_fuzzy(&self, term: &str, max_distance: usize) -> Vec<(&str, usize)> {
+        let automaton = LevenshteinAutomaton::new(term, max_distance);
+        let mut terms = automaton.matches(|from| {
+            self.df
+                .range(from.to_string()..)
+                .next()
+                .map(|(t, _)| t.as_str())
+        });
+
+        for segment in &self.segments {
+            let reader = &segment.reader;
+
+            terms.extend(automaton.matches(|from| {
+                let i = reader.seek_term(from);
+                (i < reader.term_count()).then(|| reader.term(i).term)
+            }));
+        }
+
+        terms.sort();
+        terms.dedup();
+
+        if terms.len() > MAX_EXPANSIONS {
+            terms.sort_by_key(|(t, distance)| (*distance, Reverse(self.doc_freq(t))));
+            terms.truncate(MAX_EXPANSIONS);
+        }
+
+        terms
+    }
+
+    /// Most common spelling of a stemmed term of the index
+    pub fn spelling(&self, term: &str) -> String {
+        let mut counts = HashMap::<&str, usize>::new();
+
+        if let Some(memory) = self.spellings.get(term) {
+            for (spelling, n) in memory {
+                *counts.entry(spelling).or_default() += n;
+            }
+        }
+
+        for segment in &self.segments {
+            if let Some(entry) = segment.reader.find_term(term) {
+                for (spelling, n) in entry.spellings() {
+                    *counts.entry(spelling).or_default() += n;
+                }
+            }
+        }
+
+        if counts.is_empty() {
+            return term.to_string();
+        }
+
+        // the other documents spell it as the term itself
+        let unchanged = self.doc_freq(term).saturating_sub(counts.values().sum());
+
+        counts
+            .into_iter()
+            .chain([(term, unchanged)])
+            .max_by_key(|(spelling, n)| (*n, Reverse(*spelling)))
+            .map(|(spelling, _)| spelling.to_string())
+            .unwrap_or_else(|| term.to_string())
+    }
+
+    /// Spelling of the closest and then most frequent term of the index,
+    /// `None` when the term is in the index or nothing is close enough
+    pub fn correct_term(&self, term: &str) -> Option<String> {
+        let distance = self.fallback_distance(term)?;
+
+        let (best, _) = self
+            .expand_fuzzy(term, distance)
+            .into_iter()
+            .min_by_key(|(t, distance)| (*distance, Reverse(self.doc_freq(t)), *t))?;
+
+        Some(self.spelling(best))
+    }
+
+    /// Query with the words missing from the index replaced by the closest terms,
+    /// `None` when there is nothing to correct
+    pub fn did_you_mean(&self, query: &[char]) -> Option<String> {
+        query::correct(query, &self.lexer, |term| self.correct_term(term))
+    }
+
+    /// Edit distance of the fuzzy fallback of a term, `None` when the term is in the index
+    fn fallback_distance(&self, term: &str) -> Option<usize> {
+        if self.doc_freq(term) > 0 {
+            return None;
+        }
+
+        Some(auto_distance(term)).filter(|distance| *distance > 0)
+    }
+
+    /// Replaces the wildcards, the fuzzy terms and the terms missing from the index
+    /// with the terms they match, so that the terms can be highlighted in the snippets
+    pub fn expand(&self, query: &Query) -> Query {
+        let expand = |queries: &[Query]| queries.iter().map(|q| self.expand(q)).collect();
+
+        let fuzzy = |term: &str, distance: usize| {
+            Query::Or(
+                self.expand_fuzzy(term, distance)
+                    .iter()
+                    .map(|(t, _)| Query::Term(t.to_string()))
+                    .collect(),
+            )
+        };
+
+        match query {
+            Query::Wildcard(pattern) => Query::Or(
+                self.expand_wildcard(pattern)
+                    .iter()
+                    .map(|t| Query::Term(t.to_string()))
+                    .collect(),
+            ),
+            Query::Fuzzy { term, distance } => fuzzy(term, *distance),
+            Query::Term(term) => match self.fallback_distance(term) {
+                Some(distance) => fuzzy(term, distance),
+                None => query.clone(),
+            },
+            Query::And(queries) => Query::And(expand(queries)),
+            Query::Or(queries) => Query::Or(expand(queries)),
+            Query::Clauses(queries) => Query::Clauses(expand(queries)),
+            Query::Not(query) => Query::Not(Box::new(self.expand(query))),
+            Query::Required(query) => Query::Required(Box::new(self.expand(query))),
+            Query::Field { field, query } => Query::Field {
+                field: *field,
+                query: Box::new(self.expand(query)),
+            },
+            Query::Phrase { .. } | Query::Filter(_) => query.clone(),
+        }
+    }
 
-pub type TermFreq = HashMap<String, usize>; // frequency for a token
-pub type DocFreq = HashMap<String, usize>; // frequency for a token in all the documents
+    /// Segments searched along with the documents in memory, oldest first
+    pub fn segment_readers(&self) -> impl Iterator<Item = &SegmentReader> {
+        self.segments.iter().map(|segment| segment.reader.as_ref())
+    }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct Doc {
-    tf: TermFreq,
-    count: usize,
-    // SystemTime is platform dependent
-    // to an index generated on mac  may not be deserialized
-    last_modified: SystemTime,
-}
+    /// Postings of `term` in the live documents of the segments and of the memory
+    fn postings(&self, term: &str) -> TermPostings<'_> {
+        let mut postings = Vec::new();
 
-type Docs = HashMap<PathBuf, Doc>; // token frequency for a file
+        for segment in &self.segments {
+            let Some(entry) = segment.reader.find_term(term) else {
+                continue;
+            };
 
-#[derive(Deserialize, Serialize, Default, Debug)]
-pub struct Model {
-    pub docs: Docs,
-    pub df: DocFreq,
-}
+            postings.reserve(entry.df);
 
-/// Returns the TF for a term in a particular document
-pub fn compute_tf(term: &str, doc: &Doc) -> f32 {
-    let b = doc.count as f32;
-    let a = doc.tf.get(term).cloned().unwrap_or(0) as f32;
+            for posting in entry.postings() {
+                if !segment.is_live(posting.doc) {
+                    continue;
+                }
 
-    a / b
-}
+                postings.push(Posting {
+                    doc: segment.base + posting.doc,
+                    fields: posting.fields.map(|f| f.map(FieldPositions::Mapped)),
+                });
+            }
+        }
 
-/// Computes IDF for a term
-/// # Arguments
-///
-/// * `term` term for calculate for
-/// * `n_docs` number of total documents in the index
-/// * `df` document frequency hash map (map of number of documents a terms appears in)
-pub fn compute_idf(term: &str, n_docs: usize, df: &DocFreq) -> f32 {
-    let n = n_docs as f32;
+        if let Some(ids) = self.index.get(term) {
+            let memory_base = self.memory_base();
 
-    let m = df.get(term).cloned().unwrap_or(1) as f32;
+            for id in ids.keys() {
+                let Some(doc) = self.docs.doc(*id) else {
+                    continue;
+                };
 
-    (n / m).log10() // smaller values are turned negative due to log
-}
+                let fields = Field::ALL.map(|field| {
+                    let positions = doc.field(field)?.positions.get(term)?;
+                    Some(FieldPositions::Memory(positions))
+                });
 
-impl Model {
-    /// Remove a file from the model
-    /// and also decrements the model's `document frequency` for
-    /// all the terms accordingly
-    pub fn remove_document(&mut self, file_path: &Path) {
-        if let Some(doc) = self.docs.remove(file_path) {
-            for t in doc.tf.keys() {
-                if let Some(f) = self.df.get_mut(t) {
-                    *f -= 1;
-                }
+                postings.push(Posting {
+                    doc: memory_base + id,
+                    fields,
+                });
             }
         }
+
+        TermPostings::new(postings)
     }
 
-    /// A document/file requires reindexing
-    /// * If it is already present in the index
-    /// * And the file is modified after being indexed
-    pub fn requires_reindexing(&mut self, file_path: &Path, last_modified: SystemTime) -> bool {
-        if let Some(doc) = self.docs.get(file_path) {
-            return doc.last_modified < last_modified;
+    /// Returns the score of the term of `posting` in the fields searched by `scope`
+    /// `df` is the number of documents containing the term
+    ///
+    /// The frequency of the term in every field is multiplied by the boost of the field,
+    /// BM25 also normalizes it by the length of the field (BM25F)
+    fn score_term(&self, scope: Scope, df: usize, posting: &Posting) -> f32 {
+        let n_docs = self.doc_count();
+        let lengths = self.field_lengths(posting.doc);
+
+        let weighted_tf = |normalize: &dyn Fn(Field, usize) -> f32| {
+            scope
+                .fields()
+                .filter_map(|field| {
+                    let f = posting.fields[field as usize]?.len() as f32;
+                    Some(self.boosts.get(field) * f / normalize(field, lengths[field as usize]))
+                })
+                .sum::<f32>()
+        };
+
+        match scope.scorer {
+            Scorer::TfIdf => {
+                let f = weighted_tf(&|_, _| 1.0);
+                compute_tf(f, lengths.iter().sum()) * compute_idf(n_docs, df)
+            }
+            Scorer::Bm25 { k1, b } => {
+                let f = weighted_tf(&|field, count| {
+                    let length = count as f32 / self.avg_count(field).max(1.0);
+                    1.0 - b + b * length
+                });
+
+                compute_bm25(f, n_docs, df, k1)
+            }
+        }
+    }
+
+    /// Returns the width of the closest occurrence of the terms of `postings`, all of
+    /// the same document, in a single field searched by `scope`, see [`compute_span`]
+    fn span(scope: Scope, postings: &[&Posting], ordered: bool) -> Option<usize> {
+        scope
+            .fields()
+            .filter_map(|field| {
+                let positions = postings
+                    .iter()
+                    .map(|posting| Some(posting.fields[field as usize]?.get()))
+                    .collect::<Option<Vec<_>>>()?;
+
+                let positions = positions.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+
+                compute_span(&positions, ordered)
+            })
+            .min()
+    }
+
+    /// Whether the term of the posting is in one of the fields searched by `scope`
+    fn contains(scope: Scope, posting: &Posting) -> bool {
+        scope
+            .fields()
+            .any(|field| posting.fields[field as usize].is_some())
+    }
+
+    /// Every document of the model with a score of `0`
+    fn all_documents(&self) -> Matches {
+        self.live_docs().map(|(id, _)| (id, 0.0)).collect()
+    }
+
+    /// Documents matching a single term
+    fn evaluate_term(&self, term: &str, scope: Scope) -> Matches {
+        let mut matches = Matches::new();
+
+        let postings = self.postings(term);
+
+        for posting in &postings.postings {
+            if scope.field.is_some() && !Self::contains(scope, posting) {
+                continue;
+            }
+
+            matches.insert(posting.doc, self.score_term(scope, postings.df(), posting));
+        }
+
+        matches
+    }
+
+    /// Documents containing the `terms` in order with at most `slop` extra positions
+    /// between them. The closer the terms are, the higher the document ranks.
+    fn evaluate_phrase(&self, terms: &[String], slop: usize, scope: Scope) -> Matches {
+        let mut matches = Matches::new();
+
+        let postings = terms.iter().map(|t| self.postings(t)).collect::<Vec<_>>();
+
+        // candidates have to contain the rarest term of the phrase
+        let Some(rarest) = postings.iter().min_by_key(|p| p.df()) else {
+            return matches;
+        };
+
+        for candidate in &rarest.postings {
+            let Some(found) = postings
+                .iter()
+                .map(|p| p.get(candidate.doc))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+
+            let Some(width) = Self::span(scope, &found, true) else {
+                continue;
+            };
+
+            if width > slop {
+                continue;
+            }
+
+            let score = found
+                .iter()
+                .zip(&postings)
+                .map(|(posting, p)| self.score_term(scope, p.df(), posting))
+                .sum::<f32>();
+
+            matches.insert(candidate.doc, score / (1.0 + width as f32));
         }
 
-        return true;
+        matches
     }
 
-    /// Search for a term `query` in the model
-    pub fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
-        let mut result = Vec::new();
+    /// Keeps the documents matched by every query in `required`, summing their scores
+    fn intersect(&self, required: &[&Query], scope: Scope) -> Matches {
+        let mut queries = required.iter();
 
-        let tokens = Lexer::new(&query).collect::<Vec<_>>();
+        let Some(first) = queries.next() else {
+            return Matches::new();
+        };
 
-        for (path, doc) in &self.docs {
-            let mut rank = 0f32;
+        let mut matches = self.evaluate(first, scope);
 
-            for token in &tokens {
-                let mut env = snowball::SnowballEnv::create(&token);
-                snowball::algorithms::english_stemmer::stem(&mut env);
-                let stemmed = env.get_current().to_string();
+        for query in queries {
+            if matches.is_empty() {
+                break;
+            }
+
+            let other = self.evaluate(query, scope);
+
+            matches.retain(|id, _| other.contains_key(id));
+
+            for (id, rank) in matches.iter_mut() {
+                *rank += other[id];
+            }
+        }
+
+        matches
+    }
+
+    /// Documents matched by any of the `queries`, summing their scores
+    fn union(&self, queries: &[&Query], scope: Scope) -> Matches {
+        let mut matches = Matches::new();
+
+        for query in queries {
+            for (id, rank) in self.evaluate(query, scope) {
+                *matches.entry(id).or_default() += rank;
+            }
+        }
+
+        matches
+    }
+
+    /// Removes the documents matched by any of the `excluded` queries
+    fn exclude(&self, matches: &mut Matches, excluded: &[&Query], scope: Scope) {
+        for query in excluded {
+            if matches.is_empty() {
+                return;
+            }
+
+            // a typo must not exclude the documents containing the correct term
+            let scope = Scope {
+                fallback: false,
+                ..scope
+            };
+
+            for id in self.evaluate(query, scope).keys() {
+                matches.remove(id);
+            }
+        }
+    }
+
+    /// Documents matching the terms within `max_distance` edits of `term`
+    /// Every edit lowers the score by [`FUZZY_DISCOUNT`]
+    fn evaluate_fuzzy(&self, term: &str, max_distance: usize, scope: Scope) -> Matches {
+        let mut matches = Matches::new();
+
+        for (t, distance) in self.expand_fuzzy(term, max_distance) {
+            let discount = FUZZY_DISCOUNT.powi(distance as i32);
+
+            // the best of the close terms, a document with several spellings does not add up
+            for (id, rank) in self.evaluate_term(t, scope) {
+                let best = matches.entry(id).or_default();
+                *best = best.max(rank * discount);
+            }
+        }
+
+        matches
+    }
+
+    /// Evaluates the query against the inverted index,
+    /// returns the matching documents and their scores
+    fn evaluate(&self, query: &Query, scope: Scope) -> Matches {
+        match query {
+            Query::Term(term) => match self.fallback_distance(term) {
+                Some(distance) if scope.fallback => self.evaluate_fuzzy(term, distance, scope),
+                _ => self.evaluate_term(term, scope),
+            },
+            Query::Fuzzy { term, distance } => self.evaluate_fuzzy(term, *distance, scope),
+            Query::Phrase { terms, slop } => self.evaluate_phrase(terms, *slop, scope),
+            Query::Wildcard(_) => self.evaluate(&self.expand(query), scope),
+            Query::Required(query) => self.evaluate(query, scope),
+            Query::Field { field, query } => {
+                let scope = Scope {
+                    field: Some(*field),
+                    ..scope
+                };
+
+                self.evaluate(query, scope)
+            }
+            Query::Filter(filter) => self
+                .live_docs()
+                .filter(|(_, path)| filter.matches(path))
+                .map(|(id, _)| (id, 0.0))
+                .collect(),
+            Query::Not(query) => {
+                let mut matches = self.all_documents();
+                self.exclude(&mut matches, &[query], scope);
 
-                rank +=
-                    compute_tf(&stemmed, doc) * compute_idf(&stemmed, self.docs.len(), &self.df);
+                matches
             }
+            Query::Or(queries) => self.union(&queries.iter().collect::<Vec<_>>(), scope),
+            Query::And(queries) => {
+                let (excluded, required) = split_excluded(queries);
 
-            if !rank.is_nan() && rank != 0.0 {
-                result.push((path.clone(), rank));
+                let mut matches = if required.is_empty() {
+                    self.all_documents()
+                } else {
+                    self.intersect(&required, scope)
+                };
+
+                self.exclude(&mut matches, &excluded, scope);
+
+                matches
+            }
+            Query::Clauses(queries) => {
+                let (excluded, rest) = split_excluded(queries);
+
+                // filters restrict the matches of the other clauses
+                let (filters, rest): (Vec<_>, Vec<_>) = rest
+                    .into_iter()
+                    .partition(|q| matches!(q, Query::Filter(_)));
+
+                let (required, optional): (Vec<_>, Vec<_>) = rest
+                    .into_iter()
+                    .partition(|q| matches!(q, Query::Required(_)));
+
+                let mut matches = if !required.is_empty() {
+                    let mut matches = self.intersect(&required, scope);
+
+                    // optional clauses only add to the score of the required matches
+                    for (id, rank) in self.union(&optional, scope) {
+                        if let Some(r) = matches.get_mut(&id) {
+                            *r += rank;
+                        }
+                    }
+
+                    matches
+                } else if !optional.is_empty() {
+                    self.union(&optional, scope)
+                } else if !excluded.is_empty() || !filters.is_empty() {
+                    self.all_documents()
+                } else {
+                    Matches::new()
+                };
+
+                for filter in filters {
+                    if let Query::Filter(filter) = filter {
+                        matches.retain(|id, _| {
+                            self.doc_path(*id).is_some_and(|path| filter.matches(path))
+                        });
+                    }
+                }
+
+                self.exclude(&mut matches, &excluded, scope);
+
+                matches
+            }
+        }
+    }
+
+    /// Ranks the documents matching an already parsed `query`, best first
+    /// Only the documents present in the postings of the query terms are ranked
+    ///
+    /// The query supports `AND`, `OR`, `NOT`, `+required` and `-excluded` clauses,
+    /// grouping with parentheses and quoted phrases, see [`Query`].
+    /// Quoted phrases only match documents containing their terms in order, `"foo bar"~N`
+    /// allows up to `N` extra positions between them. The closer the terms are,
+    /// the higher the document ranks.
+    pub fn search(&self, query: &Query, scorer: Scorer) -> Vec<(PathBuf, f32)> {
+        self.search_page(query, scorer, 0, usize::MAX).results
+    }
+
+    /// Ranks the documents matching `query` like [`Model::search`], but only returns
+    /// the `limit` best ones after skipping the first `offset`
+    /// Documents ranked the same are ordered by path, so that the pages do not overlap
+    pub fn search_page(
+        &self,
+        query: &Query,
+        scorer: Scorer,
+        offset: usize,
+        limit: usize,
+    ) -> SearchPage {
+        let scope = Scope {
+            scorer,
+            field: None,
+            fallback: true,
+        };
+
+        let mut ranks = self.evaluate(query, scope);
+
+        // documents where the terms of the query are close together rank higher
+        let mut terms = query.terms();
+        terms.sort();
+        terms.dedup();
+
+        if terms.len() > 1 {
+            let postings = terms.iter().map(|t| self.postings(t)).collect::<Vec<_>>();
+
+            for (id, rank) in ranks.iter_mut() {
+                let found = postings
+                    .iter()
+                    .filter_map(|p| p.get(*id))
+                    .collect::<Vec<_>>();
+
+                // the closest of the query terms found together in a field
+                let width = Field::ALL
+                    .into_iter()
+                    .filter_map(|field| {
+                        let present = found
+                            .iter()
+                            .filter(|posting| posting.fields[field as usize].is_some())
+                            .cloned()
+                            .collect::<Vec<_>>();
+
+                        if present.len() < 2 {
+                            return None;
+                        }
+
+                        let scope = Scope {
+                            scorer,
+                            field: Some(field),
+                            fallback: false,
+                        };
+
+                        Self::span(scope, &present, false)
+                    })
+                    .min();
+
+                if let Some(width) = width {
+                    *rank *= 1.0 + 1.0 / (1.0 + width as f32);
+                }
             }
         }
 
-        result.sort_by(|(_, sum_tf), (_, sum_tf_2)| sum_tf.partial_cmp(sum_tf_2).unwrap());
+        // bounded heap of the best hits up to the end of the page, the worst one on top
+        let k = offset.saturating_add(limit);
+        let mut best = BinaryHeap::new();
+        let mut total_hits = 0;
+
+        for (id, rank) in ranks {
+            let Some(path) = self.doc_path(id) else {
+                continue;
+            };
+
+            if rank.is_nan() {
+                continue;
+            }
+
+            total_hits += 1;
 
-        result.reverse();
+            if k == 0 {
+                continue;
+            }
+
+            best.push(Reverse(Hit { rank, path }));
+
+            if best.len() > k {
+                best.pop();
+            }
+        }
+
+        let results = best
+            .into_sorted_vec()
+            .into_iter()
+            .skip(offset)
+            .map(|Reverse(hit)| (hit.path.to_path_buf(), hit.rank))
+            .collect();
 
-        Ok(result)
+        SearchPage {
+            total_hits,
+            results,
+        }
     }
 
     /// Add a [file]/[document] to the model
     pub fn add_document(
         &mut self,
         file_path: PathBuf,
-        last_modified: SystemTime,
-        content: &[char],
+        stamp: FileStamp,
+        hash: u32,
+        parsed: &ParsedDocument,
     ) {
+        let doc = Doc::new(&file_path, parsed, stamp, hash, &self.lexer);
+        self.insert_document(file_path, doc);
+    }
+
+    /// Add an already tokenized document to the model
+    pub fn insert_document(&mut self, file_path: PathBuf, doc: Doc) {
         // if document is already present, removes the model
-        self.remove_document(&file_path);
+        self.hide(&file_path, self.segments.len());
+        self.forget_document(&file_path);
+        self.touched.remove(&file_path);
+        self.changed.insert(file_path.clone());
+        self.rejected.remove(&file_path);
+        self.generation += 1;
 
-        let mut tf = TermFreq::new();
+        for (field, terms) in &doc.fields {
+            *self.field_counts.entry(*field).or_default() += terms.count;
+        }
 
-        let mut count = 0;
+        for (t, spelling) in &doc.spellings {
+            *self
+                .spellings
+                .entry(t.clone())
+                .or_default()
+                .entry(spelling.clone())
+                .or_default() += 1;
+        }
+
+        let term_freqs = doc.term_freqs();
+        let id = self.docs.insert(file_path, doc);
 
-        for t in Lexer::new(content) {
-            if let Some(f) = tf.get_mut(&t) {
+        for (t, n) in term_freqs {
+            if let Some(f) = self.df.get_mut(&t) {
                 *f += 1;
             } else {
-                tf.insert(t, 1);
+                self.df.insert(t.clone(), 1);
             }
 
-            count += 1;
+            self.index.entry(t).or_default().insert(id, n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::query;
+    use crate::stopwords::Language;
+
+    const STAMP: FileStamp = FileStamp {
+        last_modified: 0,
+        size: 0,
+    };
+
+    /// Model of documents given by their path and body
+    fn model(docs: &[(&str, &str)]) -> Model {
+        let mut model = Model::new(
+            Scorer::default(),
+            Boosts::default(),
+            LexerOptions::default(),
+        );
+
+        for (path, text) in docs {
+            let parsed = ParsedDocument::new(text.to_string());
+            model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
         }
 
-        for t in tf.keys() {
-            if let Some(f) = self.df.get_mut(t) {
-                *f += 1;
-            } else {
-                self.df.insert(t.to_string(), 1);
-            }
+        model
+    }
+
+    /// Paths of the documents matching `query`, best first
+    fn search(model: &Model, query: &str, scorer: Scorer) -> Vec<String> {
+        let chars = query.chars().collect::<Vec<_>>();
+        let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+
+        model
+            .search(&query, scorer)
+            .into_iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect()
+    }
+
+    const BM25: Scorer = Scorer::Bm25 {
+        k1: Scorer::BM25_K1,
+        b: Scorer::BM25_B,
+    };
+
+    #[test]
+    fn tf_idf() {
+        assert_eq!(compute_tf(2.0, 8), 0.25);
+        assert_eq!(compute_idf(100, 10), 1.0);
+        assert_eq!(compute_idf(10, 10), 0.0);
+        // a term missing from the index does not divide by zero
+        assert_eq!(compute_idf(10, 0), 1.0);
+    }
+
+    #[test]
+    fn bm25_saturates_and_stays_positive() {
+        let once = compute_bm25(1.0, 100, 10, Scorer::BM25_K1);
+        let twice = compute_bm25(2.0, 100, 10, Scorer::BM25_K1);
+        let many = compute_bm25(100.0, 100, 10, Scorer::BM25_K1);
+
+        assert!(once < twice && twice < many);
+        // never more than `idf * (k1 + 1)`
+        let idf = ((100.0 - 10.0 + 0.5) / (10.0 + 0.5) + 1.0f32).ln();
+        assert!(many < idf * (Scorer::BM25_K1 + 1.0));
+
+        // rare terms weigh more, common ones still count
+        assert!(compute_bm25(1.0, 100, 1, 1.2) > once);
+        assert!(compute_bm25(1.0, 100, 100, 1.2) > 0.0);
+    }
+
+    #[test]
+    fn longer_documents_score_lower_with_bm25() {
+        let model = model(&[
+            ("/d/1", "shader"),
+            ("/d/2", "shader texture sampler buffer fence"),
+        ]);
+
+        let scores = |b: f32| {
+            let chars = "shader".chars().collect::<Vec<_>>();
+            let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+            let scorer = Scorer::Bm25 {
+                k1: Scorer::BM25_K1,
+                b,
+            };
+
+            model
+                .search(&query, scorer)
+                .into_iter()
+                .map(|(_, score)| score)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(&model, "shader", BM25), ["/d/1", "/d/2"]);
+        assert!(scores(0.75)[0] > scores(0.75)[1]);
+        // `b = 0` leaves out the length
+        assert_eq!(scores(0.0)[0], scores(0.0)[1]);
+    }
+
+    #[test]
+    fn only_documents_containing_the_terms_match() {
+        let model = model(&[
+            ("/d/1", "vertex shader"),
+            ("/d/2", "fragment shader"),
+            ("/d/3", "texture sampler"),
+        ]);
+
+        for scorer in [Scorer::TfIdf, BM25] {
+            let mut paths = search(&model, "vertex fragment", scorer);
+            paths.sort();
+            assert_eq!(paths, ["/d/1", "/d/2"]);
+        }
+    }
+
+    #[test]
+    fn removed_documents_leave_no_postings_behind() {
+        let mut updated = model(&[
+            ("/d/1", "vertex shader shader"),
+            ("/d/2", "fragment shader"),
+            ("/d/3", "texture sampler"),
+        ]);
+
+        updated.remove_document(Path::new("/d/3"));
+        updated.remove_document(Path::new("/d/1"));
+        updated.add_document(
+            PathBuf::from("/d/1"),
+            STAMP,
+            0,
+            &ParsedDocument::new("vertex buffer".to_string()),
+        );
+
+        let fresh = model(&[("/d/1", "vertex buffer"), ("/d/2", "fragment shader")]);
+
+        // the terms of the old contents are gone, none is left with a df of 0
+        assert_eq!(updated.df, fresh.df);
+        assert!(updated.df.values().all(|df| *df > 0));
+
+        let frequencies = |model: &Model| {
+            let mut frequencies = model
+                .index
+                .iter()
+                .map(|(t, postings)| {
+                    let mut counts = postings.values().cloned().collect::<Vec<_>>();
+                    counts.sort();
+                    (t.clone(), counts)
+                })
+                .collect::<Vec<_>>();
+            frequencies.sort();
+            frequencies
+        };
+
+        assert_eq!(frequencies(&updated), frequencies(&fresh));
+        assert!(updated
+            .index
+            .iter()
+            .all(|(t, postings)| postings.len() == updated.df[t]));
+        assert_eq!(search(&updated, "shader", BM25), ["/d/2"]);
+    }
+
+    #[test]
+    fn files_are_reindexed_when_their_stamp_changes() {
+        let file_path = Path::new("/d/1");
+        let stamp = FileStamp {
+            last_modified: 100,
+            size: 10,
+        };
+
+        let mut model = model(&[]);
+        let parsed = ParsedDocument::new("vertex shader".to_string());
+        model.add_document(file_path.to_path_buf(), stamp, 7, &parsed);
+
+        assert!(!model.requires_reindexing(file_path, stamp));
+        assert!(model.requires_reindexing(Path::new("/d/2"), stamp));
+        // restored from a backup, the modification time went backwards
+        let older = FileStamp {
+            last_modified: 50,
+            ..stamp
+        };
+        assert!(model.requires_reindexing(file_path, older));
+        // rewritten within the same time
+        let resized = FileStamp { size: 11, ..stamp };
+        assert!(model.requires_reindexing(file_path, resized));
+
+        // the content turned out to be the same, only the stamp is updated
+        let touched = FileStamp {
+            last_modified: 200,
+            ..stamp
+        };
+        assert!(model.requires_reindexing(file_path, touched));
+        model.touch_document(file_path, touched);
+
+        assert!(!model.requires_reindexing(file_path, touched));
+        assert!(model.requires_reindexing(file_path, stamp));
+        assert_eq!(model.indexed_hash(file_path), Some(7));
+        assert_eq!(model.doc_freq("vertex"), 1);
+    }
+
+    #[test]
+    fn higher_term_frequency_ranks_first() {
+        let model = model(&[
+            ("/d/1", "shader buffer buffer buffer"),
+            ("/d/2", "shader buffer texture sampler"),
+            ("/d/3", "unrelated words only"),
+        ]);
+
+        for scorer in [Scorer::TfIdf, BM25] {
+            assert_eq!(search(&model, "buffer", scorer), ["/d/1", "/d/2"]);
         }
+    }
 
-        self.docs.insert(
-            file_path,
-            Doc {
-                tf,
-                count,
-                last_modified,
+    #[test]
+    fn span_of_adjacent_terms() {
+        // a phrase in order has a width of 0
+        assert_eq!(compute_span(&[&[3], &[4]], true), Some(0));
+        // one extra position between the terms
+        assert_eq!(compute_span(&[&[3], &[5]], true), Some(1));
+        assert_eq!(compute_span(&[&[3], &[5]], false), Some(1));
+        // out of order, but adjacent
+        assert_eq!(compute_span(&[&[4], &[3]], false), Some(0));
+    }
+
+    #[test]
+    fn span_picks_the_closest_occurrences() {
+        let a: &[usize] = &[1, 20, 40];
+        let b: &[usize] = &[10, 22, 60];
+        let c: &[usize] = &[21, 50];
+
+        assert_eq!(compute_span(&[a, b, c], false), Some(0));
+        assert_eq!(compute_span(&[a, c, b], true), Some(0));
+        assert_eq!(compute_span(&[a, b], true), Some(1));
+        assert_eq!(compute_span(&[a, &[]], true), None);
+        assert_eq!(compute_span(&[], true), None);
+    }
+
+    #[test]
+    fn phrases_match_terms_in_order_within_the_slop() {
+        let model = model(&[
+            ("/d/1", "bind the vertex buffer"),
+            ("/d/2", "vertex data in a buffer"),
+            ("/d/3", "buffer vertex"),
+        ]);
+
+        assert_eq!(search(&model, "\"vertex buffer\"", BM25), ["/d/1"]);
+        assert_eq!(search(&model, "\"vertex buffer\"~1", BM25), ["/d/1"]);
+        // swapped terms are 2 positions away from the phrase, the closest rank first
+        assert_eq!(
+            search(&model, "\"vertex buffer\"~3", BM25),
+            ["/d/1", "/d/3", "/d/2"]
+        );
+    }
+
+    #[test]
+    fn stopwords_leave_positions_contiguous() {
+        let mut model = Model::new(
+            Scorer::default(),
+            Boosts::default(),
+            LexerOptions {
+                stopwords: Some(Language::English),
+                ..LexerOptions::default()
             },
         );
+
+        for (path, text) in [
+            ("/d/1", "vertex of the buffer"),
+            ("/d/2", "vertex and index buffer"),
+        ] {
+            let parsed = ParsedDocument::new(text.to_string());
+            model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
+        }
+
+        assert_eq!(search(&model, "\"vertex buffer\"", BM25), ["/d/1"]);
+        assert_eq!(search(&model, "\"vertex in a buffer\"", BM25), ["/d/1"]);
+        assert!(search(&model, "the", BM25).is_empty());
+    }
+
+    #[test]
+    fn boosted_fields_rank_first() {
+        // tf-idf gives no weight to terms found in every document
+        let mut model = model(&[
+            ("/d/1", "shader shader texture sampler"),
+            ("/d/3", "unrelated words only"),
+        ]);
+
+        let mut parsed = ParsedDocument::new("texture sampler filtering".to_string());
+        parsed.add_metadata(ParsedDocument::TITLE, "Shader".to_string());
+        model.add_document(PathBuf::from("/d/2"), STAMP, 0, &parsed);
+
+        for scorer in [Scorer::TfIdf, BM25] {
+            assert_eq!(search(&model, "shader", scorer), ["/d/2", "/d/1"]);
+        }
+
+        model.boosts.set(Field::Title, 0.5);
+
+        for scorer in [Scorer::TfIdf, BM25] {
+            assert_eq!(search(&model, "shader", scorer), ["/d/1", "/d/2"]);
+        }
+
+        assert_eq!(search(&model, "title:shader", BM25), ["/d/2"]);
+        assert_eq!(search(&model, "body:shader", BM25), ["/d/1"]);
+    }
+
+    #[test]
+    fn paths_are_searched_as_a_field() {
+        let model = model(&[
+            ("/docs/glWaitSync.xhtml", "waits on a fence"),
+            ("/docs/fences.txt", "sync objects"),
+        ]);
+
+        assert_eq!(
+            search(&model, "file:sync", BM25),
+            ["/docs/glWaitSync.xhtml"]
+        );
+        assert_eq!(
+            search(&model, "glWaitSync", BM25),
+            ["/docs/glWaitSync.xhtml"]
+        );
+        assert_eq!(search(&model, "path:fences", BM25), ["/docs/fences.txt"]);
+        assert_eq!(
+            search(&model, "ext:xhtml", BM25),
+            ["/docs/glWaitSync.xhtml"]
+        );
+        assert_eq!(search(&model, "sync ext:txt", BM25), ["/docs/fences.txt"]);
+    }
+
+    #[test]
+    fn identifiers_are_searched_whole_and_by_parts() {
+        let lexer = LexerOptions {
+            code: true,
+            ..LexerOptions::default()
+        };
+        let mut model = Model::new(Scorer::default(), Boosts::default(), lexer);
+
+        for (path, text) in [
+            ("/d/1", "glWaitSync(GL_MAX_DRAW_BUFFERS)"),
+            ("/d/2", "draw the max of the buffers"),
+        ] {
+            let parsed = ParsedDocument::new(text.to_string());
+            model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
+        }
+
+        assert_eq!(search(&model, "glWaitSync", BM25), ["/d/1"]);
+        assert_eq!(search(&model, "GL_MAX_DRAW_BUFFERS", BM25), ["/d/1"]);
+        // the parts of an identifier follow each other
+        assert_eq!(search(&model, "max_draw", BM25), ["/d/1"]);
+        assert_eq!(search(&model, "wait", BM25), ["/d/1"]);
+    }
+
+    #[test]
+    fn wildcards_expand_to_the_terms_of_the_index() {
+        let model = model(&[
+            ("/d/1", "textures and shaders"),
+            ("/d/2", "texturing"),
+            ("/d/3", "text"),
+        ]);
+
+        assert_eq!(model.expand_wildcard("textur*"), ["textur"]);
+        assert_eq!(model.expand_wildcard("tex*"), ["text", "textur"]);
+        assert_eq!(model.expand_wildcard("tex?"), ["text"]);
+        assert!(model.expand_wildcard("vertex*").is_empty());
+
+        let mut paths = search(&model, "textur*", BM25);
+        paths.sort();
+        assert_eq!(paths, ["/d/1", "/d/2"]);
+        assert_eq!(search(&model, "*der*", BM25), ["/d/1"]);
+    }
+
+    #[test]
+    fn typos_fall_back_to_the_closest_terms() {
+        let model = model(&[
+            ("/d/1", "vertex buffer"),
+            ("/d/2", "vortex of water"),
+            ("/d/3", "index buffer"),
+        ]);
+
+        // `vetrex` is a transposition away from `vertex`, two edits from `vortex`
+        let fuzzy = |query: &str| {
+            let chars = query.chars().collect::<Vec<_>>();
+            let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+            model.search(&query, BM25)
+        };
+
+        let results = fuzzy("vetrex~2");
+        assert_eq!(results[0].0, PathBuf::from("/d/1"));
+        assert_eq!(results.len(), 2);
+        assert!(results[1].1 < results[0].1);
+
+        assert_eq!(search(&model, "vetrex~1", BM25), ["/d/1"]);
+        // missing terms of six characters and more tolerate two edits
+        assert_eq!(search(&model, "vetrex", BM25), ["/d/1", "/d/2"]);
+        // the terms of the index are not corrected
+        assert_eq!(search(&model, "vortex", BM25), ["/d/2"]);
+        // nor the excluded ones
+        assert_eq!(search(&model, "buffer -vetrex", BM25).len(), 2);
+    }
+
+    #[test]
+    fn wildcards_match_the_spellings_of_stemmed_terms() {
+        let model = model(&[
+            ("/d/1", "textures and shaders"),
+            ("/d/2", "texturing"),
+            ("/d/3", "text"),
+        ]);
+
+        assert_eq!(model.expand_wildcard("textur*"), ["textur"]);
+        // `textures` is stemmed into `textur` which `texture*` does not match
+        assert_eq!(model.expand_wildcard("texture*"), ["textur"]);
+        assert_eq!(model.expand_wildcard("shaders"), ["shader"]);
+        assert_eq!(model.expand_wildcard("tex?"), ["text"]);
+
+        let mut paths = search(&model, "textur*", BM25);
+        paths.sort();
+        assert_eq!(paths, ["/d/1", "/d/2"]);
+        assert_eq!(
+            search(&model, "texture*", BM25),
+            search(&model, "textur*", BM25)
+        );
+    }
+
+    #[test]
+    fn did_you_mean_uses_the_most_common_spelling() {
+        let model = model(&[
+            ("/d/1", "Shaders compile"),
+            ("/d/2", "shaders link"),
+            ("/d/3", "a shader"),
+        ]);
+
+        let did_you_mean = |query: &str| model.did_you_mean(&query.chars().collect::<Vec<_>>());
+
+        assert_eq!(did_you_mean("shaders"), None);
+        assert_eq!(
+            did_you_mean("shdaers lnk"),
+            Some("shaders link".to_string())
+        );
+        assert_eq!(
+            did_you_mean("title:compiel"),
+            Some("title:compile".to_string())
+        );
+        // too short to be corrected
+        assert_eq!(did_you_mean("ab"), None);
+    }
+
+    #[test]
+    fn words_are_not_corrected_into_themselves() {
+        let lexer = LexerOptions {
+            code: true,
+            ..LexerOptions::default()
+        };
+        let mut model = Model::new(Scorer::default(), Boosts::default(), lexer);
+        let parsed = ParsedDocument::new("glDrawArrays draws".to_string());
+        model.add_document(PathBuf::from("/d/1"), STAMP, 0, &parsed);
+
+        // the word is stemmed into `gldrawarray`, close to the identifier spelled the same
+        let chars = "gldrawarrays".chars().collect::<Vec<_>>();
+        assert_eq!(model.did_you_mean(&chars), None);
+    }
+
+    #[test]
+    fn pages_do_not_overlap() {
+        let model = model(&[
+            ("/d/e", "shader"),
+            ("/d/b", "shader"),
+            ("/d/d", "shader shader shader"),
+            ("/d/a", "shader"),
+            ("/d/c", "shader"),
+            ("/d/f", "vertex"),
+        ]);
+
+        let chars = "shader".chars().collect::<Vec<_>>();
+        let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+
+        let page = |offset, limit| {
+            let page = model.search_page(&query, BM25, offset, limit);
+            let paths = page
+                .results
+                .into_iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect::<Vec<_>>();
+
+            (page.total_hits, paths)
+        };
+
+        // documents ranked the same are ordered by path
+        assert_eq!(
+            page(0, 2),
+            (5, vec!["/d/d".to_string(), "/d/a".to_string()])
+        );
+        assert_eq!(
+            page(2, 2),
+            (5, vec!["/d/b".to_string(), "/d/c".to_string()])
+        );
+        assert_eq!(page(4, 2), (5, vec!["/d/e".to_string()]));
+        assert_eq!(page(6, 2), (5, vec![]));
+        assert_eq!(page(0, 0), (5, vec![]));
+
+        let all = page(0, usize::MAX).1;
+        assert_eq!(all, search(&model, "shader", BM25));
+        assert_eq!(all, ["/d/d", "/d/a", "/d/b", "/d/c", "/d/e"]);
     }
 }
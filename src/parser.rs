@@ -0,0 +1,981 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+use xml::common::{Position, TextPosition};
+use xml::reader::{EventReader, XmlEvent};
+use zip::ZipArchive;
+
+use crate::html::parse_html;
+use crate::sniff::{self, is_text_mime_type, sniff, Sniffed};
+
+/// How the text nodes of an XML document are put together
+#[derive(Debug, Clone, Copy)]
+enum TextLayout {
+    // every text node is a separate piece of text (XHTML)
+    Nodes,
+    // text nodes are runs of formatting that may split words, they are joined as is
+    // and only the elements with these local names separate the text (DOCX, ODT)
+    Breaks(&'static [&'static str]),
+}
+
+// paragraphs, tabs, line breaks and table cells of a WordprocessingML document
+const DOCX_BREAKS: &[&str] = &["p", "tab", "br", "cr", "tc"];
+// paragraphs, headings, spaces, tabs, line breaks and table cells of an OpenDocument text
+const ODT_BREAKS: &[&str] = &["p", "h", "s", "tab", "line-break", "table-cell"];
+
+// Extracts the text of an xml document
+// `source` only names the document in the error messages
+fn parse_xml<R: Read>(reader: R, source: &Path, layout: TextLayout) -> Result<String, ()> {
+    let er = EventReader::new(BufReader::new(reader));
+
+    let mut content = String::new();
+
+    for event in er.into_iter() {
+        let event = event.map_err(|err| {
+            let TextPosition { row, column } = err.position();
+            let msg = err.msg();
+            // prints the location where error was stated
+            eprintln!(
+                "{source}:{row}:{column}: ERROR: {msg}",
+                source = source.display()
+            );
+        })?;
+
+        match (event, layout) {
+            (XmlEvent::Characters(text), TextLayout::Nodes) => {
+                content.push_str(&text);
+                content.push(' ');
+            }
+            (XmlEvent::Characters(text), TextLayout::Breaks(_)) => content.push_str(&text),
+            (XmlEvent::StartElement { name, .. }, TextLayout::Breaks(breaks))
+            | (XmlEvent::EndElement { name }, TextLayout::Breaks(breaks))
+                if breaks.contains(&name.local_name.as_str()) =>
+            {
+                content.push(' ');
+            }
+            _ => {}
+        }
+    }
+
+    Ok(content)
+}
+
+// Parse an xml file and returns string containing only relevant characters
+// html pages are read by the more forgiving `parse_html_file`
+fn parse_xml_file(file_path: &Path) -> Result<String, ()> {
+    let file = File::open(file_path).map_err(|err| {
+        eprintln!("ERROR: could not open file {file_path:?}: {err}",);
+    })?;
+
+    parse_xml(file, file_path, TextLayout::Nodes)
+}
+
+// archives listing more entries are not read, a zip bomb can list millions of them
+const MAX_ARCHIVE_ENTRIES: usize = 10_000;
+// bytes decompressed from an archive at most, the sizes in its headers may lie
+const MAX_ARCHIVE_SIZE: u64 = 256 * 1024 * 1024;
+
+// open a zip container (docx, odt, epub)
+fn open_archive(file_path: &Path) -> Result<ZipArchive<File>, ()> {
+    let file = File::open(file_path).map_err(|err| {
+        eprintln!("ERROR: could not open file {file_path:?}: {err}");
+    })?;
+
+    let archive = ZipArchive::new(file).map_err(|err| {
+        eprintln!("ERROR: could not read archive {file_path:?}: {err}");
+    })?;
+
+    if archive.len() > MAX_ARCHIVE_ENTRIES {
+        eprintln!(
+            "ERROR: archive {file_path:?} has {} entries, more than {MAX_ARCHIVE_ENTRIES}",
+            archive.len()
+        );
+        return Err(());
+    }
+
+    Ok(archive)
+}
+
+// reads an entry of a zip container with `read`, which may only decompress
+// the `budget` bytes left, the bytes it reads are taken from the budget
+fn read_archive_entry<R: Read + Seek, T>(
+    archive: &mut ZipArchive<R>,
+    file_path: &Path,
+    name: &str,
+    budget: &mut u64,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, ()>,
+) -> Result<T, ()> {
+    let entry = archive.by_name(name).map_err(|err| {
+        eprintln!("ERROR: could not find {name} in {file_path:?}: {err}");
+    })?;
+
+    let too_large = || {
+        eprintln!(
+            "ERROR: {name} in {file_path:?} is too large, archives are read up to {MAX_ARCHIVE_SIZE} bytes"
+        );
+    };
+
+    if entry.size() > *budget {
+        too_large();
+        return Err(());
+    }
+
+    let mut limited = entry.take(*budget);
+    let result = read(&mut limited);
+
+    *budget = limited.limit();
+
+    // the entry was cut off, whatever `read` made of it
+    if *budget == 0 {
+        too_large();
+        return Err(());
+    }
+
+    result
+}
+
+// extracts the text of an xml document stored in a zip container
+fn parse_archived_xml<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    file_path: &Path,
+    name: &str,
+    layout: TextLayout,
+    budget: &mut u64,
+) -> Result<String, ()> {
+    read_archive_entry(archive, file_path, name, budget, |entry| {
+        parse_xml(entry, &file_path.join(name), layout)
+    })
+}
+
+// parse a docx document, the body is in `word/document.xml`
+fn parse_docx_file(file_path: &Path) -> Result<String, ()> {
+    let mut archive = open_archive(file_path)?;
+    let mut budget = MAX_ARCHIVE_SIZE;
+
+    parse_archived_xml(
+        &mut archive,
+        file_path,
+        "word/document.xml",
+        TextLayout::Breaks(DOCX_BREAKS),
+        &mut budget,
+    )
+}
+
+// parse an odt document, the body is in `content.xml`
+fn parse_odt_file(file_path: &Path) -> Result<String, ()> {
+    let mut archive = open_archive(file_path)?;
+    let mut budget = MAX_ARCHIVE_SIZE;
+
+    parse_archived_xml(
+        &mut archive,
+        file_path,
+        "content.xml",
+        TextLayout::Breaks(ODT_BREAKS),
+        &mut budget,
+    )
+}
+
+// parse an epub book, its chapters are xhtml documents listed in reading order
+// by the spine of the package document
+fn parse_epub_file(file_path: &Path) -> Result<String, ()> {
+    let mut archive = open_archive(file_path)?;
+    // shared by all the documents of the book
+    let mut budget = MAX_ARCHIVE_SIZE;
+
+    let package = epub_package_path(&mut archive, file_path, &mut budget)?;
+    let chapters = epub_chapters(&mut archive, file_path, &package, &mut budget)?;
+
+    let mut content = String::new();
+
+    for chapter in chapters {
+        let text = parse_archived_xml(
+            &mut archive,
+            file_path,
+            &chapter,
+            TextLayout::Nodes,
+            &mut budget,
+        );
+
+        // a chapter that cannot be parsed is left out, the error is already reported,
+        // but nothing more is read once the book is too large
+        match text {
+            Ok(text) => content.push_str(&text),
+            Err(()) if budget == 0 => break,
+            Err(()) => {}
+        }
+    }
+
+    Ok(content)
+}
+
+// reads an xml document of a zip container
+fn read_archived_xml<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    file_path: &Path,
+    name: &str,
+    budget: &mut u64,
+) -> Result<Vec<XmlEvent>, ()> {
+    read_archive_entry(archive, file_path, name, budget, |entry| {
+        EventReader::new(BufReader::new(entry))
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                eprintln!("ERROR: could not parse {name} in {file_path:?}: {err}");
+            })
+    })
+}
+
+// `META-INF/container.xml` points to the package document of the book
+fn epub_package_path<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    file_path: &Path,
+    budget: &mut u64,
+) -> Result<String, ()> {
+    read_archived_xml(archive, file_path, "META-INF/container.xml", budget)?
+        .into_iter()
+        .find_map(|event| match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } if name.local_name == "rootfile" => attributes
+                .into_iter()
+                .find(|a| a.name.local_name == "full-path")
+                .map(|a| a.value),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            eprintln!("ERROR: no package document is listed in {file_path:?}");
+        })
+}
+
+// paths of the chapters in the order of the spine, relative to the root of the archive
+fn epub_chapters<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    file_path: &Path,
+    package: &str,
+    budget: &mut u64,
+) -> Result<Vec<String>, ()> {
+    let mut manifest = Vec::new();
+    let mut spine = Vec::new();
+
+    for event in read_archived_xml(archive, file_path, package, budget)? {
+        let XmlEvent::StartElement {
+            name, attributes, ..
+        } = event
+        else {
+            continue;
+        };
+
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|a| a.name.local_name == key)
+                .map(|a| a.value.clone())
+        };
+
+        match name.local_name.as_str() {
+            "item" => {
+                if let (Some(id), Some(href)) = (attribute("id"), attribute("href")) {
+                    manifest.push((id, href));
+                }
+            }
+            "itemref" => spine.extend(attribute("idref")),
+            _ => {}
+        }
+    }
+
+    Ok(spine
+        .iter()
+        .filter_map(|idref| manifest.iter().find(|(id, _)| id == idref))
+        // hrefs of the manifest are relative to the package document
+        .map(|(_, href)| resolve_archive_path(package, href))
+        .collect())
+}
+
+// name of the zip entry `href` refers to from the entry `base`, zip entries always
+// use `/` and `..` is resolved since archives do not have real folders to follow
+fn resolve_archive_path(base: &str, href: &str) -> String {
+    let href = href.split(['#', '?']).next().unwrap_or_default();
+
+    let mut parts = base.split('/').collect::<Vec<_>>();
+    // the name of the base entry itself
+    parts.pop();
+
+    // an absolute href starts from the root of the archive
+    if href.starts_with('/') {
+        parts.clear();
+    }
+
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    parts.retain(|part| !part.is_empty());
+    parts.join("/")
+}
+
+// parse an html page, the bytes that are not UTF-8 are replaced rather than failing
+fn parse_html_file(file_path: &Path) -> Result<ParsedDocument, ()> {
+    let bytes = fs::read(file_path).map_err(|err| {
+        eprintln!("ERROR: could not open file {file_path:?}: {err}");
+    })?;
+
+    Ok(parse_html(&String::from_utf8_lossy(&bytes)))
+}
+
+// parse an md or txt file
+fn parse_txt_file(file_path: &Path) -> Result<String, ()> {
+    fs::read_to_string(file_path).map_err(|err| {
+        eprintln!("ERROR: could not open file {file_path:?}: {err}");
+    })
+}
+
+// parse s pdf document
+fn parse_pdf_file(file_path: &Path) -> Result<String, ()> {
+    use poppler::Document;
+
+    let mut content = Vec::new();
+
+    File::open(file_path)
+        .and_then(|mut file| file.read_to_end(&mut content))
+        .map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path:?}: {err}");
+        })?;
+
+    let pdf = Document::from_data(&content, None).map_err(|err| {
+        eprintln!("ERROR: could not read file {file_path:?}: {err}");
+    })?;
+
+    let mut result = String::new();
+
+    let n = pdf.n_pages();
+
+    for i in 0..n {
+        let page = pdf
+            .page(i)
+            .unwrap_or_else(|| panic!("{i} is within the bounds of the range of the page"));
+
+        if let Some(content) = page.text() {
+            result.push_str(&content);
+            result.push(' ');
+        }
+    }
+
+    Ok(result)
+}
+
+/// Text and metadata extracted from a document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedDocument {
+    pub text: String,
+    // e.g. `title`, a key may have several values such as the authors of a book
+    pub metadata: HashMap<String, Vec<String>>,
+}
+
+impl ParsedDocument {
+    // metadata keys indexed in their own fields, see `model::Field`
+    pub const TITLE: &'static str = "title";
+    pub const HEADINGS: &'static str = "headings";
+
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn add_metadata(&mut self, key: &str, value: String) {
+        self.metadata
+            .entry(key.to_string())
+            .or_default()
+            .push(value);
+    }
+}
+
+/// Extracts the text of the documents of some file formats
+///
+/// Parsers are shared by the indexing workers, so they have to be `Send + Sync`
+pub trait DocumentParser: Send + Sync {
+    /// Extensions of the files handled by the parser, lowercase and without the dot
+    fn extensions(&self) -> &[&str];
+
+    /// MIME types of the documents handled by the parser
+    fn mime_types(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Reads the file and extracts its text and metadata
+    /// Errors are reported by the parser
+    fn parse(&self, file_path: &Path) -> Result<ParsedDocument, ()>;
+}
+
+/// Parser of a format supported out of the box
+struct BuiltinParser {
+    extensions: &'static [&'static str],
+    mime_types: &'static [&'static str],
+    parse: fn(&Path) -> Result<ParsedDocument, ()>,
+}
+
+impl DocumentParser for BuiltinParser {
+    fn extensions(&self) -> &[&str] {
+        self.extensions
+    }
+
+    fn mime_types(&self) -> &[&str] {
+        self.mime_types
+    }
+
+    fn parse(&self, file_path: &Path) -> Result<ParsedDocument, ()> {
+        (self.parse)(file_path)
+    }
+}
+
+// files that are obviously not documents, skipped without being read unless a parser is
+// registered for them: images, audio and video, archives, executables, libraries and
+// object files, fonts, databases and disk images
+const NON_DOCUMENT_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "webp", "tif", "tiff", "psd", "mp3", "wav", "flac",
+    "ogg", "m4a", "mp4", "mkv", "avi", "mov", "webm", "zip", "gz", "tgz", "bz2", "xz", "zst", "7z",
+    "rar", "tar", "jar", "exe", "dll", "so", "dylib", "o", "a", "lib", "obj", "class", "pyc",
+    "wasm", "bin", "ttf", "otf", "woff", "woff2", "sqlite", "db", "iso", "dmg",
+];
+
+/// Parsers the indexer picks from to read a file
+///
+/// The default registry handles `xhtml`, `xml`, `html`, `htm`, `txt`, `md`, `pdf`, `docx`, `odt`
+/// and `epub` files, more formats are supported by registering parsers for them
+pub struct ParserRegistry {
+    parsers: Vec<Box<dyn DocumentParser>>,
+    // text files of unknown extensions are read as plain text, see `set_text_fallback`
+    text_fallback: bool,
+}
+
+impl ParserRegistry {
+    /// Registry without any parser, not even the built-in ones
+    pub fn empty() -> Self {
+        Self {
+            parsers: Vec::new(),
+            text_fallback: false,
+        }
+    }
+
+    /// Whether the text files whose extension no parser claims, such as source code,
+    /// logs or data files, are read by the parser of their type (plain text mostly)
+    /// They are skipped by default, the files without an extension are always read
+    pub fn set_text_fallback(&mut self, enabled: bool) {
+        self.text_fallback = enabled;
+    }
+
+    /// Adds a parser, it takes precedence over the parsers registered before it
+    /// for the extensions and MIME types they have in common
+    pub fn register(&mut self, parser: impl DocumentParser + 'static) {
+        self.parsers.push(Box::new(parser));
+    }
+
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn DocumentParser> {
+        let extension = extension.to_lowercase();
+
+        self.parsers
+            .iter()
+            .rev()
+            .find(|p| p.extensions().contains(&extension.as_str()))
+            .map(|p| p.as_ref())
+    }
+
+    pub fn for_mime_type(&self, mime_type: &str) -> Option<&dyn DocumentParser> {
+        self.parsers
+            .iter()
+            .rev()
+            .find(|p| p.mime_types().contains(&mime_type))
+            .map(|p| p.as_ref())
+    }
+
+    /// Parser for the file, picked by its extension
+    pub fn for_path(&self, file_path: &Path) -> Option<&dyn DocumentParser> {
+        self.for_extension(&file_path.extension()?.to_string_lossy())
+    }
+
+    /// Whether the file is obviously not a document, such as an image or an archive,
+    /// so that it can be skipped without being read
+    pub fn skips(&self, file_path: &Path) -> bool {
+        let Some(extension) = file_path.extension() else {
+            return false;
+        };
+
+        let extension = extension.to_string_lossy().to_lowercase();
+
+        NON_DOCUMENT_EXTENSIONS.contains(&extension.as_str())
+            && self.for_extension(&extension).is_none()
+    }
+
+    /// Parser for the file, picked by its extension and the first bytes of its content,
+    /// see [`sniff::read_head`]
+    ///
+    /// The content decides when the file has no extension, when its extension is
+    /// misleading (a PDF saved as `.txt`) and when it is binary, returns `None`
+    /// when no parser can read the file. Text files whose extension no parser
+    /// claims are only read when asked for, see [`ParserRegistry::set_text_fallback`]
+    pub fn detect(&self, file_path: &Path, head: &[u8]) -> Option<&dyn DocumentParser> {
+        let extension = file_path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let by_extension = extension.as_deref().and_then(|e| self.for_extension(e));
+
+        match sniff(head) {
+            Sniffed::Format(mime_type) => {
+                // the parser of an extension that agrees with the content is trusted
+                // even if it does not list the MIME type
+                let agrees = extension
+                    .as_deref()
+                    .is_some_and(|e| sniff::extensions(mime_type).contains(&e));
+
+                by_extension
+                    .filter(|p| agrees || p.mime_types().contains(&mime_type))
+                    .or_else(|| self.for_mime_type(mime_type))
+            }
+            // text formats are told apart by their extension
+            Sniffed::Text(mime_type) => by_extension.or_else(|| {
+                if extension.is_none() || self.text_fallback {
+                    self.for_mime_type(mime_type)
+                } else {
+                    None
+                }
+            }),
+            // only the parsers of binary formats can make sense of it
+            Sniffed::Binary => {
+                by_extension.filter(|p| !p.mime_types().iter().any(|m| is_text_mime_type(m)))
+            }
+        }
+    }
+
+    /// Parses the file with the parser registered for its type
+    pub fn parse(&self, file_path: &Path) -> Result<ParsedDocument, ()> {
+        let head = sniff::read_head(file_path).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path:?}: {err}");
+        })?;
+
+        let parser = self.detect(file_path, &head).ok_or_else(|| {
+            eprintln!("ERROR: unsupported file type {file_path:?}");
+        })?;
+
+        parser.parse(file_path)
+    }
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+
+        registry.register(BuiltinParser {
+            extensions: &["xml"],
+            mime_types: &[sniff::XML, "text/xml"],
+            parse: |file_path| parse_xml_file(file_path).map(ParsedDocument::new),
+        });
+        registry.register(BuiltinParser {
+            extensions: &["html", "htm", "xhtml"],
+            mime_types: &[sniff::HTML, sniff::XHTML],
+            parse: parse_html_file,
+        });
+        registry.register(BuiltinParser {
+            extensions: &["txt", "md"],
+            mime_types: &[sniff::TEXT, "text/markdown"],
+            parse: |file_path| parse_txt_file(file_path).map(ParsedDocument::new),
+        });
+        registry.register(BuiltinParser {
+            extensions: &["pdf"],
+            mime_types: &[sniff::PDF],
+            parse: |file_path| parse_pdf_file(file_path).map(ParsedDocument::new),
+        });
+        registry.register(BuiltinParser {
+            extensions: &["docx"],
+            mime_types: &[sniff::DOCX],
+            parse: |file_path| parse_docx_file(file_path).map(ParsedDocument::new),
+        });
+        registry.register(BuiltinParser {
+            extensions: &["odt"],
+            mime_types: &[sniff::ODT],
+            parse: |file_path| parse_odt_file(file_path).map(ParsedDocument::new),
+        });
+        registry.register(BuiltinParser {
+            extensions: &["epub"],
+            mime_types: &[sniff::EPUB],
+            parse: |file_path| parse_epub_file(file_path).map(ParsedDocument::new),
+        });
+
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+    use std::process;
+
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// Parser that only tells which one it is through its extensions
+    struct FakeParser {
+        extensions: &'static [&'static str],
+        mime_types: &'static [&'static str],
+    }
+
+    impl DocumentParser for FakeParser {
+        fn extensions(&self) -> &[&str] {
+            self.extensions
+        }
+
+        fn mime_types(&self) -> &[&str] {
+            self.mime_types
+        }
+
+        fn parse(&self, _: &Path) -> Result<ParsedDocument, ()> {
+            Err(())
+        }
+    }
+
+    /// Extensions of the parser detected for a file, `None` when it is not read
+    fn detected<'a>(parsers: &'a ParserRegistry, name: &str, head: &[u8]) -> Option<&'a [&'a str]> {
+        parsers
+            .detect(Path::new(name), head)
+            .map(|p| p.extensions())
+    }
+
+    /// Zip archive holding `entries` in memory
+    fn archive(entries: &[(&str, &[u8])]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, content) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    /// Reads an entry to the end, as the parsers do
+    fn read(
+        archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+        name: &str,
+        budget: &mut u64,
+    ) -> Result<Vec<u8>, ()> {
+        read_archive_entry(archive, Path::new("test.zip"), name, budget, |entry| {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).map_err(|_| ())?;
+            Ok(content)
+        })
+    }
+
+    #[test]
+    fn hrefs_are_resolved_against_the_package_document() {
+        let package = "OEBPS/content.opf";
+
+        assert_eq!(
+            resolve_archive_path(package, "ch1.xhtml"),
+            "OEBPS/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_archive_path(package, "./Text/ch1.xhtml"),
+            "OEBPS/Text/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_archive_path("content.opf", "ch1.xhtml"),
+            "ch1.xhtml"
+        );
+        // fragments and queries do not name entries
+        assert_eq!(
+            resolve_archive_path(package, "ch1.xhtml#intro"),
+            "OEBPS/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_archive_path(package, "ch1.xhtml?v=2"),
+            "OEBPS/ch1.xhtml"
+        );
+    }
+
+    #[test]
+    fn parent_segments_are_resolved() {
+        assert_eq!(
+            resolve_archive_path("OEBPS/Package/content.opf", "../Text/ch1.xhtml"),
+            "OEBPS/Text/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_archive_path("OEBPS/content.opf", "Text/../ch1.xhtml"),
+            "OEBPS/ch1.xhtml"
+        );
+        // the root of the archive has no parent to escape to
+        assert_eq!(
+            resolve_archive_path("OEBPS/content.opf", "../../../ch1.xhtml"),
+            "ch1.xhtml"
+        );
+    }
+
+    #[test]
+    fn absolute_hrefs_start_from_the_root_of_the_archive() {
+        assert_eq!(
+            resolve_archive_path("OEBPS/content.opf", "/Text/ch1.xhtml"),
+            "Text/ch1.xhtml"
+        );
+        assert_eq!(
+            resolve_archive_path("OEBPS/content.opf", "/OEBPS//ch1.xhtml"),
+            "OEBPS/ch1.xhtml"
+        );
+    }
+
+    #[test]
+    fn reads_are_taken_from_the_budget() {
+        let mut archive = archive(&[("a.xml", b"0123456789"), ("b.xml", b"0123456789")]);
+        let mut budget = 25;
+
+        assert_eq!(
+            read(&mut archive, "a.xml", &mut budget),
+            Ok(b"0123456789".to_vec())
+        );
+        assert_eq!(budget, 15);
+
+        assert!(read(&mut archive, "b.xml", &mut budget).is_ok());
+        assert_eq!(budget, 5);
+
+        // what is left is smaller than the next entry
+        assert_eq!(read(&mut archive, "a.xml", &mut budget), Err(()));
+    }
+
+    #[test]
+    fn entries_filling_the_budget_exactly_are_rejected() {
+        let mut archive = archive(&[("a.xml", b"0123456789")]);
+        let mut budget = 10;
+
+        // nothing tells a complete entry from one cut off at the limit
+        assert_eq!(read(&mut archive, "a.xml", &mut budget), Err(()));
+        assert_eq!(budget, 0);
+    }
+
+    #[test]
+    fn missing_entries_are_errors() {
+        let mut archive = archive(&[("a.xml", b"<a/>")]);
+        let mut budget = MAX_ARCHIVE_SIZE;
+
+        assert_eq!(read(&mut archive, "b.xml", &mut budget), Err(()));
+        assert_eq!(budget, MAX_ARCHIVE_SIZE);
+    }
+
+    #[test]
+    fn archives_with_too_many_entries_are_not_opened() {
+        let file_path: PathBuf =
+            std::env::temp_dir().join(format!("parser-{}-entries.zip", process::id()));
+
+        let write = |entries: usize| {
+            let mut writer = ZipWriter::new(File::create(&file_path).unwrap());
+            for i in 0..entries {
+                writer
+                    .start_file(format!("{i}.xml"), FileOptions::default())
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+        };
+
+        write(MAX_ARCHIVE_ENTRIES);
+        assert!(open_archive(&file_path).is_ok());
+
+        write(MAX_ARCHIVE_ENTRIES + 1);
+        assert!(open_archive(&file_path).is_err());
+
+        fs::remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn epub_chapters_follow_the_spine() {
+        let container = br#"<?xml version="1.0"?>
+<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/Package/content.opf"/></rootfiles>
+</container>"#;
+        let package = br#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf">
+  <manifest>
+    <item id="one" href="../Text/one.xhtml"/>
+    <item id="two" href="/OEBPS/Text/two.xhtml#start"/>
+  </manifest>
+  <spine><itemref idref="two"/><itemref idref="one"/></spine>
+</package>"#;
+
+        let mut archive = archive(&[
+            ("META-INF/container.xml", container),
+            ("OEBPS/Package/content.opf", package),
+        ]);
+        let mut budget = MAX_ARCHIVE_SIZE;
+        let file_path = Path::new("book.epub");
+
+        let package = epub_package_path(&mut archive, file_path, &mut budget).unwrap();
+        assert_eq!(package, "OEBPS/Package/content.opf");
+
+        assert_eq!(
+            epub_chapters(&mut archive, file_path, &package, &mut budget),
+            Ok(vec![
+                "OEBPS/Text/two.xhtml".to_string(),
+                "OEBPS/Text/one.xhtml".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn registered_parsers_take_precedence() {
+        let mut parsers = ParserRegistry::default();
+        parsers.register(FakeParser {
+            extensions: &["md", "wiki"],
+            mime_types: &["text/markdown"],
+        });
+
+        let extensions = |extension| parsers.for_extension(extension).map(|p| p.extensions());
+
+        assert_eq!(extensions("md"), Some(&["md", "wiki"][..]));
+        assert_eq!(extensions("WIKI"), Some(&["md", "wiki"][..]));
+        // the extensions it does not claim keep their parser
+        assert_eq!(extensions("txt"), Some(&["txt", "md"][..]));
+        assert_eq!(extensions("rst"), None);
+
+        let mime_type = |mime_type| parsers.for_mime_type(mime_type).map(|p| p.extensions());
+
+        assert_eq!(mime_type("text/markdown"), Some(&["md", "wiki"][..]));
+        assert_eq!(mime_type(sniff::TEXT), Some(&["txt", "md"][..]));
+    }
+
+    #[test]
+    fn the_last_of_overlapping_parsers_wins() {
+        let mut parsers = ParserRegistry::empty();
+        parsers.register(FakeParser {
+            extensions: &["log"],
+            mime_types: &[sniff::TEXT],
+        });
+        parsers.register(FakeParser {
+            extensions: &["log", "out"],
+            mime_types: &[sniff::TEXT],
+        });
+
+        assert_eq!(
+            detected(&parsers, "build.log", b"ok"),
+            Some(&["log", "out"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "LICENSE", b"MIT"),
+            Some(&["log", "out"][..])
+        );
+    }
+
+    #[test]
+    fn binary_formats_win_over_misleading_extensions() {
+        let parsers = ParserRegistry::default();
+
+        assert_eq!(
+            detected(&parsers, "report.txt", b"%PDF-1.7"),
+            Some(&["pdf"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "report", b"%PDF-1.7"),
+            Some(&["pdf"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "report.pdf", b"%PDF-1.7"),
+            Some(&["pdf"][..])
+        );
+        // binary content is not read by the parsers of text
+        assert_eq!(
+            detected(&parsers, "image.txt", b"\x89PNG\r\n\x1a\n\0\0"),
+            None
+        );
+    }
+
+    #[test]
+    fn parsers_agreeing_with_the_content_are_kept() {
+        let mut parsers = ParserRegistry::default();
+        // lists no MIME type, the content only has to match its extension
+        parsers.register(FakeParser {
+            extensions: &["pdf"],
+            mime_types: &[],
+        });
+
+        assert_eq!(
+            detected(&parsers, "paper.pdf", b"%PDF-1.7"),
+            Some(&["pdf"][..])
+        );
+        assert!(parsers
+            .detect(Path::new("paper.pdf"), b"%PDF-1.7")
+            .is_some_and(|p| p.mime_types().is_empty()));
+        // the content does not match its extension, the built-in parser reads it
+        assert!(parsers
+            .detect(Path::new("paper.txt"), b"%PDF-1.7")
+            .is_some_and(|p| p.mime_types() == [sniff::PDF]));
+    }
+
+    #[test]
+    fn text_is_told_apart_by_the_extension() {
+        let parsers = ParserRegistry::default();
+
+        assert_eq!(
+            detected(&parsers, "notes.md", b"# Notes"),
+            Some(&["txt", "md"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "page.html", b"plain text"),
+            Some(&["html", "htm", "xhtml"][..])
+        );
+        // without an extension the content decides
+        assert_eq!(
+            detected(&parsers, "README", b"Read me"),
+            Some(&["txt", "md"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "index", b"<!DOCTYPE html>"),
+            Some(&["html", "htm", "xhtml"][..])
+        );
+        assert_eq!(
+            detected(&parsers, "feed", b"<?xml version=\"1.0\"?><feed/>"),
+            Some(&["xml"][..])
+        );
+    }
+
+    #[test]
+    fn unknown_text_extensions_need_the_text_fallback() {
+        let mut parsers = ParserRegistry::default();
+
+        assert_eq!(detected(&parsers, "main.rs", b"fn main() {}"), None);
+
+        parsers.set_text_fallback(true);
+
+        assert_eq!(
+            detected(&parsers, "main.rs", b"fn main() {}"),
+            Some(&["txt", "md"][..])
+        );
+        // binary files are still left out
+        assert_eq!(
+            detected(&parsers, "main.o", b"\x7fELF\x02\x01\x01\0\0"),
+            None
+        );
+    }
+
+    #[test]
+    fn non_documents_are_skipped_unless_a_parser_claims_them() {
+        let mut parsers = ParserRegistry::default();
+
+        assert!(parsers.skips(Path::new("photo.PNG")));
+        assert!(parsers.skips(Path::new("archive.tar")));
+        assert!(!parsers.skips(Path::new("notes.txt")));
+        assert!(!parsers.skips(Path::new("README")));
+        assert!(!parsers.skips(Path::new("main.rs")));
+
+        parsers.register(FakeParser {
+            extensions: &["png"],
+            mime_types: &["image/png"],
+        });
+
+        assert!(!parsers.skips(Path::new("photo.PNG")));
+    }
+}
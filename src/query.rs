@@ -0,0 +1,964 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::lexer::{Lexer, LexerOptions};
+use crate::model::Field;
+
+// fuzzy terms tolerate at most that many edits, more would match most of the vocabulary
+const MAX_FUZZY_DISTANCE: usize = 2;
+
+/// Parsed search query
+///
+/// Grammar, from the loosest to the tightest binding:
+///
+/// ```text
+/// clauses := or+                      clauses written next to each other
+/// or      := and ("OR" and)*
+/// and     := unary ("AND" unary)*
+/// unary   := "NOT" unary | "-" unary | "+" unary | primary
+/// primary := filter | field ":" primary | "(" clauses ")" | "\"phrase\"" ["~" N] | wildcard | word
+/// filter  := ("path:" | "ext:") (word | "\"text\"")
+/// wildcard := word with `*` (any characters) or `?` (one character), `shad*`, `gl*buffer*`
+///             a trailing `?` ends a question and is ignored, `texture?` is the word `texture`
+/// fuzzy   := word "~" [N]             `vetrex~1`, up to 2 edits, 2 when N is left out
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// A stemmed term, matches documents containing it anywhere
+    Term(String),
+    /// Lowercase pattern matched against the terms of the index, which are stemmed,
+    /// `*` matches any characters and `?` a single one (`shad*`)
+    Wildcard(String),
+    /// A stemmed term and the terms of the index within `distance` edits of it,
+    /// the more edits the lower the score (`vetrex~1`)
+    Fuzzy { term: String, distance: usize },
+    /// Stemmed terms that have to appear in order, with at most `slop`
+    /// extra positions between them (`"foo bar"~5`)
+    Phrase { terms: Vec<String>, slop: usize },
+    /// Every sub query has to match (`a AND b`)
+    And(Vec<Query>),
+    /// At least one sub query has to match (`a OR b`)
+    Or(Vec<Query>),
+    /// The sub query must not match (`NOT a`, `-a`)
+    Not(Box<Query>),
+    /// The sub query has to match for the enclosing clauses to match (`+a`)
+    Required(Box<Query>),
+    /// The sub query only matches in one field of the documents (`title:shader`)
+    Field { field: Field, query: Box<Query> },
+    /// Only keeps the documents whose path passes the filter, without scoring them
+    /// Next to other clauses, it restricts their matches (`shader ext:pdf`)
+    Filter(Filter),
+    /// Clauses written next to each other (`+a b -c`)
+    /// Required clauses have to match, excluded clauses must not match
+    /// and the remaining ones are optional but add to the score
+    Clauses(Vec<Query>),
+}
+
+impl Query {
+    /// Terms the matching documents are looked up by,
+    /// terms that are only excluded are left out
+    pub fn terms(&self) -> Vec<&String> {
+        let mut terms = Vec::new();
+        self.collect_terms(&mut terms);
+
+        terms
+    }
+
+    fn collect_terms<'a>(&'a self, terms: &mut Vec<&'a String>) {
+        match self {
+            Query::Term(term) | Query::Fuzzy { term, .. } => terms.push(term),
+            Query::Phrase { terms: phrase, .. } => terms.extend(phrase),
+            Query::And(queries) | Query::Or(queries) | Query::Clauses(queries) => {
+                for query in queries {
+                    query.collect_terms(terms);
+                }
+            }
+            Query::Required(query) | Query::Field { query, .. } => query.collect_terms(terms),
+            Query::Not(_) | Query::Filter(_) | Query::Wildcard(_) => {}
+        }
+    }
+}
+
+/// Whether the `term` matches the wildcard `pattern` as a whole, see [`Query::Wildcard`]
+pub fn matches_wildcard(pattern: &str, term: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let term = term.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the term character it was tried at
+    let mut star = None;
+
+    while t < term.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(x) if *x == '?' || *x == term[t] => {
+                p += 1;
+                t += 1;
+            }
+            // the last `*` swallows one more character
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Condition on the path of the documents
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The path contains the text, ignoring case (`path:src/model`)
+    /// Unlike the `file:` field, the text is not split into terms
+    Path(String),
+    /// The file has that extension, ignoring case (`ext:pdf`)
+    Extension(String),
+}
+
+impl Filter {
+    fn from_name(name: &str, value: &str) -> Option<Self> {
+        match name {
+            "path" => Some(Filter::Path(value.to_lowercase())),
+            "ext" => Some(Filter::Extension(
+                value.trim_start_matches('.').to_lowercase(),
+            )),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, file_path: &Path) -> bool {
+        match self {
+            Filter::Path(text) => file_path
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(text.as_str()),
+            Filter::Extension(extension) => file_path
+                .extension()
+                .is_some_and(|e| e.to_string_lossy().to_lowercase() == *extension),
+        }
+    }
+}
+
+/// Error in the syntax of a query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryError {
+    pub message: String,
+    // offset of the offending character in the query
+    pub position: usize,
+}
+
+impl QueryError {
+    fn new(message: impl Into<String>, position: usize) -> Self {
+        Self {
+            message: message.into(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Phrase { text: String, slop: usize },
+    Open,
+    Close,
+    Plus,
+    Minus,
+    And,
+    Or,
+    Not,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+// Tokenizer should contain the query text, doesn't modify
+#[derive(Debug)]
+struct Tokenizer<'a> {
+    content: &'a [char],
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(content: &'a [char]) -> Self {
+        Self {
+            content,
+            position: 0,
+        }
+    }
+
+    fn chop(&mut self, n: usize) -> &'a [char] {
+        let n = n.min(self.content.len());
+        let chopped = &self.content[0..n];
+        self.content = &self.content[n..];
+        self.position += n;
+
+        chopped
+    }
+
+    fn chop_while<P>(&mut self, mut predicate: P) -> &'a [char]
+    where
+        P: FnMut(&char) -> bool,
+    {
+        let mut n = 0;
+        while n < self.content.len() && predicate(&self.content[n]) {
+            n += 1;
+        }
+
+        self.chop(n)
+    }
+
+    fn is_word_char(x: &char) -> bool {
+        !x.is_whitespace() && !matches!(x, '(' | ')' | '"')
+    }
+
+    /// Parses `"..."` and an optional `~N` slop right after the closing quote
+    fn phrase(&mut self, position: usize) -> Result<TokenKind, QueryError> {
+        // opening quote
+        self.chop(1);
+
+        let text = self.chop_while(|x| *x != '"').iter().collect();
+
+        if self.content.is_empty() {
+            return Err(QueryError::new("unterminated phrase", position));
+        }
+
+        // closing quote
+        self.chop(1);
+
+        let mut slop = 0;
+
+        if self.content.first() == Some(&'~') {
+            let slop_position = self.position;
+            self.chop(1);
+
+            let digits = self.chop_while(|x| x.is_ascii_digit());
+
+            slop = digits
+                .iter()
+                .collect::<String>()
+                .parse()
+                .map_err(|_| QueryError::new("expected a number after `~`", slop_position))?;
+        }
+
+        Ok(TokenKind::Phrase { text, slop })
+    }
+
+    fn next_token(&mut self) -> Option<Result<Token, QueryError>> {
+        self.chop_while(|x| x.is_whitespace());
+
+        let position = self.position;
+
+        let kind = match self.content.first()? {
+            '(' => {
+                self.chop(1);
+                TokenKind::Open
+            }
+            ')' => {
+                self.chop(1);
+                TokenKind::Close
+            }
+            '"' => match self.phrase(position) {
+                Ok(kind) => kind,
+                Err(err) => return Some(Err(err)),
+            },
+            // `+` and `-` are only operators right in front of what they apply to
+            '+' | '-' if self.content.get(1).is_some_and(Self::is_word_char_or_group) => {
+                let prefix = self.chop(1)[0];
+
+                if prefix == '+' {
+                    TokenKind::Plus
+                } else {
+                    TokenKind::Minus
+                }
+            }
+            _ => {
+                let word = self
+                    .chop_while(Self::is_word_char)
+                    .iter()
+                    .collect::<String>();
+
+                match word.as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => TokenKind::Word(word),
+                }
+            }
+        };
+
+        Some(Ok(Token { kind, position }))
+    }
+
+    fn is_word_char_or_group(x: &char) -> bool {
+        Self::is_word_char(x) || matches!(x, '(' | '"')
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+/// Recursive descent parser over the tokens of a query
+#[derive(Debug)]
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    current: usize,
+    // length of the query, reported as the position of errors at its end
+    end: usize,
+    // the words are tokenized like the documents of the index
+    lexer: &'a LexerOptions,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.current).map(|t| &t.kind)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.current)
+            .map_or(self.end, |t| t.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.current).cloned();
+        self.current += 1;
+
+        token
+    }
+
+    fn clauses(&mut self) -> Result<Query, QueryError> {
+        let mut clauses = Vec::new();
+
+        while !matches!(self.peek(), None | Some(TokenKind::Close)) {
+            clauses.push(self.or()?);
+        }
+
+        match clauses.len() {
+            1 if !matches!(clauses[0], Query::Required(_) | Query::Not(_)) => Ok(clauses.remove(0)),
+            _ => Ok(Query::Clauses(clauses)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut queries = vec![self.and()?];
+
+        while self.peek() == Some(&TokenKind::Or) {
+            self.advance();
+            queries.push(self.and()?);
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+
+        Ok(Query::Or(queries))
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut queries = vec![self.unary()?];
+
+        while self.peek() == Some(&TokenKind::And) {
+            self.advance();
+            queries.push(self.unary()?);
+        }
+
+        if queries.len() == 1 {
+            return Ok(queries.remove(0));
+        }
+
+        Ok(Query::And(queries))
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        match self.peek() {
+            Some(TokenKind::Not) | Some(TokenKind::Minus) => {
+                self.advance();
+                Ok(Query::Not(Box::new(self.unary()?)))
+            }
+            Some(TokenKind::Plus) => {
+                self.advance();
+                Ok(Query::Required(Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Query, QueryError> {
+        let position = self.position();
+
+        let Some(token) = self.advance() else {
+            return Err(QueryError::new("expected a term", position));
+        };
+
+        match token.kind {
+            TokenKind::Open => {
+                if self.peek() == Some(&TokenKind::Close) {
+                    return Err(QueryError::new("empty group", position));
+                }
+
+                let query = self.clauses()?;
+
+                if self.advance().map(|t| t.kind) != Some(TokenKind::Close) {
+                    return Err(QueryError::new("missing closing `)`", position));
+                }
+
+                Ok(query)
+            }
+            TokenKind::Word(word) => {
+                if let Some((name, value)) = word.split_once(':') {
+                    if let Some(filter) = self.filter(name, value, position)? {
+                        return Ok(Query::Filter(filter));
+                    }
+                }
+
+                // words with an unknown prefix are searched as they are (`std::fs`)
+                let field = word
+                    .split_once(':')
+                    .and_then(|(name, rest)| Some((Field::from_name(name)?, rest)));
+
+                let Some((field, rest)) = field else {
+                    return self.word_query(&word, position);
+                };
+
+                // `title:"..."` and `title:(...)`
+                let query = if rest.is_empty() {
+                    self.primary()?
+                } else {
+                    self.word_query(rest, position)?
+                };
+
+                Ok(Query::Field {
+                    field,
+                    query: Box::new(query),
+                })
+            }
+            TokenKind::Phrase { text, slop } => Ok(self.terms_query(&text, slop)),
+            TokenKind::Close => Err(QueryError::new("unexpected `)`", position)),
+            TokenKind::And | TokenKind::Or => Err(QueryError::new(
+                "expected a term before the operator",
+                position,
+            )),
+            TokenKind::Plus | TokenKind::Minus | TokenKind::Not => {
+                Err(QueryError::new("expected a term", position))
+            }
+        }
+    }
+
+    /// Parses the value of a `path:` or `ext:` filter, returns `None` for other prefixes
+    /// The value is the rest of the word, or the quoted text right after the colon
+    fn filter(
+        &mut self,
+        name: &str,
+        value: &str,
+        position: usize,
+    ) -> Result<Option<Filter>, QueryError> {
+        if Filter::from_name(name, "").is_none() {
+            return Ok(None);
+        }
+
+        let value = if !value.is_empty() {
+            value.to_string()
+        } else if let Some(TokenKind::Phrase { text, .. }) = self.peek() {
+            let text = text.clone();
+            self.advance();
+            text
+        } else {
+            return Err(QueryError::new(
+                format!("expected a value after `{name}:`"),
+                position,
+            ));
+        };
+
+        Ok(Filter::from_name(name, &value))
+    }
+
+    /// Words with `*` or `?` are wildcards, words ending with `~N` are fuzzy
+    /// and the other ones go through the lexer
+    fn word_query(&self, word: &str, position: usize) -> Result<Query, QueryError> {
+        let word = word.trim_end_matches('?');
+
+        if let Some((word, distance)) = split_fuzzy(word) {
+            if word.contains(['*', '?']) {
+                return Err(QueryError::new(
+                    "a wildcard cannot be fuzzy",
+                    position + word.chars().count(),
+                ));
+            }
+
+            return self.fuzzy_query(word, distance, position);
+        }
+
+        if !word.contains(['*', '?']) {
+            return Ok(self.terms_query(word, 0));
+        }
+
+        if word.chars().all(|x| matches!(x, '*' | '?')) {
+            return Err(QueryError::new(
+                "expected a character besides the wildcards",
+                position,
+            ));
+        }
+
+        Ok(Query::Wildcard(word.to_lowercase()))
+    }
+
+    /// A word lexed into a single term becomes fuzzy, the others are searched as they are
+    fn fuzzy_query(
+        &self,
+        word: &str,
+        distance: &str,
+        position: usize,
+    ) -> Result<Query, QueryError> {
+        let distance = match distance {
+            "" => MAX_FUZZY_DISTANCE,
+            distance => distance
+                .parse::<usize>()
+                .ok()
+                .filter(|d| *d <= MAX_FUZZY_DISTANCE)
+                .ok_or_else(|| {
+                    QueryError::new(
+                        format!("expected a distance up to {MAX_FUZZY_DISTANCE} after `~`"),
+                        position + word.chars().count(),
+                    )
+                })?,
+        };
+
+        match self.terms_query(word, 0) {
+            Query::Term(term) => Ok(Query::Fuzzy { term, distance }),
+            query => Ok(query),
+        }
+    }
+
+    /// Runs the text of a word or a phrase through the lexer,
+    /// several terms become a phrase so that `vertex-attrib` keeps its order
+    fn terms_query(&self, text: &str, slop: usize) -> Query {
+        let chars = text.chars().collect::<Vec<_>>();
+        let tokens = Lexer::with_options(&chars, self.lexer)
+            .tokens()
+            .collect::<Vec<_>>();
+
+        // a single identifier followed by its parts, see `LexerOptions::code`
+        // `max_draw` also finds `GL_MAX_DRAW_BUFFERS` through its parts
+        if tokens.len() > 2 && tokens.iter().all(|t| t.start == tokens[0].start) {
+            let mut terms = tokens.into_iter().map(|t| t.term);
+
+            let identifier = Query::Term(terms.next().unwrap_or_default());
+            let parts = Query::Phrase {
+                terms: terms.collect(),
+                slop,
+            };
+
+            return Query::Or(vec![identifier, parts]);
+        }
+
+        let mut terms = tokens.into_iter().map(|t| t.term).collect::<Vec<_>>();
+
+        match terms.len() {
+            1 => Query::Term(terms.remove(0)),
+            // a word made only of ignored characters matches nothing
+            0 => Query::Clauses(Vec::new()),
+            _ => Query::Phrase { terms, slop },
+        }
+    }
+}
+
+/// Splits a fuzzy word at its trailing `~`, only followed by the distance if any,
+/// `shader~1` gives `shader` and `1`. The `~` of other words is only punctuation
+fn split_fuzzy(word: &str) -> Option<(&str, &str)> {
+    word.rsplit_once('~')
+        .filter(|(_, distance)| distance.chars().all(|x| x.is_ascii_digit()))
+}
+
+/// Replaces the words of the query that `correct_term` corrects, leaving the syntax
+/// and the other words as they are written. Returns `None` when nothing is corrected
+///
+/// `correct_term` is given the terms the words are lexed into
+pub fn correct<F>(query: &[char], lexer: &LexerOptions, correct_term: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let tokens = Tokenizer::new(query).collect::<Result<Vec<_>, _>>().ok()?;
+
+    // character offsets of the corrected words in the query, and their correction
+    let mut corrections = Vec::new();
+
+    for token in tokens {
+        let (text, start) = match token.kind {
+            TokenKind::Word(word) => {
+                let (text, start) = match word.split_once(':') {
+                    Some((name, _)) if Filter::from_name(name, "").is_some() => continue,
+                    Some((name, rest)) if Field::from_name(name).is_some() => {
+                        (rest.to_string(), token.position + name.chars().count() + 1)
+                    }
+                    _ => (word, token.position),
+                };
+
+                if text.trim_end_matches('?').contains(['*', '?']) {
+                    continue;
+                }
+
+                // the distance of fuzzy words is left as it is
+                match split_fuzzy(&text) {
+                    Some((text, _)) => (text.to_string(), start),
+                    None => (text, start),
+                }
+            }
+            TokenKind::Phrase { text, .. } => (text, token.position + 1),
+            _ => continue,
+        };
+
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut previous_start = None;
+
+        for t in Lexer::with_options(&chars, lexer).tokens() {
+            // the parts of an identifier are corrected along with it
+            if previous_start == Some(t.start) {
+                continue;
+            }
+
+            previous_start = Some(t.start);
+
+            let Some(correction) = correct_term(&t.term) else {
+                continue;
+            };
+
+            // the closest term can be spelled as the word, `gldrawarrays` is stemmed
+            // into `gldrawarray` which is missing but close to the identifier
+            let written = chars[t.start..t.end].iter().collect::<String>();
+
+            if correction != written.to_lowercase() {
+                corrections.push((start + t.start, start + t.end, correction));
+            }
+        }
+    }
+
+    if corrections.is_empty() {
+        return None;
+    }
+
+    let mut corrected = String::new();
+    let mut last = 0;
+
+    for (start, end, correction) in corrections {
+        corrected.extend(&query[last..start]);
+        corrected.push_str(&correction);
+        last = end;
+    }
+
+    corrected.extend(&query[last..]);
+
+    Some(corrected)
+}
+
+/// Parses the query text into a [`Query`], its words are tokenized with the `lexer` options
+/// of the index. Returns `None` when the query does not contain anything to search for
+pub fn parse(query: &[char], lexer: &LexerOptions) -> Result<Option<Query>, QueryError> {
+    let tokens = Tokenizer::new(query).collect::<Result<Vec<_>, _>>()?;
+
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        current: 0,
+        end: query.len(),
+        lexer,
+    };
+
+    let query = parser.clauses()?;
+
+    if parser.peek().is_some() {
+        return Err(QueryError::new("unexpected `)`", parser.position()));
+    }
+
+    Ok(Some(query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(query: &str) -> Result<Option<Query>, QueryError> {
+        parse(&query.chars().collect::<Vec<_>>(), &LexerOptions::default())
+    }
+
+    fn term(term: &str) -> Query {
+        Query::Term(term.to_string())
+    }
+
+    fn error_position(query: &str) -> usize {
+        parse_str(query).unwrap_err().position
+    }
+
+    #[test]
+    fn empty_query_has_nothing_to_search() {
+        assert_eq!(parse_str("").unwrap(), None);
+        assert_eq!(parse_str("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn words_are_stemmed_clauses() {
+        assert_eq!(parse_str("shaders").unwrap(), Some(term("shader")));
+        assert_eq!(
+            parse_str("vertex shaders").unwrap(),
+            Some(Query::Clauses(vec![term("vertex"), term("shader")]))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse_str("a OR b AND c").unwrap(),
+            Some(Query::Or(vec![
+                term("a"),
+                Query::And(vec![term("b"), term("c")])
+            ]))
+        );
+    }
+
+    #[test]
+    fn groups_and_unary_operators() {
+        assert_eq!(
+            parse_str("+(vertex OR buffer) -texture NOT shader").unwrap(),
+            Some(Query::Clauses(vec![
+                Query::Required(Box::new(Query::Or(vec![term("vertex"), term("buffer")]))),
+                Query::Not(Box::new(term("textur"))),
+                Query::Not(Box::new(term("shader"))),
+            ]))
+        );
+
+        // a single required clause is kept in its clauses
+        assert_eq!(
+            parse_str("+vertex").unwrap(),
+            Some(Query::Clauses(vec![Query::Required(Box::new(term(
+                "vertex"
+            )))]))
+        );
+    }
+
+    #[test]
+    fn dash_inside_a_word_is_not_an_operator() {
+        assert_eq!(
+            parse_str("vertex-attrib").unwrap(),
+            Some(Query::Phrase {
+                terms: vec!["vertex".to_string(), "attrib".to_string()],
+                slop: 0,
+            })
+        );
+        assert!(parse_str("a - b").unwrap().is_some());
+    }
+
+    #[test]
+    fn phrases_with_slop() {
+        assert_eq!(
+            parse_str("\"vertex buffer\"~3").unwrap(),
+            Some(Query::Phrase {
+                terms: vec!["vertex".to_string(), "buffer".to_string()],
+                slop: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            parse_str("title:shaders").unwrap(),
+            Some(Query::Field {
+                field: Field::Title,
+                query: Box::new(term("shader")),
+            })
+        );
+        assert_eq!(
+            parse_str("file:(src OR lib)").unwrap(),
+            Some(Query::Field {
+                field: Field::Path,
+                query: Box::new(Query::Or(vec![term("src"), term("lib")])),
+            })
+        );
+        // unknown prefixes are searched as words
+        assert_eq!(
+            parse_str("std::fs").unwrap(),
+            Some(Query::Phrase {
+                terms: ["std", "fs"].map(String::from).to_vec(),
+                slop: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn identifiers_also_match_their_parts() {
+        let chars = "GL_MAX_DRAW".chars().collect::<Vec<_>>();
+        let code = LexerOptions {
+            code: true,
+            ..LexerOptions::default()
+        };
+
+        assert_eq!(
+            parse(&chars, &code).unwrap(),
+            Some(Query::Or(vec![
+                term("gl_max_draw"),
+                Query::Phrase {
+                    terms: ["gl", "max", "draw"].map(String::from).to_vec(),
+                    slop: 0,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            parse_str("path:Src/Model").unwrap(),
+            Some(Query::Filter(Filter::Path("src/model".to_string())))
+        );
+        assert_eq!(
+            parse_str("ext:\".PDF\"").unwrap(),
+            Some(Query::Filter(Filter::Extension("pdf".to_string())))
+        );
+        assert_eq!(error_position("a path:"), 2);
+
+        assert!(Filter::Path("src/mod".to_string()).matches(Path::new("/a/SRC/Model.rs")));
+        assert!(Filter::Extension("pdf".to_string()).matches(Path::new("/a/b.PDF")));
+        assert!(!Filter::Extension("pdf".to_string()).matches(Path::new("/a/pdf")));
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        assert_eq!(error_position("\"vertex buffer"), 0);
+        assert_eq!(error_position("a \"b\"~x"), 5);
+        assert_eq!(error_position("a (b"), 2);
+        assert_eq!(error_position("a ()"), 2);
+        assert_eq!(error_position("a )"), 2);
+        assert_eq!(error_position("a AND"), 5);
+        assert_eq!(error_position("OR a"), 0);
+        assert_eq!(error_position("a NOT"), 5);
+    }
+
+    #[test]
+    fn error_messages() {
+        let err = parse_str("a (b").unwrap_err();
+        assert_eq!(err.to_string(), "missing closing `)` at position 2");
+    }
+
+    #[test]
+    fn wildcards_match_whole_terms() {
+        assert!(matches_wildcard("shad*", "shader"));
+        assert!(matches_wildcard("shad*", "shad"));
+        assert!(matches_wildcard("*der", "shader"));
+        assert!(matches_wildcard("gl*buffer*", "glbindbufferrange"));
+        assert!(matches_wildcard("sh?der", "shader"));
+        assert!(matches_wildcard("*", ""));
+        assert!(matches_wildcard("a*b*c", "aXbYbZc"));
+        // `*` backtracks past earlier partial matches
+        assert!(matches_wildcard("*ab", "aab"));
+
+        assert!(!matches_wildcard("shad*", "sha"));
+        assert!(!matches_wildcard("sh?der", "shder"));
+        assert!(!matches_wildcard("shad", "shader"));
+        assert!(!matches_wildcard("*der", "derive"));
+        assert!(!matches_wildcard("é?", "é"));
+    }
+
+    #[test]
+    fn wildcard_words() {
+        assert_eq!(
+            parse_str("Shad*").unwrap(),
+            Some(Query::Wildcard("shad*".to_string()))
+        );
+        assert_eq!(
+            parse_str("sh?der").unwrap(),
+            Some(Query::Wildcard("sh?der".to_string()))
+        );
+        // a question ends with a `?` which is not a wildcard
+        assert_eq!(parse_str("textures?").unwrap(), Some(term("textur")));
+        assert_eq!(
+            parse_str("shad*?").unwrap(),
+            Some(Query::Wildcard("shad*".to_string()))
+        );
+        assert_eq!(error_position("a **"), 2);
+    }
+
+    #[test]
+    fn fuzzy_words() {
+        let fuzzy = |term: &str, distance| {
+            Some(Query::Fuzzy {
+                term: term.to_string(),
+                distance,
+            })
+        };
+
+        assert_eq!(parse_str("vetrex~1").unwrap(), fuzzy("vetrex", 1));
+        assert_eq!(parse_str("shaders~").unwrap(), fuzzy("shader", 2));
+        // a `~` inside a word is punctuation
+        assert_eq!(
+            parse_str("foo~bar").unwrap(),
+            Some(Query::Phrase {
+                terms: vec!["foo".to_string(), "bar".to_string()],
+                slop: 0,
+            })
+        );
+
+        assert_eq!(error_position("a vetrex~3"), 8);
+        assert_eq!(error_position("shad*~1"), 5);
+        assert_eq!(
+            parse_str("shad*~1").unwrap_err().message,
+            "a wildcard cannot be fuzzy"
+        );
+    }
+
+    #[test]
+    fn corrections_replace_only_the_misspelled_words() {
+        let correct = |query: &str| {
+            let chars = query.chars().collect::<Vec<_>>();
+            correct(&chars, &LexerOptions::default(), |term| match term {
+                "shdaer" => Some("shader".to_string()),
+                "vertx" => Some("vertex".to_string()),
+                _ => None,
+            })
+        };
+
+        assert_eq!(correct("vertex shader"), None);
+        assert_eq!(correct("Shdaer"), Some("shader".to_string()));
+        assert_eq!(
+            correct("(été OR vertx)  -shdaer"),
+            Some("(été OR vertex)  -shader".to_string())
+        );
+        assert_eq!(
+            correct("title:shdaer \"bind vertx\"~2"),
+            Some("title:shader \"bind vertex\"~2".to_string())
+        );
+        // the distance of fuzzy words is kept
+        assert_eq!(correct("shdaer~1"), Some("shader~1".to_string()));
+        // filters and wildcards are left as they are
+        assert_eq!(correct("path:shdaer vertx*"), None);
+        assert_eq!(correct("shdaer?"), Some("shader?".to_string()));
+        // an invalid query is not corrected
+        assert_eq!(correct("\"vertx"), None);
+    }
+}
@@ -0,0 +1,903 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::index_file::sync_dir;
+use crate::model::Field;
+
+// Layout of a segment file, integers are little endian:
+//
+// | header: magic "LSRS", version, counts, offsets of the tables, crc32 of the data   |
+// |         and of every table, crc32 of the header                                    |
+// | data: the paths, terms, postings, spellings and word pairs the tables point to     |
+// | documents: fixed size records sorted by path, a document is known by its rank      |
+// | terms: fixed size records sorted by term                                           |
+// | spellings: fixed size records of the spellings other than the term, sorted         |
+// | deleted: fixed size records of the paths removed from the older segments, sorted   |
+//
+// Opening a segment only checks the header and the tables, the data holding the
+// postings is checked by `SegmentReader::verify` before merging. Postings of a term,
+// for every document containing it:
+// | document: u32 | fields holding the term, one bit each: u8 | for each of those |
+// | fields: number of positions: u32 | positions: u32 each                          |
+//
+// Spellings and word pairs of a term are lists of (length: u32, UTF-8 word, count: u32).
+//
+// Nothing is compressed, the tables are searched in place in the mapped file
+// and only the postings of the searched terms are ever decoded.
+const MAGIC: &[u8; 4] = b"LSRS";
+const HEADER_LEN: usize = 4 + 5 * 4 + 4 * 8 + 4 * 8 + 5 * 4 + 4;
+// where the checksums of the data and of the tables start in the header
+const CHECKSUMS_OFFSET: usize = HEADER_LEN - 6 * 4;
+
+/// Version of the segment layout, segments written with another one cannot be read
+pub const SEGMENT_VERSION: u32 = 2;
+
+// path: offset u64, length u32 | last modified u64 | size u64 | hash u32 | counts 4 * u32
+const DOC_RECORD_LEN: usize = 12 + 8 + 8 + 4 + 4 * 4;
+// term: offset u64, length u32 | document frequency u32 | postings, spellings
+// and word pairs: offset u64, length u64 each
+const TERM_RECORD_LEN: usize = 12 + 4 + 3 * 16;
+// spelling: offset u64, length u32 | rank of the term: u32
+const SPELLING_RECORD_LEN: usize = 12 + 4;
+// path: offset u64, length u32
+const DELETED_RECORD_LEN: usize = 12;
+
+/// Statistics of a document kept in the segment, its terms are in the postings
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DocInfo {
+    // Unix time in nanoseconds
+    pub last_modified: u64,
+    pub size: u64,
+    pub hash: u32,
+    // number of terms of every field, in the order of `Field::ALL`
+    pub counts: [usize; 4],
+}
+
+/// Term of a segment as found in its table
+#[derive(Debug, Clone, Copy)]
+pub struct TermEntry<'a> {
+    pub term: &'a str,
+    // documents of the segment containing it, including the ones replaced since
+    pub df: usize,
+    postings: &'a [u8],
+    spellings: &'a [u8],
+    pairs: &'a [u8],
+}
+
+impl<'a> TermEntry<'a> {
+    pub fn postings(&self) -> PostingsIter<'a> {
+        PostingsIter {
+            bytes: self.postings,
+        }
+    }
+
+    /// Spellings of the term other than the term itself, with the number of documents using them
+    pub fn spellings(&self) -> CountsIter<'a> {
+        CountsIter {
+            bytes: self.spellings,
+        }
+    }
+
+    /// Terms following this one in the text, with the number of times they do
+    pub fn pairs(&self) -> CountsIter<'a> {
+        CountsIter { bytes: self.pairs }
+    }
+}
+
+/// Occurrences of a term in a document of a segment
+#[derive(Debug, Clone, Copy)]
+pub struct RawPosting<'a> {
+    // rank of the document in the segment
+    pub doc: u32,
+    // positions of the term in every field as little endian u32, see `positions`
+    pub fields: [Option<&'a [u8]>; 4],
+}
+
+/// Iterates over the encoded postings of a term
+#[derive(Debug, Clone)]
+pub struct PostingsIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for PostingsIter<'a> {
+    type Item = RawPosting<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let doc = read_u32(self.bytes, 0)?;
+        let mask = *self.bytes.get(4)?;
+        let mut offset = 5;
+        let mut fields = [None; 4];
+
+        for (i, slot) in fields.iter_mut().enumerate() {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+
+            let n = read_u32(self.bytes, offset)? as usize;
+            let end = n.checked_mul(4)?.checked_add(offset + 4)?;
+
+            *slot = Some(self.bytes.get(offset + 4..end)?);
+            offset = end;
+        }
+
+        self.bytes = &self.bytes[offset..];
+
+        Some(RawPosting { doc, fields })
+    }
+}
+
+/// Iterates over a list of words and their counts
+#[derive(Debug, Clone)]
+pub struct CountsIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for CountsIter<'a> {
+    type Item = (&'a str, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = read_u32(self.bytes, 0)? as usize;
+        let word = std::str::from_utf8(self.bytes.get(4..4 + len)?).ok()?;
+        let count = read_u32(self.bytes, 4 + len)? as usize;
+
+        self.bytes = &self.bytes[8 + len..];
+
+        Some((word, count))
+    }
+}
+
+/// Decodes positions stored as little endian u32
+pub fn positions(bytes: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+}
+
+/// Appends the postings of a term in a document, the positions of every field in order
+pub fn push_posting(buffer: &mut Vec<u8>, doc: u32, fields: [Option<&[usize]>; 4]) {
+    push_encoded_posting(
+        buffer,
+        doc,
+        fields.map(|positions| {
+            positions.map(|positions| {
+                positions
+                    .iter()
+                    .flat_map(|p| (*p as u32).to_le_bytes())
+                    .collect::<Vec<_>>()
+            })
+        }),
+    )
+}
+
+/// Appends the postings of a term read from another segment under a new document rank
+pub fn push_raw_posting(buffer: &mut Vec<u8>, doc: u32, fields: [Option<&[u8]>; 4]) {
+    push_encoded_posting(buffer, doc, fields)
+}
+
+fn push_encoded_posting<B: AsRef<[u8]>>(buffer: &mut Vec<u8>, doc: u32, fields: [Option<B>; 4]) {
+    let mask = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, positions)| positions.is_some())
+        .fold(0u8, |mask, (i, _)| mask | 1 << i);
+
+    buffer.extend_from_slice(&doc.to_le_bytes());
+    buffer.push(mask);
+
+    for positions in fields.iter().flatten() {
+        let positions = positions.as_ref();
+        buffer.extend_from_slice(&(positions.len() as u32 / 4).to_le_bytes());
+        buffer.extend_from_slice(positions);
+    }
+}
+
+/// Key of a path in the tables, paths are compared as their UTF-8 bytes
+pub fn path_key(path: &Path) -> std::borrow::Cow<'_, str> {
+    path.to_string_lossy()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let b = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(b.try_into().unwrap()))
+}
+
+/// Segment file mapped in memory
+/// Opening it checks the header and the checksums of the tables, without reading
+/// the data, the tables are only read when they are searched
+#[derive(Debug)]
+pub struct SegmentReader {
+    mmap: Mmap,
+    doc_count: u32,
+    term_count: u32,
+    spelling_count: u32,
+    deleted_count: u32,
+    // terms of every field in all the documents
+    field_counts: [usize; 4],
+    docs_offset: usize,
+    terms_offset: usize,
+    spellings_offset: usize,
+    deleted_offset: usize,
+    // checksum of the paths, terms, postings, spellings and word pairs, see `verify`
+    data_checksum: u32,
+}
+
+impl SegmentReader {
+    pub fn open(segment_path: &Path) -> Result<Self, ()> {
+        let file = File::open(segment_path).map_err(|err| {
+            eprintln!("ERROR: could not open segment {segment_path:?}: {err}");
+        })?;
+
+        // SAFETY: segments are never modified once written, new versions of
+        // the documents always go to new files that replace them atomically
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|err| {
+            eprintln!("ERROR: could not map segment {segment_path:?}: {err}");
+        })?;
+
+        let bytes = &mmap[..];
+
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            eprintln!("ERROR: {segment_path:?} is not a segment file");
+            return Err(());
+        }
+
+        let version = read_u32(bytes, 4).unwrap_or(0);
+
+        if version != SEGMENT_VERSION {
+            eprintln!(
+                "ERROR: {segment_path:?} has segment version {version}, expected {SEGMENT_VERSION}"
+            );
+            return Err(());
+        }
+
+        let header_checksum = read_u32(bytes, HEADER_LEN - 4).unwrap_or(0);
+
+        if crc32fast::hash(&bytes[..HEADER_LEN - 4]) != header_checksum {
+            eprintln!("ERROR: {segment_path:?} is corrupted");
+            return Err(());
+        }
+
+        let checksum_at = |i: usize| read_u32(bytes, CHECKSUMS_OFFSET + i * 4).unwrap_or(0);
+        let table_checksums = [
+            checksum_at(1),
+            checksum_at(2),
+            checksum_at(3),
+            checksum_at(4),
+        ];
+
+        let u32_at = |i: usize| read_u32(bytes, 8 + i * 4).unwrap_or(0);
+        let u64_at = |i: usize| read_u64(bytes, 24 + i * 8).unwrap_or(0) as usize;
+
+        let reader = Self {
+            doc_count: u32_at(0),
+            term_count: u32_at(1),
+            spelling_count: u32_at(2),
+            deleted_count: u32_at(3),
+            field_counts: [u64_at(0), u64_at(1), u64_at(2), u64_at(3)],
+            docs_offset: u64_at(4),
+            terms_offset: u64_at(5),
+            spellings_offset: u64_at(6),
+            deleted_offset: u64_at(7),
+            data_checksum: checksum_at(0),
+            mmap,
+        };
+
+        let tables = [
+            (reader.docs_offset, reader.doc_count, DOC_RECORD_LEN),
+            (reader.terms_offset, reader.term_count, TERM_RECORD_LEN),
+            (
+                reader.spellings_offset,
+                reader.spelling_count,
+                SPELLING_RECORD_LEN,
+            ),
+            (
+                reader.deleted_offset,
+                reader.deleted_count,
+                DELETED_RECORD_LEN,
+            ),
+        ];
+
+        // the data goes from the header to the first table
+        let intact = reader.docs_offset >= HEADER_LEN
+            && tables
+                .iter()
+                .zip(table_checksums)
+                .all(|((offset, count, len), checksum)| {
+                    let table = (*count as usize)
+                        .checked_mul(*len)
+                        .and_then(|size| size.checked_add(*offset))
+                        .and_then(|end| reader.mmap.get(*offset..end));
+
+                    table.is_some_and(|table| crc32fast::hash(table) == checksum)
+                });
+
+        if !intact {
+            eprintln!("ERROR: {segment_path:?} is corrupted");
+            return Err(());
+        }
+
+        Ok(reader)
+    }
+
+    /// Checks the data of the segment, which `open` leaves out to stay fast
+    /// Postings decoded from a corrupted segment would be wrong without failing
+    pub fn verify(&self) -> Result<(), ()> {
+        if crc32fast::hash(&self.mmap[HEADER_LEN..self.docs_offset]) != self.data_checksum {
+            eprintln!("ERROR: the data of a segment is corrupted");
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    pub fn doc_count(&self) -> u32 {
+        self.doc_count
+    }
+
+    pub fn term_count(&self) -> u32 {
+        self.term_count
+    }
+
+    /// Number of terms of `field` in all the documents of the segment
+    pub fn field_count(&self, field: Field) -> usize {
+        self.field_counts[field as usize]
+    }
+
+    /// Bytes of the data part of the file, empty when the record points outside of it
+    fn data(&self, offset: u64, len: u64) -> &[u8] {
+        let start = offset as usize;
+        let end = start.saturating_add(len as usize);
+
+        self.mmap.get(start..end).unwrap_or_default()
+    }
+
+    fn string(&self, record: &[u8]) -> &str {
+        let offset = read_u64(record, 0).unwrap_or(0);
+        let len = read_u32(record, 8).unwrap_or(0);
+
+        std::str::from_utf8(self.data(offset, len as u64)).unwrap_or_default()
+    }
+
+    fn record(&self, offset: usize, len: usize, i: u32) -> &[u8] {
+        let start = offset + i as usize * len;
+        &self.mmap[start..start + len]
+    }
+
+    /// Path of the document of rank `id`
+    pub fn doc_path(&self, id: u32) -> &Path {
+        Path::new(self.string(self.record(self.docs_offset, DOC_RECORD_LEN, id)))
+    }
+
+    pub fn doc_info(&self, id: u32) -> DocInfo {
+        let record = self.record(self.docs_offset, DOC_RECORD_LEN, id);
+
+        let u32_at = |offset: usize| read_u32(record, offset).unwrap_or(0);
+
+        DocInfo {
+            last_modified: read_u64(record, 12).unwrap_or(0),
+            size: read_u64(record, 20).unwrap_or(0),
+            hash: u32_at(28),
+            counts: [u32_at(32), u32_at(36), u32_at(40), u32_at(44)].map(|n| n as usize),
+        }
+    }
+
+    /// Rank of the document of that path
+    pub fn find_doc(&self, file_path: &Path) -> Option<u32> {
+        let key = path_key(file_path);
+        let rank = self.partition_point(self.doc_count, |id| {
+            self.string(self.record(self.docs_offset, DOC_RECORD_LEN, id)) < key.as_ref()
+        });
+
+        (rank < self.doc_count && self.doc_path(rank) == Path::new(key.as_ref())).then_some(rank)
+    }
+
+    /// Paths of the documents removed from the older segments
+    pub fn deleted(&self) -> impl Iterator<Item = &Path> {
+        (0..self.deleted_count).map(|i| {
+            Path::new(self.string(self.record(self.deleted_offset, DELETED_RECORD_LEN, i)))
+        })
+    }
+
+    /// Term of rank `i` in the sorted table
+    pub fn term(&self, i: u32) -> TermEntry<'_> {
+        let record = self.record(self.terms_offset, TERM_RECORD_LEN, i);
+        let part = |offset: usize| {
+            self.data(
+                read_u64(record, offset).unwrap_or(0),
+                read_u64(record, offset + 8).unwrap_or(0),
+            )
+        };
+
+        TermEntry {
+            term: self.string(record),
+            df: read_u32(record, 12).unwrap_or(0) as usize,
+            postings: part(16),
+            spellings: part(32),
+            pairs: part(48),
+        }
+    }
+
+    /// Rank of the first term that is not smaller than `term`
+    pub fn seek_term(&self, term: &str) -> u32 {
+        self.partition_point(self.term_count, |i| {
+            self.string(self.record(self.terms_offset, TERM_RECORD_LEN, i)) < term
+        })
+    }
+
+    pub fn find_term(&self, term: &str) -> Option<TermEntry<'_>> {
+        let i = self.seek_term(term);
+
+        (i < self.term_count)
+            .then(|| self.term(i))
+            .filter(|entry| entry.term == term)
+    }
+
+    /// Terms from the first one that is not smaller than `term`, in order
+    pub fn terms_from(&self, term: &str) -> impl Iterator<Item = TermEntry<'_>> {
+        (self.seek_term(term)..self.term_count).map(|i| self.term(i))
+    }
+
+    /// Spellings from the first one that is not smaller than `spelling`, in order,
+    /// along with the term they spell
+    pub fn spellings_from(&self, spelling: &str) -> impl Iterator<Item = (&str, TermEntry<'_>)> {
+        let spelling_at = |i: u32| {
+            let record = self.record(self.spellings_offset, SPELLING_RECORD_LEN, i);
+            (self.string(record), read_u32(record, 12).unwrap_or(0))
+        };
+
+        let start = self.partition_point(self.spelling_count, |i| spelling_at(i).0 < spelling);
+
+        (start..self.spelling_count).filter_map(move |i| {
+            let (spelling, term) = spelling_at(i);
+            (term < self.term_count).then(|| (spelling, self.term(term)))
+        })
+    }
+
+    /// First rank in `0..count` for which `is_before` is false, the ranks before it all being true
+    fn partition_point(&self, count: u32, is_before: impl Fn(u32) -> bool) -> u32 {
+        let (mut low, mut high) = (0, count);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            if is_before(middle) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        low
+    }
+}
+
+/// Writes a segment file, the data is written as it comes and the tables at the end
+///
+/// Documents have to be added sorted by path (see [`path_key`]), their rank is the id used
+/// in the postings. Terms and deleted paths have to be added sorted as well.
+#[derive(Debug)]
+pub struct SegmentWriter {
+    segment_path: PathBuf,
+    temp_path: PathBuf,
+    file: BufWriter<File>,
+    // length of the file so far
+    offset: u64,
+    // checksum of the data, everything written before the tables
+    data: crc32fast::Hasher,
+    docs: Vec<u8>,
+    doc_count: u32,
+    terms: Vec<u8>,
+    term_count: u32,
+    // spelling, where it is written and the rank of its term
+    spellings: Vec<(String, u64, u32)>,
+    deleted: Vec<u8>,
+    deleted_count: u32,
+    field_counts: [usize; 4],
+}
+
+impl SegmentWriter {
+    /// Creates the file next to its destination, it is renamed once finished
+    pub fn create(segment_path: &Path) -> Result<Self, ()> {
+        let temp_path = segment_path.with_extension("tmp");
+
+        let mut file = File::create(&temp_path).map_err(|err| {
+            eprintln!("ERROR: could not create file {temp_path:?}: {err}");
+        })?;
+
+        // the header is written once the tables are known
+        file.write_all(&[0; HEADER_LEN]).map_err(|err| {
+            eprintln!("ERROR: could not write file {temp_path:?}: {err}");
+        })?;
+
+        Ok(Self {
+            segment_path: segment_path.to_path_buf(),
+            temp_path,
+            file: BufWriter::new(file),
+            offset: HEADER_LEN as u64,
+            data: crc32fast::Hasher::new(),
+            docs: Vec::new(),
+            doc_count: 0,
+            terms: Vec::new(),
+            term_count: 0,
+            spellings: Vec::new(),
+            deleted: Vec::new(),
+            deleted_count: 0,
+            field_counts: [0; 4],
+        })
+    }
+
+    pub fn doc_count(&self) -> u32 {
+        self.doc_count
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<u64, ()> {
+        let offset = self.offset;
+
+        self.file.write_all(bytes).map_err(|err| {
+            eprintln!("ERROR: could not write file {:?}: {err}", self.temp_path);
+        })?;
+
+        self.data.update(bytes);
+        self.offset += bytes.len() as u64;
+
+        Ok(offset)
+    }
+
+    /// Writes a string in the data, returns the reference to it used by the records
+    fn write_string(&mut self, s: &str) -> Result<[u8; 12], ()> {
+        let offset = self.write(s.as_bytes())?;
+
+        let mut reference = [0; 12];
+        reference[..8].copy_from_slice(&offset.to_le_bytes());
+        reference[8..].copy_from_slice(&(s.len() as u32).to_le_bytes());
+
+        Ok(reference)
+    }
+
+    /// Adds the next document, returns its rank
+    pub fn add_doc(&mut self, file_path: &Path, info: &DocInfo) -> Result<u32, ()> {
+        let reference = self.write_string(&path_key(file_path))?;
+
+        self.docs.extend_from_slice(&reference);
+        self.docs
+            .extend_from_slice(&info.last_modified.to_le_bytes());
+        self.docs.extend_from_slice(&info.size.to_le_bytes());
+        self.docs.extend_from_slice(&info.hash.to_le_bytes());
+
+        for (total, count) in self.field_counts.iter_mut().zip(info.counts) {
+            self.docs.extend_from_slice(&(count as u32).to_le_bytes());
+            *total += count;
+        }
+
+        self.doc_count += 1;
+
+        Ok(self.doc_count - 1)
+    }
+
+    /// Adds the path of a document removed from the older segments
+    pub fn add_deleted(&mut self, file_path: &Path) -> Result<(), ()> {
+        let reference = self.write_string(&path_key(file_path))?;
+
+        self.deleted.extend_from_slice(&reference);
+        self.deleted_count += 1;
+
+        Ok(())
+    }
+
+    /// Adds the next term
+    ///
+    /// # Arguments
+    ///
+    /// * `df` number of documents in the postings
+    /// * `postings` encoded with [`push_posting`] or [`push_raw_posting`]
+    /// * `spellings` spellings of the term other than itself and their number of documents
+    /// * `pairs` terms following it in the text and the number of times they do
+    pub fn add_term(
+        &mut self,
+        term: &str,
+        df: usize,
+        postings: &[u8],
+        spellings: &[(&str, usize)],
+        pairs: &[(&str, usize)],
+    ) -> Result<(), ()> {
+        let reference = self.write_string(term)?;
+        let postings_offset = self.write(postings)?;
+
+        let spellings_offset = self.offset;
+        let mut spelling_offsets = Vec::with_capacity(spellings.len());
+
+        for (spelling, count) in spellings {
+            self.write(&(spelling.len() as u32).to_le_bytes())?;
+            spelling_offsets.push(self.write(spelling.as_bytes())?);
+            self.write(&(*count as u32).to_le_bytes())?;
+        }
+
+        let pairs_offset = self.offset;
+
+        for (next, count) in pairs {
+            self.write(&(next.len() as u32).to_le_bytes())?;
+            self.write(next.as_bytes())?;
+            self.write(&(*count as u32).to_le_bytes())?;
+        }
+
+        for ((spelling, _), offset) in spellings.iter().zip(spelling_offsets) {
+            self.spellings
+                .push((spelling.to_string(), offset, self.term_count));
+        }
+
+        self.terms.extend_from_slice(&reference);
+        self.terms.extend_from_slice(&(df as u32).to_le_bytes());
+
+        for (offset, end) in [
+            (postings_offset, spellings_offset),
+            (spellings_offset, pairs_offset),
+            (pairs_offset, self.offset),
+        ] {
+            self.terms.extend_from_slice(&offset.to_le_bytes());
+            self.terms.extend_from_slice(&(end - offset).to_le_bytes());
+        }
+
+        self.term_count += 1;
+
+        Ok(())
+    }
+
+    /// Writes the tables and the header, then moves the file to its destination
+    /// once it is on the disk
+    /// Returns the size of the file
+    pub fn finish(mut self) -> Result<u64, ()> {
+        let data_checksum = self.data.clone().finalize();
+
+        let docs = std::mem::take(&mut self.docs);
+        let docs_offset = self.write(&docs)?;
+        let terms = std::mem::take(&mut self.terms);
+        let terms_offset = self.write(&terms)?;
+
+        let mut spellings = std::mem::take(&mut self.spellings);
+        spellings.sort_by(|(a, _, x), (b, _, y)| a.cmp(b).then(x.cmp(y)));
+
+        let mut table = Vec::with_capacity(spellings.len() * SPELLING_RECORD_LEN);
+
+        for (spelling, offset, term) in &spellings {
+            table.extend_from_slice(&offset.to_le_bytes());
+            table.extend_from_slice(&(spelling.len() as u32).to_le_bytes());
+            table.extend_from_slice(&term.to_le_bytes());
+        }
+
+        let spellings_offset = self.write(&table)?;
+        let deleted = std::mem::take(&mut self.deleted);
+        let deleted_offset = self.write(&deleted)?;
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&SEGMENT_VERSION.to_le_bytes());
+
+        for count in [
+            self.doc_count,
+            self.term_count,
+            spellings.len() as u32,
+            self.deleted_count,
+        ] {
+            header.extend_from_slice(&count.to_le_bytes());
+        }
+
+        for value in self.field_counts.map(|n| n as u64).into_iter().chain([
+            docs_offset,
+            terms_offset,
+            spellings_offset,
+            deleted_offset,
+        ]) {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+
+        header.extend_from_slice(&data_checksum.to_le_bytes());
+
+        for table in [&docs, &terms, &table, &deleted] {
+            header.extend_from_slice(&crc32fast::hash(table).to_le_bytes());
+        }
+
+        header.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+
+        let temp_path = self.temp_path.clone();
+
+        self.file
+            .flush()
+            .and_then(|()| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(&header))
+            .and_then(|()| self.file.flush())
+            .and_then(|()| self.file.get_ref().sync_all())
+            .map_err(|err| {
+                eprintln!("ERROR: could not write file {temp_path:?}: {err}");
+            })?;
+
+        fs::rename(&temp_path, &self.segment_path).map_err(|err| {
+            eprintln!(
+                "ERROR: could not move {temp_path:?} to {:?}: {err}",
+                self.segment_path
+            );
+        })?;
+
+        sync_dir(&self.segment_path)?;
+
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    /// Segment file of the test, removed when it is dropped
+    struct TempSegment(PathBuf);
+
+    impl TempSegment {
+        /// Writes two documents, a deleted path and two terms
+        fn new(name: &str) -> Self {
+            let segment_path =
+                std::env::temp_dir().join(format!("segment-file-{}-{name}.seg", process::id()));
+
+            let mut writer = SegmentWriter::create(&segment_path).unwrap();
+            assert_eq!(writer.add_doc(Path::new("/d/a"), &INFO_A), Ok(0));
+            assert_eq!(writer.add_doc(Path::new("/d/b"), &INFO_B), Ok(1));
+            writer.add_deleted(Path::new("/d/old")).unwrap();
+
+            let mut postings = Vec::new();
+            push_posting(&mut postings, 0, [None, None, Some(&[1]), None]);
+            push_posting(&mut postings, 1, [Some(&[0]), None, Some(&[2, 5]), None]);
+            writer
+                .add_term("shader", 2, &postings, &[("Shaders", 1)], &[("vertex", 2)])
+                .unwrap();
+
+            let mut postings = Vec::new();
+            push_posting(&mut postings, 1, [None, None, Some(&[1]), None]);
+            writer.add_term("vertex", 1, &postings, &[], &[]).unwrap();
+
+            writer.finish().unwrap();
+
+            Self(segment_path)
+        }
+
+        /// Changes the bytes of the file, the header checksum is updated when `reseal`
+        fn patch(&self, reseal: bool, patch: impl FnOnce(&mut Vec<u8>)) {
+            let mut bytes = fs::read(&self.0).unwrap();
+            patch(&mut bytes);
+
+            if reseal {
+                let checksum = crc32fast::hash(&bytes[..HEADER_LEN - 4]);
+                bytes[HEADER_LEN - 4..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+            }
+
+            fs::write(&self.0, bytes).unwrap();
+        }
+    }
+
+    impl Drop for TempSegment {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    const INFO_A: DocInfo = DocInfo {
+        last_modified: 100,
+        size: 10,
+        hash: 7,
+        counts: [0, 0, 3, 2],
+    };
+
+    const INFO_B: DocInfo = DocInfo {
+        last_modified: 200,
+        size: 20,
+        hash: 8,
+        counts: [1, 0, 6, 2],
+    };
+
+    fn field_positions(posting: &RawPosting) -> Vec<Option<Vec<usize>>> {
+        posting
+            .fields
+            .iter()
+            .map(|field| field.map(|bytes| positions(bytes).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn segments_read_what_was_written() {
+        let segment = TempSegment::new("round-trip");
+        let reader = SegmentReader::open(&segment.0).unwrap();
+        assert_eq!(reader.verify(), Ok(()));
+
+        assert_eq!(reader.doc_count(), 2);
+        assert_eq!(reader.doc_path(1), Path::new("/d/b"));
+        assert_eq!(reader.doc_info(0), INFO_A);
+        assert_eq!(reader.doc_info(1), INFO_B);
+        assert_eq!(reader.field_count(Field::Body), 9);
+        assert_eq!(reader.find_doc(Path::new("/d/b")), Some(1));
+        assert_eq!(reader.find_doc(Path::new("/d/c")), None);
+        assert_eq!(reader.deleted().collect::<Vec<_>>(), [Path::new("/d/old")]);
+
+        assert_eq!(reader.term_count(), 2);
+        assert!(reader.find_term("fragment").is_none());
+
+        let shader = reader.find_term("shader").unwrap();
+        assert_eq!(shader.df, 2);
+        assert_eq!(shader.spellings().collect::<Vec<_>>(), [("Shaders", 1)]);
+        assert_eq!(shader.pairs().collect::<Vec<_>>(), [("vertex", 2)]);
+
+        let postings = shader.postings().collect::<Vec<_>>();
+        assert_eq!(postings.iter().map(|p| p.doc).collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(
+            field_positions(&postings[1]),
+            [Some(vec![0]), None, Some(vec![2, 5]), None]
+        );
+
+        let vertex = reader.find_term("vertex").unwrap();
+        assert_eq!(vertex.postings().count(), 1);
+        assert_eq!(vertex.spellings().count(), 0);
+
+        let terms = reader.terms_from("t").map(|entry| entry.term);
+        assert_eq!(terms.collect::<Vec<_>>(), ["vertex"]);
+
+        let spellings = reader
+            .spellings_from("Sh")
+            .map(|(spelling, entry)| (spelling, entry.term));
+        assert_eq!(spellings.collect::<Vec<_>>(), [("Shaders", "shader")]);
+    }
+
+    #[test]
+    fn corrupted_data_is_found_by_verify() {
+        let segment = TempSegment::new("data");
+        segment.patch(false, |bytes| bytes[HEADER_LEN + 1] ^= 0xff);
+
+        // the postings are not read when opening
+        let reader = SegmentReader::open(&segment.0).unwrap();
+        assert_eq!(reader.verify(), Err(()));
+    }
+
+    #[test]
+    fn corrupted_headers_and_tables_are_not_opened() {
+        let segment = TempSegment::new("corrupted");
+        let bytes = fs::read(&segment.0).unwrap();
+        let len = bytes.len();
+
+        let fails = |patch: &dyn Fn(&mut Vec<u8>), reseal: bool| {
+            fs::write(&segment.0, &bytes).unwrap();
+            segment.patch(reseal, patch);
+            SegmentReader::open(&segment.0).is_err()
+        };
+
+        // a flipped byte in the header or in the last table
+        assert!(fails(&|bytes| bytes[8] ^= 1, false));
+        assert!(fails(&|bytes| bytes[len - 1] ^= 1, false));
+
+        // truncated, within the tables and within the header
+        assert!(fails(&|bytes| bytes.truncate(len - 4), false));
+        assert!(fails(&|bytes| bytes.truncate(HEADER_LEN - 1), false));
+        assert!(fails(&|bytes| bytes.clear(), false));
+
+        // counts and offsets pointing outside of the file, with a valid header
+        assert!(fails(
+            &|bytes| bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes()),
+            true
+        ));
+        let docs_offset = 24 + 4 * 8;
+        assert!(fails(
+            &|bytes| bytes[docs_offset..docs_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes()),
+            true
+        ));
+        assert!(fails(
+            &|bytes| bytes[docs_offset..docs_offset + 8].copy_from_slice(&0u64.to_le_bytes()),
+            true
+        ));
+
+        // the untouched file still opens
+        assert!(!fails(&|_| {}, false));
+    }
+}
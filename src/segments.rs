@@ -0,0 +1,824 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::index_file::write_file;
+use crate::lexer::LexerOptions;
+use crate::model::{Boosts, Doc, Field, FileStamp, Model, Scorer};
+use crate::segment_file::{self, path_key, SegmentReader, SegmentWriter, TermEntry};
+
+// Layout of a segmented index folder:
+//
+// segments.json    the manifest, segments listed from the oldest to the newest
+// 00000001.seg     immutable segments, see `segment_file` for their layout
+//
+// A document updated in a later segment overrides its older versions
+// and removals are recorded as tombstones until the segments are merged.
+// The segments are mapped in memory and searched in place, see `Model::set_segments`.
+const MANIFEST: &str = "segments.json";
+const SEGMENT_EXTENSION: &str = "seg";
+
+// segments are merged in the background once there are more than that
+const MAX_SEGMENTS: usize = 8;
+// number of adjacent segments combined by a merge
+const MERGE_FACTOR: usize = 4;
+// most frequent words following a term kept in a segment, for the suggestions
+const MAX_PAIRS: usize = 16;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SegmentInfo {
+    pub name: String,
+    // number of documents and tombstones, for the logs
+    pub docs: usize,
+    // size of the file, the merge policy combines the smallest segments first
+    pub bytes: usize,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct Manifest {
+    // id of the next segment written
+    pub next_id: u64,
+    pub segments: Vec<SegmentInfo>,
+    #[serde(default)]
+    pub scorer: Scorer,
+    #[serde(default)]
+    pub boosts: Boosts,
+    #[serde(default = "LexerOptions::unversioned")]
+    pub lexer: LexerOptions,
+    // modification times of the documents of the segments touched since they were written
+    #[serde(default)]
+    pub touched: HashMap<PathBuf, u64>,
+    // files that no parser could read, see `Model::rejected`
+    #[serde(default)]
+    pub rejected: HashMap<PathBuf, FileStamp>,
+    // the mapped segments, in the same order
+    #[serde(skip)]
+    readers: Vec<Arc<SegmentReader>>,
+    // bumped when `rewrite` replaces the segments, the flushes and merges
+    // started before are dropped
+    #[serde(skip)]
+    generation: u64,
+}
+
+impl Manifest {
+    /// Reserves the id of a new segment
+    fn take_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Settings of the model saved along with the segments
+    fn update(&mut self, model: &Model) {
+        self.scorer = model.scorer;
+        self.boosts = model.boosts;
+        self.lexer = model.lexer.clone();
+        self.touched = model.touched.clone();
+        self.rejected = model.rejected.clone();
+    }
+}
+
+/// Index saved as a folder of immutable segments
+/// Saving only writes the documents changed since the previous save
+/// and small segments are merged in the background
+///
+/// The model is always locked before the manifest
+#[derive(Debug)]
+pub struct SegmentStore {
+    dir_path: PathBuf,
+    manifest: Mutex<Manifest>,
+    // held for a whole flush, so that the segments are appended in the order of the changes
+    flushing: Mutex<()>,
+    // only one merge runs at a time
+    merging: AtomicBool,
+}
+
+impl SegmentStore {
+    /// Opens the segmented index in `dir_path`, creating an empty one when it does not exist
+    /// Returns the store and the model searching its segments, which are only mapped in memory
+    pub fn open(dir_path: &Path) -> Result<(Arc<Self>, Model), ()> {
+        fs::create_dir_all(dir_path).map_err(|err| {
+            eprintln!("ERROR: could not create index folder {dir_path:?}: {err}");
+        })?;
+
+        let manifest_path = dir_path.join(MANIFEST);
+
+        let mut manifest: Manifest = if manifest_path.is_file() {
+            let file = File::open(&manifest_path).map_err(|err| {
+                eprintln!("ERROR: could not open {manifest_path:?}: {err}");
+            })?;
+
+            serde_json::from_reader(file).map_err(|err| {
+                eprintln!("ERROR: could not parse {manifest_path:?}: {err}");
+            })?
+        } else {
+            Manifest::default()
+        };
+
+        manifest.readers = manifest
+            .segments
+            .iter()
+            .map(|info| SegmentReader::open(&dir_path.join(&info.name)).map(Arc::new))
+            .collect::<Result<_, ()>>()?;
+
+        let mut model = Model::new(manifest.scorer, manifest.boosts, manifest.lexer.clone());
+        model.touched = manifest.touched.clone();
+        model.rejected = manifest.rejected.clone();
+        model.set_segments(manifest.readers.clone());
+
+        let store = Self {
+            dir_path: dir_path.to_path_buf(),
+            manifest: Mutex::new(manifest),
+            flushing: Mutex::new(()),
+            merging: AtomicBool::new(false),
+        };
+
+        store.remove_unused_files();
+
+        Ok((Arc::new(store), model))
+    }
+
+    /// Saves the documents changed since the previous save as a new segment
+    /// and starts a merge in the background when there are too many segments
+    /// The model then searches the new segment instead of the documents in memory
+    ///
+    /// The changes are copied under the lock of the model but written without it,
+    /// so that searches and updates go on while the segment is written
+    pub fn flush(self: &Arc<Self>, model: &Mutex<Model>) -> Result<(), ()> {
+        let _flushing = self.flushing.lock().unwrap();
+
+        let (changes, id, generation) = {
+            let mut model = model.lock().unwrap();
+            let mut manifest = self.manifest.lock().unwrap();
+            manifest.update(&model);
+
+            let changes = model.take_changes();
+            let id = (!changes.is_empty()).then(|| manifest.take_id());
+
+            (changes, id, manifest.generation)
+        };
+
+        let Some(id) = id else {
+            return self.write_manifest(&self.manifest.lock().unwrap());
+        };
+
+        let docs = changes
+            .docs
+            .iter()
+            .map(|(path, doc)| (path.as_path(), doc))
+            .collect::<Vec<_>>();
+        let deleted = changes
+            .deleted
+            .iter()
+            .map(PathBuf::as_path)
+            .collect::<Vec<_>>();
+
+        let written = self.write_docs(id, &docs, &deleted);
+
+        let mut model = model.lock().unwrap();
+        let mut manifest = self.manifest.lock().unwrap();
+
+        let (info, reader) = match written {
+            Ok(written) => written,
+            Err(()) => {
+                model.restore_changes(&changes);
+                return Err(());
+            }
+        };
+
+        // the segments were rewritten from the whole model meanwhile, changes included
+        if manifest.generation != generation {
+            let _ = fs::remove_file(self.dir_path.join(info.name));
+            return Ok(());
+        }
+
+        println!("Saving {} changes to segment {:?}...", info.docs, info.name);
+
+        manifest.segments.push(info);
+        manifest.readers.push(Arc::new(reader));
+
+        if self.write_manifest(&manifest).is_err() {
+            let info = manifest.segments.pop();
+            manifest.readers.pop();
+
+            if let Some(info) = info {
+                let _ = fs::remove_file(self.dir_path.join(info.name));
+            }
+
+            model.restore_changes(&changes);
+            return Err(());
+        }
+
+        model.replace_memory(&changes, manifest.readers.clone());
+
+        if manifest.segments.len() > MAX_SEGMENTS && !self.merging.swap(true, Ordering::SeqCst) {
+            let store = Arc::clone(self);
+
+            thread::spawn(move || {
+                let _ = store.merge();
+                store.merging.store(false, Ordering::SeqCst);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Replaces all the segments with a single one holding the documents in memory
+    /// The model has to hold the whole index, such as a migrated one
+    pub fn rewrite(&self, model: &mut Model) -> Result<(), ()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        manifest.update(model);
+
+        let (segments, readers) = if model.docs.is_empty() {
+            (Vec::new(), Vec::new())
+        } else {
+            let docs = model.docs.iter().map(|(_, path, doc)| (path, doc));
+            let (info, reader) =
+                self.write_docs(manifest.take_id(), &docs.collect::<Vec<_>>(), &[])?;
+
+            println!(
+                "Saving {} documents to segment {:?}...",
+                info.docs, info.name
+            );
+
+            (vec![info], vec![Arc::new(reader)])
+        };
+
+        let old = std::mem::replace(&mut manifest.segments, segments);
+        manifest.readers = readers;
+        manifest.generation += 1;
+        self.write_manifest(&manifest)?;
+
+        model.clear_memory();
+        model.set_segments(manifest.readers.clone());
+
+        for info in old {
+            let _ = fs::remove_file(self.dir_path.join(info.name));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the documents and the tombstones of the `deleted` paths in a new segment
+    /// Documents of old indexes without fields are left out, so that they are indexed again
+    fn write_docs(
+        &self,
+        id: u64,
+        docs: &[(&Path, &Doc)],
+        deleted: &[&Path],
+    ) -> Result<(SegmentInfo, SegmentReader), ()> {
+        let (name, segment_path) = self.segment_path(id);
+        let mut writer = SegmentWriter::create(&segment_path)?;
+
+        let mut docs = docs
+            .iter()
+            .filter(|(_, doc)| doc.count() > 0)
+            .collect::<Vec<_>>();
+        docs.sort_by(|(a, _), (b, _)| path_key(a).cmp(&path_key(b)));
+
+        // documents containing every term, by rank
+        let mut terms = BTreeMap::<&str, Vec<(u32, &Doc)>>::new();
+        // number of documents spelling every term in each way, other than the term itself
+        let mut spellings = HashMap::<&str, BTreeMap<&str, usize>>::new();
+        // words following every term in all the documents
+        let mut pairs = HashMap::<&str, HashMap<&str, usize>>::new();
+
+        for (path, doc) in docs {
+            let rank = writer.add_doc(path, &doc.info())?;
+
+            for (a, b) in doc.pairs() {
+                *pairs.entry(a).or_default().entry(b).or_default() += 1;
+            }
+
+            for (t, spelling) in doc.spellings() {
+                *spellings.entry(t).or_default().entry(spelling).or_default() += 1;
+            }
+
+            for field in Field::ALL {
+                for t in doc
+                    .field(field)
+                    .into_iter()
+                    .flat_map(|terms| terms.tf.keys())
+                {
+                    let docs = terms.entry(t).or_default();
+
+                    // a term found in several fields is a single posting
+                    if docs.last().map(|(last, _)| *last) != Some(rank) {
+                        docs.push((rank, doc));
+                    }
+                }
+            }
+        }
+
+        let mut deleted = deleted.to_vec();
+        deleted.sort_by(|a, b| path_key(a).cmp(&path_key(b)));
+
+        for path in &deleted {
+            writer.add_deleted(path)?;
+        }
+
+        let mut postings = Vec::new();
+
+        for (term, docs) in &terms {
+            postings.clear();
+
+            for (rank, doc) in docs {
+                let fields = Field::ALL.map(|field| {
+                    doc.field(field)
+                        .and_then(|terms| terms.positions.get(*term))
+                        .map(Vec::as_slice)
+                });
+
+                segment_file::push_posting(&mut postings, *rank, fields);
+            }
+
+            let spellings = spellings
+                .get(term)
+                .into_iter()
+                .flatten()
+                .map(|(spelling, n)| (*spelling, *n))
+                .collect::<Vec<_>>();
+
+            let pairs = top_pairs(pairs.get(term).into_iter().flatten());
+
+            writer.add_term(term, docs.len(), &postings, &spellings, &pairs)?;
+        }
+
+        let docs = writer.doc_count() as usize + deleted.len();
+        let bytes = writer.finish()?;
+        let reader = SegmentReader::open(&segment_path)?;
+
+        let info = SegmentInfo {
+            name,
+            docs,
+            bytes: bytes as usize,
+        };
+
+        Ok((info, reader))
+    }
+
+    /// Combines the adjacent segments taking the least space
+    /// Flushes keep appending segments while the merged one is written,
+    /// the model searches the merged segment from the next flush on
+    fn merge(&self) -> Result<(), ()> {
+        let (start, window, readers, id, generation) = {
+            let mut manifest = self.manifest.lock().unwrap();
+
+            if manifest.segments.len() < MERGE_FACTOR {
+                return Ok(());
+            }
+
+            let start = (0..=manifest.segments.len() - MERGE_FACTOR)
+                .min_by_key(|i| {
+                    manifest.segments[*i..*i + MERGE_FACTOR]
+                        .iter()
+                        .map(|info| info.bytes)
+                        .sum::<usize>()
+                })
+                .unwrap_or(0);
+
+            let window = manifest.segments[start..start + MERGE_FACTOR].to_vec();
+            let readers = manifest.readers[start..start + MERGE_FACTOR].to_vec();
+
+            (
+                start,
+                window,
+                readers,
+                manifest.take_id(),
+                manifest.generation,
+            )
+        };
+
+        // every posting is read below, the data is checked first so that
+        // a corrupted segment is not copied into the merged one
+        for reader in &readers {
+            reader.verify()?;
+        }
+
+        // the oldest segment has nothing older to hide documents from
+        let keep_deleted = start > 0;
+
+        // the newest version of every path, a document or a tombstone
+        let mut seen = HashSet::new();
+        // live documents, by path, with their segment and rank
+        let mut live = BTreeMap::new();
+        let mut deleted = Vec::new();
+
+        for (i, reader) in readers.iter().enumerate().rev() {
+            for rank in 0..reader.doc_count() {
+                let path = reader.doc_path(rank);
+
+                if seen.insert(path) {
+                    live.insert(path_key(path), (i, rank));
+                }
+            }
+
+            for path in reader.deleted() {
+                if seen.insert(path) && keep_deleted {
+                    deleted.push(path_key(path));
+                }
+            }
+        }
+
+        deleted.sort();
+
+        let (name, segment_path) = self.segment_path(id);
+        let mut writer = SegmentWriter::create(&segment_path)?;
+
+        // new rank of the live documents in every segment of the window
+        let mut remap = readers
+            .iter()
+            .map(|reader| vec![None; reader.doc_count() as usize])
+            .collect::<Vec<_>>();
+
+        for (path, (i, rank)) in &live {
+            let reader = &readers[*i];
+            remap[*i][*rank as usize] =
+                Some(writer.add_doc(Path::new(path.as_ref()), &reader.doc_info(*rank))?);
+        }
+
+        for path in &deleted {
+            writer.add_deleted(Path::new(path.as_ref()))?;
+        }
+
+        let mut cursors = readers
+            .iter()
+            .map(|reader| reader.terms_from("").peekable())
+            .collect::<Vec<_>>();
+
+        let mut postings = Vec::new();
+
+        while let Some(term) = next_term(&mut cursors) {
+            postings.clear();
+
+            let mut merged = Vec::new();
+            // the spellings of the replaced documents still count, like the document frequency
+            let mut spellings = BTreeMap::<&str, usize>::new();
+            let mut pairs = HashMap::<&str, usize>::new();
+
+            for (i, cursor) in cursors.iter_mut().enumerate() {
+                let Some(entry) = cursor.next_if(|entry| entry.term == term) else {
+                    continue;
+                };
+
+                for (spelling, n) in entry.spellings() {
+                    *spellings.entry(spelling).or_default() += n;
+                }
+
+                for (next, n) in entry.pairs() {
+                    *pairs.entry(next).or_default() += n;
+                }
+
+                for posting in entry.postings() {
+                    if let Some(rank) = remap[i][posting.doc as usize] {
+                        merged.push((rank, posting.fields));
+                    }
+                }
+            }
+
+            if merged.is_empty() {
+                continue;
+            }
+
+            merged.sort_by_key(|(rank, _)| *rank);
+
+            for (rank, fields) in &merged {
+                segment_file::push_raw_posting(&mut postings, *rank, *fields);
+            }
+
+            let spellings = spellings.into_iter().collect::<Vec<_>>();
+            let pairs = top_pairs(&pairs);
+
+            writer.add_term(term, merged.len(), &postings, &spellings, &pairs)?;
+        }
+
+        let docs = writer.doc_count() as usize + deleted.len();
+        let bytes = writer.finish()?;
+        let reader = SegmentReader::open(&segment_path)?;
+
+        let info = SegmentInfo {
+            name,
+            docs,
+            bytes: bytes as usize,
+        };
+
+        let mut manifest = self.manifest.lock().unwrap();
+
+        // flushes only append segments, but a rewrite replaces all of them
+        if manifest.generation != generation {
+            let _ = fs::remove_file(segment_path);
+            return Ok(());
+        }
+
+        println!(
+            "Merged {} segments into segment {:?}...",
+            window.len(),
+            info.name
+        );
+
+        manifest
+            .segments
+            .splice(start..start + window.len(), [info]);
+        manifest
+            .readers
+            .splice(start..start + window.len(), [Arc::new(reader)]);
+        self.write_manifest(&manifest)?;
+
+        drop(manifest);
+
+        // the model may still map the old segments until the next flush, which is fine
+        // once they are removed since their content stays in place while they are mapped
+        for info in window {
+            let _ = fs::remove_file(self.dir_path.join(info.name));
+        }
+
+        Ok(())
+    }
+
+    /// Name and path of the segment of that id
+    fn segment_path(&self, id: u64) -> (String, PathBuf) {
+        let name = format!("{id:08}.{SEGMENT_EXTENSION}");
+        let segment_path = self.dir_path.join(&name);
+
+        (name, segment_path)
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> Result<(), ()> {
+        let manifest_path = self.dir_path.join(MANIFEST);
+
+        let bytes = serde_json::to_vec_pretty(manifest).map_err(|err| {
+            eprintln!("ERROR: could not serialize {manifest_path:?}: {err}");
+        })?;
+
+        write_file(&manifest_path, &bytes)
+    }
+
+    /// Removes the segments left behind by a crash before the manifest was updated
+    fn remove_unused_files(&self) {
+        let manifest = self.manifest.lock().unwrap();
+
+        let Ok(entries) = fs::read_dir(&self.dir_path) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let listed = manifest
+                .segments
+                .iter()
+                .any(|info| path.file_name() == Some(info.name.as_ref()));
+
+            if !listed
+                && path
+                    .extension()
+                    .is_some_and(|e| e == SEGMENT_EXTENSION || e == "tmp")
+            {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Smallest of the next terms of the segments being merged
+fn next_term<'a, I>(cursors: &mut [Peekable<I>]) -> Option<&'a str>
+where
+    I: Iterator<Item = TermEntry<'a>>,
+{
+    cursors
+        .iter_mut()
+        .filter_map(|cursor| cursor.peek().map(|entry| entry.term))
+        .min()
+}
+
+/// The [`MAX_PAIRS`] most frequent words following a term
+fn top_pairs<'a, 'b>(
+    pairs: impl IntoIterator<Item = (&'b &'a str, &'b usize)>,
+) -> Vec<(&'a str, usize)>
+where
+    'a: 'b,
+{
+    let mut pairs = pairs
+        .into_iter()
+        .map(|(next, n)| (*next, *n))
+        .collect::<Vec<_>>();
+
+    pairs.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+    pairs.truncate(MAX_PAIRS);
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    use crate::model::FileStamp;
+    use crate::parser::ParsedDocument;
+    use crate::query;
+
+    const STAMP: FileStamp = FileStamp {
+        last_modified: 0,
+        size: 0,
+    };
+
+    /// Empty folder of the test, removed when it is dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir_path = std::env::temp_dir().join(format!("segments-{}-{name}", process::id()));
+            let _ = fs::remove_dir_all(&dir_path);
+
+            Self(dir_path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn add(model: &Mutex<Model>, path: &str, text: &str) {
+        let parsed = ParsedDocument::new(text.to_string());
+        model
+            .lock()
+            .unwrap()
+            .add_document(PathBuf::from(path), STAMP, 0, &parsed);
+    }
+
+    fn remove(model: &Mutex<Model>, path: &str) {
+        model.lock().unwrap().remove_document(Path::new(path));
+    }
+
+    /// Paths of the documents matching every query, best first, along with their scores
+    fn search(model: &Mutex<Model>) -> Vec<Vec<(PathBuf, f32)>> {
+        let model = model.lock().unwrap();
+
+        // `texture*` only matches `textur` through its spelling
+        ["shader", "vertex buffer", "\"index buffer\"", "texture*"]
+            .iter()
+            .map(|query| {
+                let chars = query.chars().collect::<Vec<_>>();
+                let query = query::parse(&chars, &model.lexer).unwrap().unwrap();
+                model.search(&query, Scorer::default())
+            })
+            .collect()
+    }
+
+    fn paths(results: &[(PathBuf, f32)]) -> Vec<&str> {
+        results
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect()
+    }
+
+    fn open(dir: &TempDir) -> (Arc<SegmentStore>, Mutex<Model>) {
+        let (store, model) = SegmentStore::open(&dir.0).unwrap();
+        (store, Mutex::new(model))
+    }
+
+    #[test]
+    fn flushed_documents_are_searched_in_place() {
+        let dir = TempDir::new("flush");
+        let (store, model) = open(&dir);
+
+        add(&model, "/d/1", "vertex shader reading the vertex buffer");
+        add(&model, "/d/2", "fragment shader sampling a texture");
+        add(&model, "/d/3", "index buffer of the mesh");
+
+        let expected = search(&model);
+        store.flush(&model).unwrap();
+
+        assert!(model.lock().unwrap().docs.is_empty());
+        assert_eq!(search(&model), expected);
+
+        drop(model);
+        let (_, model) = open(&dir);
+        assert_eq!(search(&model), expected);
+    }
+
+    #[test]
+    fn loaded_segments_keep_the_spellings() {
+        let dir = TempDir::new("spellings");
+        let (store, model) = open(&dir);
+
+        add(&model, "/d/1", "Shaders compile");
+        add(&model, "/d/2", "shaders link");
+        add(&model, "/d/3", "a shader");
+
+        let expected = model.lock().unwrap().spellings.clone();
+        store.flush(&model).unwrap();
+
+        let mut model = model.into_inner().unwrap();
+        let query = "shdaers compiel".chars().collect::<Vec<_>>();
+        let did_you_mean = model.did_you_mean(&query);
+
+        model.load_segments();
+
+        assert_eq!(model.spellings, expected);
+        assert_eq!(model.spelling("shader"), "shaders");
+        assert_eq!(model.spelling("compil"), "compile");
+        assert_eq!(model.did_you_mean(&query), did_you_mean);
+        assert_eq!(did_you_mean, Some("shaders compile".to_string()));
+    }
+
+    #[test]
+    fn tombstones_hide_older_versions() {
+        let dir = TempDir::new("tombstones");
+        let (store, model) = open(&dir);
+
+        add(&model, "/d/1", "vertex shader");
+        add(&model, "/d/2", "fragment shader");
+        add(&model, "/d/3", "index buffer");
+        store.flush(&model).unwrap();
+
+        add(&model, "/d/2", "texture sampler");
+        remove(&model, "/d/3");
+        store.flush(&model).unwrap();
+
+        let results = search(&model);
+        assert_eq!(paths(&results[0]), ["/d/1"]);
+        assert!(results[2].is_empty());
+        assert_eq!(paths(&results[3]), ["/d/2"]);
+
+        let (_, model) = open(&dir);
+        assert_eq!(search(&model), results);
+    }
+
+    #[test]
+    fn merges_keep_the_results() {
+        let dir = TempDir::new("merge");
+        let (store, model) = open(&dir);
+
+        add(&model, "/d/1", "vertex shader");
+        add(&model, "/d/2", "fragment shader");
+        store.flush(&model).unwrap();
+
+        add(&model, "/d/3", "index buffer of the vertex buffer");
+        add(&model, "/d/4", "texture of the fragment");
+        store.flush(&model).unwrap();
+
+        add(&model, "/d/1", "vertex buffer binding");
+        remove(&model, "/d/2");
+        store.flush(&model).unwrap();
+
+        add(&model, "/d/5", "shader texture");
+        remove(&model, "/d/4");
+        store.flush(&model).unwrap();
+
+        let expected = search(&model);
+        assert_eq!(paths(&expected[0]), ["/d/5"]);
+
+        store.merge().unwrap();
+
+        let manifest = store.manifest.lock().unwrap();
+        assert_eq!(manifest.segments.len(), 1);
+        // the oldest segment leaves no tombstones
+        assert_eq!(manifest.segments[0].docs, 3);
+        assert_eq!(manifest.readers[0].deleted().count(), 0);
+        drop(manifest);
+
+        drop(model);
+        let (_, model) = open(&dir);
+        let results = search(&model);
+
+        for (merged, expected) in results.iter().zip(&expected) {
+            assert_eq!(paths(merged), paths(expected));
+
+            for ((_, a), (_, b)) in merged.iter().zip(expected) {
+                assert!((a - b).abs() < 1e-6, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn documents_changed_during_a_flush_stay_in_memory() {
+        let dir = TempDir::new("changed");
+        let (_, model) = open(&dir);
+
+        add(&model, "/d/1", "vertex shader");
+        add(&model, "/d/2", "fragment shader");
+
+        let changes = model.lock().unwrap().take_changes();
+        assert_eq!(changes.docs.len(), 2);
+
+        // updated while the segment would be written
+        add(&model, "/d/2", "texture sampler");
+
+        let mut model = model.into_inner().unwrap();
+        model.replace_memory(&changes, Vec::new());
+
+        let paths = model.docs.paths().collect::<Vec<_>>();
+        assert_eq!(paths, [Path::new("/d/2")]);
+        assert!(model.changed.contains(Path::new("/d/2")));
+    }
+}
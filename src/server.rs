@@ -1,11 +1,58 @@
 use std::{
     fs::File,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
+use serde::Serialize;
+
 use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
 
-use crate::model::Model;
+use crate::{
+    model::{Model, Scorer, SearchPage},
+    parser::ParserRegistry,
+    query::{self, QueryError},
+    snippet::{self, Snippet},
+    suggest::{SharedSuggester, Suggestion, MAX_SUGGESTIONS},
+};
+
+// number of snippets returned for every search result
+const MAX_SNIPPETS: usize = 3;
+
+// results of `/api/search` returned when the request does not set a `limit`
+const DEFAULT_LIMIT: usize = 20;
+
+// most results of `/api/search` returned at once, the files are parsed again for their snippets
+const MAX_LIMIT: usize = 100;
+
+/// A search result as returned by `/api/search`
+#[derive(Serialize, Debug)]
+struct SearchHit {
+    path: PathBuf,
+    rank: f32,
+    snippets: Vec<Snippet>,
+}
+
+/// Response of `/api/search`
+/// The response used to be the bare array of the hits, they are now in `results`
+#[derive(Serialize, Debug)]
+struct SearchResponse {
+    // number of documents matching the query, on every page
+    total_hits: usize,
+    // time spent ranking the documents, without waiting for the index,
+    // the corrections and the snippets
+    took_ms: u64,
+    results: Vec<SearchHit>,
+    // the query with its words missing from the index corrected
+    did_you_mean: Option<String>,
+}
+
+/// Response of `/api/suggest`
+#[derive(Serialize, Debug)]
+struct SuggestResponse {
+    suggestions: Vec<Suggestion>,
+}
 
 fn serve_404(request: Request) -> Result<(), ()> {
     request
@@ -23,24 +70,222 @@ fn serve_500(request: Request) -> Result<(), ()> {
         })
 }
 
-fn serve_api_search(model: Arc<Mutex<Model>>, mut request: tiny_http::Request) -> Result<(), ()> {
+/// Responds to an invalid request with the error as JSON, `{"error": "..."}`
+fn serve_400(request: Request, message: &str) -> Result<(), ()> {
+    serve_json_error(request, serde_json::json!({ "error": message }))
+}
+
+/// Responds with the syntax error of a search query as JSON
+/// `{"error": {"message": "...", "position": 3}}`
+fn serve_query_error(request: Request, err: &QueryError) -> Result<(), ()> {
+    serve_json_error(request, serde_json::json!({ "error": err }))
+}
+
+fn serve_json_error(request: Request, error: serde_json::Value) -> Result<(), ()> {
+    let json = error.to_string();
+
+    let content_type_header =
+        Header::from_bytes("Content-Type", "application/json").expect("No garbage in header");
+
+    request
+        .respond(
+            Response::from_string(json)
+                .with_status_code(StatusCode(400))
+                .with_header(content_type_header),
+        )
+        .map_err(|err| {
+            eprintln!("Something is wrong with the request :{err}");
+        })
+}
+
+/// Splits a request url into its path and its `key=value` query parameters
+fn parse_url(url: &str) -> (&str, Vec<(&str, &str)>) {
+    let Some((path, query)) = url.split_once('?') else {
+        return (url, Vec::new());
+    };
+
+    let params = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect();
+
+    (path, params)
+}
+
+/// Decodes a query parameter, `+` is a space and `%XX` an escaped byte
+/// Invalid escapes are kept as they are written
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                // `from_str_radix` alone would take a sign such as `%+1`
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            x => decoded.push(x),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Reads the `offset` and `limit` query parameters of a page of results
+/// `limit` is capped to `MAX_LIMIT`
+fn page_from_params(params: &[(&str, &str)]) -> Result<(usize, usize), String> {
+    let mut offset = 0;
+    let mut limit = DEFAULT_LIMIT;
+
+    for (key, value) in params {
+        if matches!(*key, "offset" | "limit") {
+            let value = value
+                .parse::<usize>()
+                .map_err(|err| format!("invalid value {value} for {key}: {err}"))?;
+
+            if *key == "offset" {
+                offset = value;
+            } else {
+                limit = value.min(MAX_LIMIT);
+            }
+        }
+    }
+
+    Ok((offset, limit))
+}
+
+/// Picks the scorer from the `scorer`, `k1` and `b` query parameters
+/// Returns `None` when the index default should be used, `k1` or `b` alone ask for BM25
+fn scorer_from_params(params: &[(&str, &str)]) -> Result<Option<Scorer>, String> {
+    let mut name = None;
+    let mut k1 = None;
+    let mut b = None;
+
+    for (key, value) in params {
+        match *key {
+            "scorer" => name = Some(*value),
+            "k1" | "b" => {
+                let value = value
+                    .parse::<f32>()
+                    .map_err(|err| format!("invalid value {value} for {key}: {err}"))?;
+
+                if *key == "k1" {
+                    k1 = Some(value);
+                } else {
+                    b = Some(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // as with the `search` subcommand, `k1` and `b` alone pick BM25
+    if name.is_none() && (k1.is_some() || b.is_some()) {
+        name = Some("bm25");
+    }
+
+    match name {
+        Some(name) => Scorer::from_name(name, k1, b)
+            .map(Some)
+            .map_err(|()| format!("unknown scorer {name}")),
+        None => Ok(None),
+    }
+}
+
+fn serve_api_search(
+    model: Arc<Mutex<Model>>,
+    parsers: &ParserRegistry,
+    mut request: tiny_http::Request,
+) -> Result<(), ()> {
+    let (_, params) = parse_url(request.url());
+
+    let scorer = match scorer_from_params(&params) {
+        Ok(scorer) => scorer,
+        Err(message) => return serve_400(request, &message),
+    };
+
+    let (offset, limit) = match page_from_params(&params) {
+        Ok(page) => page,
+        Err(message) => return serve_400(request, &message),
+    };
+
     let mut buf = Vec::<u8>::new();
     request.as_reader().read_to_end(&mut buf).map_err(|err| {
         eprintln!("ERROR: Cannot read request body : {err}");
     })?;
 
-    let body = std::str::from_utf8(&buf)
-        .map_err(|err| {
-            eprintln!("ERROR: Cannot interpret body at UTF-8 string: {err}");
-        })?
-        .chars()
-        .collect::<Vec<_>>();
+    let body = match std::str::from_utf8(&buf) {
+        Ok(body) => body.chars().collect::<Vec<_>>(),
+        Err(err) => {
+            return serve_400(request, &format!("the query is not valid UTF-8: {err}"));
+        }
+    };
 
-    let model = model.lock().unwrap();
+    let lexer = model.lock().unwrap().lexer.clone();
+
+    let query = match query::parse(&body, &lexer) {
+        Ok(query) => query,
+        Err(err) => return serve_query_error(request, &err),
+    };
 
-    let results = model.search_query(&body)?;
+    let (query, page, did_you_mean, took_ms) = match query {
+        Some(query) => {
+            let model = model.lock().unwrap();
+            let scorer = scorer.unwrap_or(model.scorer);
 
-    let json = match serde_json::to_string(&results.iter().take(20).collect::<Vec<_>>()) {
+            // only the search itself is timed, not the wait for the lock
+            let started = Instant::now();
+            let page = model.search_page(&query, scorer, offset, limit);
+            let took_ms = started.elapsed().as_millis() as u64;
+
+            // the terms matched by the wildcards and the fuzzy terms are highlighted
+            (
+                Some(model.expand(&query)),
+                page,
+                model.did_you_mean(&body),
+                took_ms,
+            )
+        }
+        None => (None, SearchPage::default(), None, 0),
+    };
+
+    // the model is unlocked while the files are parsed again for the snippets
+    let terms = query.as_ref().map(|q| q.terms()).unwrap_or_default();
+
+    let results = page
+        .results
+        .into_iter()
+        .map(|(path, rank)| SearchHit {
+            snippets: snippet::snippets_for_file(&path, &terms, MAX_SNIPPETS, parsers, &lexer),
+            path,
+            rank,
+        })
+        .collect::<Vec<_>>();
+
+    let response = SearchResponse {
+        total_hits: page.total_hits,
+        took_ms,
+        results,
+        did_you_mean,
+    };
+
+    let json = match serde_json::to_string(&response) {
         Ok(json) => json,
         Err(err) => {
             eprintln!("ERROR: could not convert search results to JSON: {err}");
@@ -51,13 +296,49 @@ fn serve_api_search(model: Arc<Mutex<Model>>, mut request: tiny_http::Request) -
     let content_type_header =
         Header::from_bytes("Content-Type", "application/json").expect("No garbage in header");
 
-    let _x = request
+    request
         .respond(Response::from_string(&json).with_header(content_type_header))
         .unwrap();
 
     Ok(())
 }
 
+/// Completes the query of the `q` parameter while it is typed
+/// The suggester is built again in the background when the model changed
+fn serve_api_suggest(
+    model: Arc<Mutex<Model>>,
+    suggester: &Arc<SharedSuggester>,
+    request: Request,
+) -> Result<(), ()> {
+    let (_, params) = parse_url(request.url());
+
+    let query = params
+        .iter()
+        .find(|(key, _)| *key == "q")
+        .map(|(_, value)| percent_decode(value))
+        .unwrap_or_default();
+
+    // the model is not locked while the query is completed
+    let suggestions = suggester.get(&model).suggest(&query, MAX_SUGGESTIONS);
+
+    let json = match serde_json::to_string(&SuggestResponse { suggestions }) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("ERROR: could not convert suggestions to JSON: {err}");
+            return serve_500(request);
+        }
+    };
+
+    let content_type_header =
+        Header::from_bytes("Content-Type", "application/json").expect("No garbage in header");
+
+    request
+        .respond(Response::from_string(json).with_header(content_type_header))
+        .map_err(|err| {
+            eprintln!("ERROR: could not respond with the suggestions: {err}");
+        })
+}
+
 fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> Result<(), ()> {
     let content_type_header =
         Header::from_bytes("Content-Type", content_type).expect("No invalid header");
@@ -73,15 +354,23 @@ fn serve_static_file(request: Request, file_path: &str, content_type: &str) -> R
     })
 }
 
-fn serve_request(model: Arc<Mutex<Model>>, request: tiny_http::Request) -> Result<(), ()> {
+fn serve_request(
+    model: Arc<Mutex<Model>>,
+    parsers: &ParserRegistry,
+    suggester: &Arc<SharedSuggester>,
+    request: tiny_http::Request,
+) -> Result<(), ()> {
     println!(
         "INFO: Received request method: {:?}, url: {:?}",
         request.method(),
         request.url()
     );
 
-    match (request.method(), request.url()) {
-        (Method::Post, "/api/search") => serve_api_search(model, request),
+    let (path, _) = parse_url(request.url());
+
+    match (request.method(), path) {
+        (Method::Post, "/api/search") => serve_api_search(model, parsers, request),
+        (Method::Get, "/api/suggest") => serve_api_suggest(model, suggester, request),
         (Method::Get, "/index.js") => {
             serve_static_file(request, "index.js", "text/javascript; charset=utf-8")
         }
@@ -92,16 +381,25 @@ fn serve_request(model: Arc<Mutex<Model>>, request: tiny_http::Request) -> Resul
     }
 }
 
-pub fn start(address: &str, model: Arc<Mutex<Model>>) -> Result<(), ()> {
-    let server = Server::http(&address).map_err(|err| {
+/// Serves the web interface and the search API
+/// `parsers` read the matched files again to show snippets of them
+pub fn start(
+    address: &str,
+    model: Arc<Mutex<Model>>,
+    parsers: Arc<ParserRegistry>,
+) -> Result<(), ()> {
+    let server = Server::http(address).map_err(|err| {
         eprintln!("ERROR: couldnot start the server at {address}: {err}");
     })?;
 
     println!("INFO: Listening at HTTP server at {address}");
 
+    // built on the first `/api/suggest` request
+    let suggester = Arc::new(SharedSuggester::default());
+
     for request in server.incoming_requests() {
         // convert to option, to not break on errors
-        serve_request(Arc::clone(&model), request)
+        serve_request(Arc::clone(&model), &parsers, &suggester, request)
             .map_err(|err| {
                 eprintln!("ERROR: couldnot serve reponse: {err:?}");
             })
@@ -112,3 +410,93 @@ pub fn start(address: &str, model: Arc<Mutex<Model>>) -> Result<(), ()> {
 
     Err(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_split_into_path_and_params() {
+        assert_eq!(parse_url("/api/search"), ("/api/search", vec![]));
+        assert_eq!(
+            parse_url("/api/search?offset=20&&limit&scorer=bm25"),
+            (
+                "/api/search",
+                vec![("offset", "20"), ("limit", ""), ("scorer", "bm25")]
+            )
+        );
+        // only the first `=` separates the key, the value is still encoded
+        assert_eq!(
+            parse_url("/api/suggest?q=a%3Db=c"),
+            ("/api/suggest", vec![("q", "a%3Db=c")])
+        );
+    }
+
+    #[test]
+    fn params_are_percent_decoded() {
+        assert_eq!(percent_decode("vertex+shader"), "vertex shader");
+        assert_eq!(percent_decode("path%3A%2fsrc"), "path:/src");
+        // escaped UTF-8 bytes and UTF-8 written as it is
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("naïve+%E2%9C%93"), "naïve ✓");
+
+        // invalid and truncated escapes are kept
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        // a sign is not a hex digit, the `+` is still a space
+        assert_eq!(percent_decode("%zz%+1"), "%zz% 1");
+        assert_eq!(percent_decode("%%41"), "%A");
+        // an escape cut inside a multibyte character
+        assert_eq!(percent_decode("%é"), "%é");
+        // bytes that are not UTF-8 are replaced
+        assert_eq!(percent_decode("%FFx"), "\u{FFFD}x");
+    }
+
+    #[test]
+    fn pages_are_read_from_the_params() {
+        assert_eq!(page_from_params(&[]), Ok((0, DEFAULT_LIMIT)));
+        assert_eq!(
+            page_from_params(&[("offset", "40"), ("limit", "10"), ("scorer", "bm25")]),
+            Ok((40, 10))
+        );
+        assert_eq!(page_from_params(&[("limit", "1000")]), Ok((0, MAX_LIMIT)));
+
+        // becomes a 400 with the message
+        assert!(page_from_params(&[("offset", "-1")]).is_err());
+        assert!(page_from_params(&[("limit", "")]).is_err());
+        assert_eq!(
+            page_from_params(&[("limit", "ten")]),
+            Err("invalid value ten for limit: invalid digit found in string".to_string())
+        );
+    }
+
+    #[test]
+    fn scorers_are_picked_from_the_params() {
+        assert_eq!(scorer_from_params(&[]), Ok(None));
+        assert_eq!(
+            scorer_from_params(&[("scorer", "tfidf"), ("offset", "20")]),
+            Ok(Some(Scorer::TfIdf))
+        );
+        assert_eq!(
+            scorer_from_params(&[("scorer", "bm25"), ("k1", "2")]),
+            Ok(Some(Scorer::Bm25 {
+                k1: 2.0,
+                b: Scorer::BM25_B
+            }))
+        );
+        // BM25 is the only scorer with parameters
+        assert_eq!(
+            scorer_from_params(&[("b", "0.5")]),
+            Ok(Some(Scorer::Bm25 {
+                k1: Scorer::BM25_K1,
+                b: 0.5
+            }))
+        );
+
+        assert_eq!(
+            scorer_from_params(&[("scorer", "pagerank")]),
+            Err("unknown scorer pagerank".to_string())
+        );
+        assert!(scorer_from_params(&[("scorer", "bm25"), ("k1", "high")]).is_err());
+    }
+}
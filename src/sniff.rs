@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub const PDF: &str = "application/pdf";
+pub const DOCX: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+pub const ODT: &str = "application/vnd.oasis.opendocument.text";
+pub const EPUB: &str = "application/epub+zip";
+pub const XML: &str = "application/xml";
+pub const XHTML: &str = "application/xhtml+xml";
+pub const HTML: &str = "text/html";
+pub const TEXT: &str = "text/plain";
+
+/// Number of bytes looked at to guess the type of a file, see [`read_head`]
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+/// Type of a file guessed from its content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sniffed {
+    /// A binary document format recognized by its signature
+    Format(&'static str),
+    /// Text, the MIME type tells the markup languages from plain text
+    Text(&'static str),
+    /// Any other binary content
+    Binary,
+}
+
+/// Whether documents of that MIME type are text, which binary content cannot be
+pub fn is_text_mime_type(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type.ends_with("xml")
+}
+
+/// Reads the first [`SNIFF_LEN`] bytes of a file, all that [`sniff`] needs
+pub fn read_head(file_path: &Path) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+
+    File::open(file_path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+
+    Ok(head)
+}
+
+/// Extensions of the files of a binary document format
+pub fn extensions(mime_type: &str) -> &'static [&'static str] {
+    match mime_type {
+        PDF => &["pdf"],
+        DOCX => &["docx"],
+        ODT => &["odt"],
+        EPUB => &["epub"],
+        _ => &[],
+    }
+}
+
+/// Guesses the type of a file from its magic bytes,
+/// or from the first bytes of its content when it has none
+/// Only the first [`SNIFF_LEN`] bytes are looked at, see [`read_head`]
+pub fn sniff(head: &[u8]) -> Sniffed {
+    let head = &head[..head.len().min(SNIFF_LEN)];
+
+    if head.starts_with(b"%PDF-") {
+        return Sniffed::Format(PDF);
+    }
+
+    if head.starts_with(b"PK\x03\x04") {
+        return sniff_zip(head);
+    }
+
+    sniff_text(head)
+}
+
+/// Tells the document formats stored in zip containers apart from the local headers
+/// of their first entries, the directory of the archive is at the end of the file
+fn sniff_zip(head: &[u8]) -> Sniffed {
+    let u16_at = |offset: usize| {
+        head.get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+    let u32_at = |offset: usize| {
+        head.get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    let mut offset = 0;
+
+    while head[offset..].starts_with(b"PK\x03\x04") {
+        let (Some(flags), Some(method), Some(size), Some(name_len), Some(extra_len)) = (
+            u16_at(offset + 6),
+            u16_at(offset + 8),
+            u32_at(offset + 18),
+            u16_at(offset + 26),
+            u16_at(offset + 28),
+        ) else {
+            break;
+        };
+
+        let name_start = offset + 30;
+        let data_start = name_start + name_len + extra_len;
+
+        let Some(name) = head.get(name_start..name_start + name_len) else {
+            break;
+        };
+
+        // OpenDocument and EPUB name their type in a first `mimetype` entry, stored as is
+        if name == b"mimetype" && method == 0 {
+            let mime_type = head
+                .get(data_start..data_start + size)
+                .and_then(|data| std::str::from_utf8(data).ok())
+                .map(str::trim);
+
+            match mime_type {
+                Some(ODT) => return Sniffed::Format(ODT),
+                Some(EPUB) => return Sniffed::Format(EPUB),
+                _ => {}
+            }
+        }
+
+        if name.starts_with(b"word/") {
+            return Sniffed::Format(DOCX);
+        }
+
+        // the size of the entry only follows its data then
+        if flags & 0x08 != 0 || data_start + size >= head.len() {
+            break;
+        }
+
+        offset = data_start + size;
+    }
+
+    Sniffed::Binary
+}
+
+/// Text is valid UTF-8 without NUL bytes and with hardly any control characters
+fn sniff_text(head: &[u8]) -> Sniffed {
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+
+    if head.contains(&0) {
+        return Sniffed::Binary;
+    }
+
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the sample may end in the middle of a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return Sniffed::Binary,
+    };
+
+    let controls = text
+        .chars()
+        .filter(|x| x.is_control() && !matches!(x, '\n' | '\r' | '\t' | '\x0C'))
+        .count();
+
+    if controls * 100 > text.len() {
+        return Sniffed::Binary;
+    }
+
+    let start = text.trim_start().to_lowercase();
+
+    if start.starts_with("<?xml") {
+        if start.contains("<html") {
+            return Sniffed::Text(XHTML);
+        }
+
+        return Sniffed::Text(XML);
+    }
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return Sniffed::Text(HTML);
+    }
+
+    Sniffed::Text(TEXT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Write};
+
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    /// Zip archive of the entries, stored without compression
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, data) in entries {
+            writer
+                .start_file(
+                    *name,
+                    FileOptions::default().compression_method(CompressionMethod::Stored),
+                )
+                .unwrap();
+            writer.write_all(data.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn documents_in_zip_containers() {
+        let odt = zip(&[("mimetype", ODT), ("content.xml", "<office:document/>")]);
+        let epub = zip(&[
+            ("mimetype", EPUB),
+            ("META-INF/container.xml", "<container/>"),
+        ]);
+        let docx = zip(&[
+            ("[Content_Types].xml", "<Types/>"),
+            ("_rels/.rels", "<Relationships/>"),
+            ("word/document.xml", "<w:document/>"),
+        ]);
+        let other = zip(&[("readme.txt", "not a document")]);
+
+        assert_eq!(sniff(&odt), Sniffed::Format(ODT));
+        assert_eq!(sniff(&epub), Sniffed::Format(EPUB));
+        assert_eq!(sniff(&docx), Sniffed::Format(DOCX));
+        assert_eq!(sniff(&other), Sniffed::Binary);
+    }
+
+    #[test]
+    fn only_the_head_is_looked_at() {
+        let filler = "x".repeat(2 * SNIFF_LEN);
+        let late = zip(&[("[Content_Types].xml", &filler), ("word/document.xml", "")]);
+
+        // the entries after the sniffed bytes are not found
+        assert_eq!(sniff(&late[..SNIFF_LEN]), Sniffed::Binary);
+
+        let mut text = "é".repeat(SNIFF_LEN).into_bytes();
+        // ends in the middle of a character once cut
+        text.truncate(SNIFF_LEN + 1);
+        assert_eq!(sniff(&text), Sniffed::Text(TEXT));
+    }
+
+    #[test]
+    fn text_and_markup() {
+        assert_eq!(sniff(b"%PDF-1.7\n..."), Sniffed::Format(PDF));
+        assert_eq!(sniff(b"plain\ttext\r\n"), Sniffed::Text(TEXT));
+        assert_eq!(sniff(b"\xEF\xBB\xBF  <!DOCTYPE html>"), Sniffed::Text(HTML));
+        assert_eq!(sniff(b"<HTML><body>"), Sniffed::Text(HTML));
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?>\n<html xmlns=\"\">"),
+            Sniffed::Text(XHTML)
+        );
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><feed/>"), Sniffed::Text(XML));
+        assert_eq!(sniff(b""), Sniffed::Text(TEXT));
+    }
+
+    #[test]
+    fn binary_content() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), Sniffed::Binary);
+        assert_eq!(sniff(b"text with a \0 byte"), Sniffed::Binary);
+        assert_eq!(sniff(b"\xff\xfe invalid utf-8"), Sniffed::Binary);
+        assert_eq!(sniff(b"\x01\x02\x03\x04 mostly control"), Sniffed::Binary);
+    }
+}
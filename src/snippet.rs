@@ -0,0 +1,241 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    lexer::{Lexer, LexerOptions, Token},
+    parser::ParserRegistry,
+};
+
+// number of tokens kept on each side of a matched term
+const SNIPPET_CONTEXT: usize = 8;
+
+/// Piece of a document around the terms of a query
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Snippet {
+    pub text: String,
+    // character offsets of the matched terms in `text`, `end` is exclusive
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// Window of tokens around a matched term
+#[derive(Debug)]
+struct Window {
+    // token indices, `end` is inclusive
+    start: usize,
+    end: usize,
+    // indices of the matched tokens inside the window
+    matched: Vec<usize>,
+    score: usize,
+}
+
+/// Builds the window of tokens centered on the matched token `center`
+fn window_around(center: usize, tokens: &[Token], matched: &[usize], terms: &[&String]) -> Window {
+    let start = center.saturating_sub(SNIPPET_CONTEXT);
+    let end = (center + SNIPPET_CONTEXT).min(tokens.len() - 1);
+
+    // `matched` is sorted
+    let from = matched.partition_point(|i| *i < start);
+    let to = matched.partition_point(|i| *i <= end);
+    let matched = matched[from..to].to_vec();
+
+    let distinct = terms
+        .iter()
+        .filter(|t| matched.iter().any(|i| &&tokens[*i].term == *t))
+        .count();
+
+    // windows containing more of the different query terms win,
+    // repeated terms only break the ties
+    Window {
+        start,
+        end,
+        score: distinct * tokens.len() + matched.len(),
+        matched,
+    }
+}
+
+/// Copies the content of the window, collapsing runs of whitespace
+/// into a single space, and moves the highlights along with the text
+fn render(content: &[char], tokens: &[Token], window: &Window) -> Snippet {
+    let start = tokens[window.start].start;
+    let end = tokens[window.end].end;
+
+    let mut text = String::new();
+    // offset in `text` of every character of the window
+    let mut offsets = Vec::with_capacity(end - start + 1);
+    let mut length = 0;
+    let mut previous_space = false;
+
+    for x in &content[start..end] {
+        offsets.push(length);
+
+        if x.is_whitespace() {
+            if !previous_space {
+                text.push(' ');
+                length += 1;
+            }
+
+            previous_space = true;
+            continue;
+        }
+
+        text.push(*x);
+        length += 1;
+        previous_space = false;
+    }
+
+    offsets.push(length);
+
+    let mut highlights = window
+        .matched
+        .iter()
+        .map(|i| {
+            let token = &tokens[*i];
+            (offsets[token.start - start], offsets[token.end - start])
+        })
+        .collect::<Vec<_>>();
+
+    // the parts of an identifier share its span
+    highlights.dedup();
+
+    Snippet { text, highlights }
+}
+
+/// Picks up to `max_snippets` non overlapping pieces of `content`
+/// containing the most of the query `terms`, best first
+/// The content is tokenized with the `lexer` options the terms come from
+pub fn make_snippets(
+    content: &[char],
+    terms: &[&String],
+    max_snippets: usize,
+    lexer: &LexerOptions,
+) -> Vec<Snippet> {
+    let tokens = Lexer::with_options(content, lexer)
+        .tokens()
+        .collect::<Vec<_>>();
+
+    let mut matched = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| terms.contains(&&token.term))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    let mut snippets = Vec::new();
+
+    while snippets.len() < max_snippets && !matched.is_empty() {
+        let Some(best) = matched
+            .iter()
+            .map(|center| window_around(*center, &tokens, &matched, terms))
+            .max_by_key(|window| window.score)
+        else {
+            break;
+        };
+
+        // later windows are centered far enough not to overlap this one
+        matched.retain(|i| *i + SNIPPET_CONTEXT < best.start || *i > best.end + SNIPPET_CONTEXT);
+
+        snippets.push(render(content, &tokens, &best));
+    }
+
+    snippets
+}
+
+/// Parses the file again and picks the snippets for the query `terms`
+/// Returns no snippets when the file cannot be parsed anymore
+pub fn snippets_for_file(
+    file_path: &Path,
+    terms: &[&String],
+    max_snippets: usize,
+    parsers: &ParserRegistry,
+    lexer: &LexerOptions,
+) -> Vec<Snippet> {
+    match parsers.parse(file_path) {
+        Ok(parsed) => {
+            let content = parsed.text.chars().collect::<Vec<_>>();
+            make_snippets(&content, terms, max_snippets, lexer)
+        }
+        Err(()) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippets(content: &str, terms: &[&str], lexer: &LexerOptions) -> Vec<Snippet> {
+        let content = content.chars().collect::<Vec<_>>();
+        let terms = terms.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let terms = terms.iter().collect::<Vec<_>>();
+
+        make_snippets(&content, &terms, 3, lexer)
+    }
+
+    /// Highlighted parts of the text of a snippet
+    fn highlighted(snippet: &Snippet) -> Vec<String> {
+        let chars = snippet.text.chars().collect::<Vec<_>>();
+
+        snippet
+            .highlights
+            .iter()
+            .map(|(start, end)| chars[*start..*end].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn highlights_follow_the_collapsed_whitespace() {
+        let found = snippets(
+            "Bind the \t vertex\n\n buffers now",
+            &["vertex", "buffer"],
+            &LexerOptions::default(),
+        );
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].text, "Bind the vertex buffers now");
+        assert_eq!(found[0].highlights, [(9, 15), (16, 23)]);
+    }
+
+    #[test]
+    fn highlights_are_character_offsets() {
+        let found = snippets("déjà vu — l'été, été", &["été"], &LexerOptions::default());
+
+        assert_eq!(found[0].highlights, [(12, 15), (17, 20)]);
+        assert_eq!(highlighted(&found[0]), ["été", "été"]);
+    }
+
+    #[test]
+    fn windows_with_more_terms_come_first() {
+        let filler = "lorem ipsum dolor sit amet consectetur adipiscing elit ".repeat(3);
+        let content = format!("shader {filler} vertex shader {filler} shader");
+
+        let found = snippets(&content, &["vertex", "shader"], &LexerOptions::default());
+
+        assert_eq!(found.len(), 3);
+        assert_eq!(highlighted(&found[0]), ["vertex", "shader"]);
+        assert_eq!(highlighted(&found[1]), ["shader"]);
+        assert_eq!(highlighted(&found[2]), ["shader"]);
+
+        // the snippets do not overlap
+        let mut texts = [&found[1].text, &found[2].text];
+        texts.sort();
+        assert!(texts[0].ends_with(" shader"));
+        assert!(texts[1].starts_with("shader "));
+    }
+
+    #[test]
+    fn identifiers_are_highlighted_once() {
+        let lexer = LexerOptions {
+            code: true,
+            ..LexerOptions::default()
+        };
+        let found = snippets("call glDrawArrays here", &["draw", "array"], &lexer);
+
+        assert_eq!(highlighted(&found[0]), ["glDrawArrays"]);
+    }
+
+    #[test]
+    fn nothing_matched() {
+        assert!(snippets("vertex", &["shader"], &LexerOptions::default()).is_empty());
+        assert!(snippets("", &["shader"], &LexerOptions::default()).is_empty());
+    }
+}
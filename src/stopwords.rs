@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// Built-in stopword lists, lowercase and sorted so that they can be binary searched
+
+const ENGLISH: &[&str] = &[
+    "a",
+    "about",
+    "above",
+    "after",
+    "again",
+    "against",
+    "all",
+    "am",
+    "an",
+    "and",
+    "any",
+    "are",
+    "as",
+    "at",
+    "be",
+    "because",
+    "been",
+    "before",
+    "being",
+    "below",
+    "between",
+    "both",
+    "but",
+    "by",
+    "can",
+    "could",
+    "did",
+    "do",
+    "does",
+    "doing",
+    "don",
+    "down",
+    "during",
+    "each",
+    "few",
+    "for",
+    "from",
+    "further",
+    "had",
+    "has",
+    "have",
+    "having",
+    "he",
+    "her",
+    "here",
+    "hers",
+    "herself",
+    "him",
+    "himself",
+    "his",
+    "how",
+    "i",
+    "if",
+    "in",
+    "into",
+    "is",
+    "it",
+    "its",
+    "itself",
+    "just",
+    "me",
+    "more",
+    "most",
+    "my",
+    "myself",
+    "no",
+    "nor",
+    "not",
+    "now",
+    "of",
+    "off",
+    "on",
+    "once",
+    "only",
+    "or",
+    "other",
+    "our",
+    "ours",
+    "ourselves",
+    "out",
+    "over",
+    "own",
+    "s",
+    "same",
+    "she",
+    "should",
+    "so",
+    "some",
+    "such",
+    "t",
+    "than",
+    "that",
+    "the",
+    "their",
+    "theirs",
+    "them",
+    "themselves",
+    "then",
+    "there",
+    "these",
+    "they",
+    "this",
+    "those",
+    "through",
+    "to",
+    "too",
+    "under",
+    "until",
+    "up",
+    "very",
+    "was",
+    "we",
+    "were",
+    "what",
+    "when",
+    "where",
+    "which",
+    "while",
+    "who",
+    "whom",
+    "why",
+    "will",
+    "with",
+    "would",
+    "you",
+    "your",
+    "yours",
+    "yourself",
+    "yourselves",
+];
+
+const FRENCH: &[&str] = &[
+    "a", "ai", "as", "au", "aux", "avec", "avez", "avoir", "avons", "c", "car", "ce", "ces", "cet",
+    "cette", "comme", "d", "dans", "de", "des", "donc", "du", "elle", "en", "est", "et", "eux",
+    "il", "ils", "j", "je", "l", "la", "le", "les", "leur", "lui", "m", "ma", "mais", "me", "mes",
+    "moi", "mon", "n", "ne", "ni", "nos", "notre", "nous", "on", "ont", "ou", "par", "pas", "pour",
+    "qu", "que", "qui", "s", "sa", "se", "ses", "si", "son", "sont", "sur", "t", "ta", "te", "tes",
+    "toi", "ton", "tu", "un", "une", "vos", "votre", "vous", "y", "étaient", "était", "été",
+    "être",
+];
+
+const GERMAN: &[&str] = &[
+    "aber", "alle", "allem", "allen", "aller", "alles", "als", "also", "am", "an", "ander",
+    "andere", "anderem", "anderen", "anderer", "anderes", "auch", "auf", "aus", "bei", "bin",
+    "bis", "bist", "da", "damit", "dann", "das", "dass", "dem", "den", "der", "des", "dich", "die",
+    "dir", "du", "durch", "ein", "eine", "einem", "einen", "einer", "eines", "er", "es", "für",
+    "hab", "habe", "haben", "hat", "hatte", "hier", "ich", "ihm", "ihn", "ihnen", "ihr", "ihre",
+    "im", "in", "ist", "ja", "kann", "kein", "keine", "mich", "mir", "mit", "muss", "nach",
+    "nicht", "noch", "nun", "nur", "ob", "oder", "ohne", "sehr", "sein", "seine", "sich", "sie",
+    "sind", "so", "um", "und", "uns", "unter", "vom", "von", "vor", "war", "waren", "was", "weil",
+    "wenn", "wer", "wie", "wir", "wird", "wo", "zu", "zum", "zur", "über",
+];
+
+const SPANISH: &[&str] = &[
+    "a", "al", "algo", "algunas", "algunos", "ante", "antes", "como", "con", "contra", "cual",
+    "cuando", "de", "del", "desde", "donde", "durante", "e", "el", "ella", "ellas", "ellos", "en",
+    "entre", "era", "es", "esa", "esas", "ese", "eso", "esos", "esta", "estaba", "estado", "estar",
+    "este", "esto", "estos", "fue", "ha", "había", "han", "hasta", "hay", "la", "las", "le", "les",
+    "lo", "los", "me", "mi", "mis", "mucho", "muy", "más", "nada", "ni", "no", "nos", "nosotros",
+    "o", "os", "otra", "otro", "para", "pero", "poco", "por", "porque", "que", "quien", "se",
+    "ser", "si", "sin", "sobre", "son", "su", "sus", "también", "te", "tiene", "todo", "todos",
+    "tu", "tus", "un", "una", "uno", "unos", "y", "ya", "yo", "él",
+];
+
+/// Language of a built-in stopword list
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    French,
+    German,
+    Spanish,
+}
+
+impl Language {
+    pub const ALL: [Language; 4] = [
+        Language::English,
+        Language::French,
+        Language::German,
+        Language::Spanish,
+    ];
+
+    /// Name of the language on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "english",
+            Language::French => "french",
+            Language::German => "german",
+            Language::Spanish => "spanish",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.name() == name)
+    }
+
+    pub fn stopwords(self) -> &'static [&'static str] {
+        match self {
+            Language::English => ENGLISH,
+            Language::French => FRENCH,
+            Language::German => GERMAN,
+            Language::Spanish => SPANISH,
+        }
+    }
+
+    /// Whether the lowercase `word` is a stopword of the language
+    pub fn is_stopword(self, word: &str) -> bool {
+        self.stopwords().binary_search(&word).is_ok()
+    }
+}
+
+/// Reads a list of stopwords, one per line, the lines starting with `#` are comments
+/// Returns the words lowercased and sorted, see `LexerOptions::extra_stopwords`
+pub fn read_stopwords(file_path: &Path) -> Result<Vec<String>, ()> {
+    let content = fs::read_to_string(file_path).map_err(|err| {
+        eprintln!("ERROR: could not read stopwords file {file_path:?}: {err}");
+    })?;
+
+    let mut words = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+
+    words.sort();
+    words.dedup();
+
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    #[test]
+    fn lists_are_sorted_for_binary_search() {
+        for language in Language::ALL {
+            let words = language.stopwords();
+
+            assert!(
+                words.windows(2).all(|pair| pair[0] < pair[1]),
+                "{} stopwords are not sorted",
+                language.name()
+            );
+            assert!(words.iter().all(|word| word.to_lowercase() == *word));
+            assert!(words.iter().all(|word| language.is_stopword(word)));
+        }
+
+        assert!(Language::French.is_stopword("été"));
+        assert!(!Language::English.is_stopword("shader"));
+    }
+
+    #[test]
+    fn names() {
+        for language in Language::ALL {
+            assert_eq!(Language::from_name(language.name()), Some(language));
+        }
+
+        assert_eq!(Language::from_name("klingon"), None);
+    }
+
+    #[test]
+    fn stopwords_file() {
+        let file_path = std::env::temp_dir().join(format!("stopwords-{}.txt", process::id()));
+        fs::write(
+            &file_path,
+            "# project words\nShader\n\n  vertex \nshader\nbuffer\n",
+        )
+        .unwrap();
+
+        let words = read_stopwords(&file_path);
+        fs::remove_file(&file_path).unwrap();
+
+        assert_eq!(
+            words,
+            Ok(vec!["buffer".into(), "shader".into(), "vertex".into()])
+        );
+        assert!(read_stopwords(&file_path).is_err());
+    }
+}
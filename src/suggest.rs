@@ -0,0 +1,449 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::{
+    lexer::{Lexer, LexerOptions},
+    model::Model,
+};
+
+// words following a term that are kept, the most frequent ones
+const MAX_CONTINUATIONS: usize = 8;
+
+// pairs of words seen fewer times are not suggested
+const MIN_CONTINUATION_COUNT: usize = 2;
+
+// completions of the partial word that are followed by their most frequent next word
+const CONTINUED_COMPLETIONS: usize = 3;
+
+// the suggester is not rebuilt more often while documents are indexed
+const MIN_REBUILD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of completions returned by `/api/suggest`
+pub const MAX_SUGGESTIONS: usize = 10;
+
+/// Completion of a query as returned by `/api/suggest`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub text: String,
+    // documents containing the completed word, or occurrences of the word pair
+    pub frequency: usize,
+}
+
+/// Word of the vocabulary, as it is written in the documents
+#[derive(Debug)]
+struct Entry {
+    spelling: String,
+    term: String,
+    df: usize,
+}
+
+/// Words of a model and the pairs of adjacent words, copied while the model is locked
+/// so that the suggester is built without holding it, see [`Suggester::new`]
+#[derive(Debug)]
+pub struct Vocabulary {
+    // every term, its most common spelling and the documents containing it
+    terms: Vec<(String, String, usize)>,
+    // number of times a term follows another one
+    pairs: HashMap<(String, String), usize>,
+    lexer: LexerOptions,
+    generation: u64,
+}
+
+impl Vocabulary {
+    /// Copies the terms of the documents in memory and of the segments
+    /// The segments only keep the most frequent pairs of every term, see `SegmentStore`
+    pub fn new(model: &Model) -> Self {
+        let terms = model
+            .terms_with_prefix("")
+            .into_iter()
+            .map(|(term, df)| (term.to_string(), model.spelling(term), df))
+            .collect();
+
+        let mut pairs = HashMap::<(String, String), usize>::new();
+
+        for (_, _, doc) in model.docs.iter() {
+            for (a, b) in doc.pairs() {
+                *pairs.entry((a.to_string(), b.to_string())).or_default() += 1;
+            }
+        }
+
+        for reader in model.segment_readers() {
+            for i in 0..reader.term_count() {
+                let entry = reader.term(i);
+
+                for (next, n) in entry.pairs() {
+                    *pairs
+                        .entry((entry.term.to_string(), next.to_string()))
+                        .or_default() += n;
+                }
+            }
+        }
+
+        Self {
+            terms,
+            pairs,
+            lexer: model.lexer.clone(),
+            generation: model.generation,
+        }
+    }
+}
+
+/// Completes the last word of a query while it is typed
+///
+/// Words are found by prefix in the spellings of the vocabulary sorted once,
+/// the words following each term are counted from the positions of the documents.
+/// It is built from a [`Vocabulary`] and has to be built again once the model changes,
+/// see [`Suggester::is_stale`] and [`SharedSuggester`]
+#[derive(Debug)]
+pub struct Suggester {
+    // sorted by spelling
+    entries: Vec<Entry>,
+    // most frequent words following a term, with the number of times they do
+    continuations: HashMap<String, Vec<(String, usize)>>,
+    lexer: LexerOptions,
+    // generation of the model it was built from
+    generation: u64,
+}
+
+impl Suggester {
+    pub fn new(vocabulary: Vocabulary) -> Self {
+        let spellings = vocabulary
+            .terms
+            .iter()
+            .map(|(term, spelling, _)| (term.as_str(), spelling.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let continuations = count_continuations(&vocabulary.pairs, &spellings);
+
+        let mut entries = vocabulary
+            .terms
+            .into_iter()
+            .map(|(term, spelling, df)| Entry { spelling, term, df })
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| a.spelling.cmp(&b.spelling));
+
+        Self {
+            entries,
+            continuations,
+            lexer: vocabulary.lexer,
+            generation: vocabulary.generation,
+        }
+    }
+
+    /// Whether documents were added or removed since it was built
+    pub fn is_stale(&self, model: &Model) -> bool {
+        self.generation != model.generation || self.lexer != model.lexer
+    }
+
+    /// Entries whose spelling starts with `prefix`
+    fn with_prefix(&self, prefix: &str) -> &[Entry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.spelling.as_str() < prefix);
+        let end = start
+            + self.entries[start..].partition_point(|entry| entry.spelling.starts_with(prefix));
+
+        &self.entries[start..end]
+    }
+
+    /// The `limit` words starting with `prefix` found in the most documents
+    fn complete(&self, prefix: &str, limit: usize) -> Vec<&Entry> {
+        let entries = self.with_prefix(prefix);
+
+        // bounded heap of the best entries, the worst one on top
+        let mut best = BinaryHeap::with_capacity(limit + 1);
+
+        for (i, entry) in entries.iter().enumerate() {
+            best.push(Reverse((entry.df, Reverse(i))));
+
+            if best.len() > limit {
+                best.pop();
+            }
+        }
+
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_, Reverse(i)))| &entries[i])
+            .collect()
+    }
+
+    /// Completions of `query`, at most `limit` of them
+    ///
+    /// The partial word at the end of the query is completed with the words of the
+    /// index found in the most documents, and the words usually following the
+    /// previous word come first. A query ending with a space is continued
+    /// with the words usually following its last word.
+    pub fn suggest(&self, query: &str, limit: usize) -> Vec<Suggestion> {
+        let partial_len = query
+            .chars()
+            .rev()
+            .take_while(|x| x.is_alphanumeric() || *x == '_')
+            .map(|x| x.len_utf8())
+            .sum::<usize>();
+
+        let (head, partial) = query.split_at(query.len() - partial_len);
+        let partial = partial.to_lowercase();
+
+        // the word before a field name or an operator is not the previous word of the text
+        let previous = head
+            .ends_with(char::is_whitespace)
+            .then(|| Lexer::with_options(&head.chars().collect::<Vec<_>>(), &self.lexer).last())
+            .flatten();
+
+        let continuations = previous
+            .and_then(|term| self.continuations.get(&term))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let mut suggestions = Vec::new();
+
+        if partial.is_empty() {
+            for (next, n) in continuations {
+                suggestions.push(Suggestion {
+                    text: format!("{head}{next}"),
+                    frequency: *n,
+                });
+            }
+        } else {
+            for (next, n) in continuations {
+                if next.starts_with(&partial) {
+                    suggestions.push(Suggestion {
+                        text: format!("{head}{next}"),
+                        frequency: *n,
+                    });
+                }
+            }
+
+            let completions = self.complete(&partial, limit);
+
+            for entry in &completions {
+                suggestions.push(Suggestion {
+                    text: format!("{head}{}", entry.spelling),
+                    frequency: entry.df,
+                });
+            }
+
+            for entry in completions.iter().take(CONTINUED_COMPLETIONS) {
+                if let Some((next, n)) = self
+                    .continuations
+                    .get(&entry.term)
+                    .and_then(|words| words.first())
+                {
+                    suggestions.push(Suggestion {
+                        text: format!("{head}{} {next}", entry.spelling),
+                        frequency: *n,
+                    });
+                }
+            }
+        }
+
+        let mut seen = Vec::<String>::new();
+        suggestions.retain(|suggestion| {
+            if seen.contains(&suggestion.text) {
+                return false;
+            }
+
+            seen.push(suggestion.text.clone());
+            true
+        });
+
+        suggestions.truncate(limit);
+        suggestions
+    }
+}
+
+/// Suggester shared by the requests, built again in the background once the model changed
+/// and at most once every [`MIN_REBUILD_INTERVAL`], the previous one answers meanwhile
+#[derive(Debug, Default)]
+pub struct SharedSuggester {
+    current: Mutex<Option<Arc<Suggester>>>,
+    // only one build runs at a time
+    building: AtomicBool,
+    // when the last build started
+    built_at: Mutex<Option<Instant>>,
+}
+
+impl SharedSuggester {
+    /// Returns the latest suggester, only the first one is built before returning
+    pub fn get(self: &Arc<Self>, model: &Arc<Mutex<Model>>) -> Arc<Suggester> {
+        let current = self.current.lock().unwrap().clone();
+
+        let Some(suggester) = current else {
+            *self.built_at.lock().unwrap() = Some(Instant::now());
+
+            let vocabulary = Vocabulary::new(&model.lock().unwrap());
+            let suggester = Arc::new(Suggester::new(vocabulary));
+            *self.current.lock().unwrap() = Some(Arc::clone(&suggester));
+
+            return suggester;
+        };
+
+        // `Option::is_none_or` needs Rust 1.82
+        #[allow(clippy::unnecessary_map_or)]
+        let due = self
+            .built_at
+            .lock()
+            .unwrap()
+            .map_or(true, |built_at| built_at.elapsed() >= MIN_REBUILD_INTERVAL);
+
+        if due
+            && suggester.is_stale(&model.lock().unwrap())
+            && !self.building.swap(true, Ordering::SeqCst)
+        {
+            *self.built_at.lock().unwrap() = Some(Instant::now());
+
+            let shared = Arc::clone(self);
+            let model = Arc::clone(model);
+
+            thread::spawn(move || {
+                // only the copy is made under the lock, searches go on while it is built
+                let vocabulary = Vocabulary::new(&model.lock().unwrap());
+                let suggester = Suggester::new(vocabulary);
+                *shared.current.lock().unwrap() = Some(Arc::new(suggester));
+                shared.building.store(false, Ordering::SeqCst);
+            });
+        }
+
+        suggester
+    }
+}
+
+/// Keeps the most frequent words following each term, written with their `spellings`
+fn count_continuations(
+    pairs: &HashMap<(String, String), usize>,
+    spellings: &HashMap<&str, &str>,
+) -> HashMap<String, Vec<(String, usize)>> {
+    let mut continuations = HashMap::<String, Vec<(String, usize)>>::new();
+
+    for ((a, b), n) in pairs {
+        if *n < MIN_CONTINUATION_COUNT {
+            continue;
+        }
+
+        continuations
+            .entry(a.clone())
+            .or_default()
+            .push((b.clone(), *n));
+    }
+
+    for words in continuations.values_mut() {
+        words.sort_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+        words.truncate(MAX_CONTINUATIONS);
+
+        for (word, _) in words.iter_mut() {
+            if let Some(spelling) = spellings.get(word.as_str()) {
+                *word = spelling.to_string();
+            }
+        }
+    }
+
+    continuations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Path, PathBuf};
+
+    use crate::model::{Boosts, FileStamp, Scorer};
+    use crate::parser::ParsedDocument;
+
+    const STAMP: FileStamp = FileStamp {
+        last_modified: 0,
+        size: 0,
+    };
+
+    fn add(model: &mut Model, path: &str, text: &str) {
+        let parsed = ParsedDocument::new(text.to_string());
+        model.add_document(PathBuf::from(path), STAMP, 0, &parsed);
+    }
+
+    fn model(docs: &[(&str, &str)]) -> Model {
+        let mut model = Model::new(
+            Scorer::default(),
+            Boosts::default(),
+            LexerOptions::default(),
+        );
+
+        for (path, text) in docs {
+            add(&mut model, path, text);
+        }
+
+        model
+    }
+
+    fn suggest(model: &Model, query: &str, limit: usize) -> Vec<String> {
+        Suggester::new(Vocabulary::new(model))
+            .suggest(query, limit)
+            .into_iter()
+            .map(|suggestion| suggestion.text)
+            .collect()
+    }
+
+    #[test]
+    fn completions_are_ranked_by_document_frequency() {
+        let model = model(&[
+            ("/d/1", "shader shadow"),
+            ("/d/2", "shader sharp"),
+            ("/d/3", "Shaders shadow"),
+            ("/d/4", "vertex shader"),
+        ]);
+
+        // the words are written as most documents spell them, the frequent pairs follow
+        assert_eq!(
+            suggest(&model, "sha", MAX_SUGGESTIONS),
+            ["shader", "shadow", "sharp", "shader shadow"]
+        );
+        // the words usually following the previous one come first
+        assert_eq!(
+            suggest(&model, "(shader sh", MAX_SUGGESTIONS),
+            [
+                "(shader shadow",
+                "(shader shader",
+                "(shader sharp",
+                "(shader shader shadow"
+            ]
+        );
+        assert_eq!(
+            suggest(&model, "shader ", MAX_SUGGESTIONS),
+            ["shader shadow"]
+        );
+        assert!(suggest(&model, "xyz", MAX_SUGGESTIONS).is_empty());
+    }
+
+    #[test]
+    fn suggestions_are_truncated() {
+        let text = ('a'..='z')
+            .map(|x| format!("t{x}x"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let model = model(&[("/d/1", &text)]);
+
+        assert_eq!(suggest(&model, "t", MAX_SUGGESTIONS).len(), MAX_SUGGESTIONS);
+        assert_eq!(suggest(&model, "t", 3).len(), 3);
+    }
+
+    #[test]
+    fn suggesters_go_stale_when_the_model_changes() {
+        let mut model = model(&[("/d/1", "vertex shader")]);
+        let suggester = Suggester::new(Vocabulary::new(&model));
+        assert!(!suggester.is_stale(&model));
+
+        add(&mut model, "/d/2", "fragment shader");
+        assert!(suggester.is_stale(&model));
+
+        let suggester = Suggester::new(Vocabulary::new(&model));
+        assert!(!suggester.is_stale(&model));
+
+        model.remove_document(Path::new("/d/1"));
+        assert!(suggester.is_stale(&model));
+    }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::indexer::{add_folder_to_model, is_dot_file};
+use crate::model::{content_hash, Doc, FileStamp, Model};
+use crate::parser::ParserRegistry;
+use crate::sniff;
+
+const TIMING: Timing = Timing {
+    debounce: Duration::from_millis(500),
+    max_delay: Duration::from_secs(5),
+    persist_interval: Duration::from_secs(30),
+};
+
+/// When the changes of the folder are applied and saved
+struct Timing {
+    // changes are applied once the folder has been quiet for that long
+    debounce: Duration,
+    // changes are applied at least that often even if the folder keeps changing
+    max_delay: Duration,
+    // how often the index is saved when it has changed
+    persist_interval: Duration,
+}
+
+/// Watches a folder through the OS notifications (inotify on Linux)
+/// and keeps the model in sync with the files it contains
+pub struct FolderWatcher {
+    dir_path: PathBuf,
+    // notifications may report absolute paths while the model is keyed by `dir_path`
+    canonical_dir_path: PathBuf,
+    // dropping the watcher stops the notifications
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    parsers: Arc<ParserRegistry>,
+    // number of workers indexing the folders created in the watched folder
+    jobs: usize,
+}
+
+impl FolderWatcher {
+    /// Starts watching the folder recursively
+    /// Changes made from now on are queued until `run` is called
+    pub fn new(dir_path: &Path, parsers: Arc<ParserRegistry>, jobs: usize) -> Result<Self, ()> {
+        let (sender, events) = mpsc::channel();
+
+        let mut watcher = notify::recommended_watcher(sender).map_err(|err| {
+            eprintln!("ERROR: could not create a watcher for {dir_path:?}: {err}");
+        })?;
+
+        watcher
+            .watch(dir_path, RecursiveMode::Recursive)
+            .map_err(|err| {
+                eprintln!("ERROR: could not watch {dir_path:?}: {err}");
+            })?;
+
+        let canonical_dir_path = dir_path.canonicalize().map_err(|err| {
+            eprintln!("ERROR: could not resolve {dir_path:?}: {err}");
+        })?;
+
+        Ok(Self {
+            dir_path: dir_path.to_path_buf(),
+            canonical_dir_path,
+            _watcher: watcher,
+            events,
+            parsers,
+            jobs,
+        })
+    }
+
+    /// Converts a path reported by the notifications into the key of the model
+    /// Changes to dot files and folders are ignored, the index itself is one of them
+    fn model_path(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path
+            .strip_prefix(&self.canonical_dir_path)
+            .or_else(|_| path.strip_prefix(&self.dir_path))
+            .ok()?;
+
+        if relative.ancestors().any(is_dot_file) {
+            return None;
+        }
+
+        Some(self.dir_path.join(relative))
+    }
+
+    /// Applies the changes of the folder to the model until the watcher stops
+    /// `persist` is called with the model periodically when it has changed,
+    /// it takes the lock itself so that the searches go on while it saves
+    pub fn run<F>(self, model: Arc<Mutex<Model>>, mut persist: F) -> Result<(), ()>
+    where
+        F: FnMut(&Mutex<Model>),
+    {
+        println!("INFO: Watching {:?} for changes", self.dir_path);
+
+        process_events(
+            &self.events,
+            &TIMING,
+            |path| {
+                self.model_path(path)
+                    .is_some_and(|path| apply_change(&path, &model, &self.parsers, self.jobs))
+            },
+            || persist(&model),
+        );
+
+        eprintln!("ERROR: stopped watching {:?}", self.dir_path);
+        Err(())
+    }
+}
+
+/// Applies the changes received from `events` in batches and persists them periodically
+/// `apply` updates the model for a changed path and returns whether it was modified
+/// Returns once the watcher sending the events is dropped, the pending changes are
+/// applied and persisted first
+fn process_events<A, P>(
+    events: &Receiver<notify::Result<Event>>,
+    timing: &Timing,
+    mut apply: A,
+    mut persist: P,
+) where
+    A: FnMut(&Path) -> bool,
+    P: FnMut(),
+{
+    let mut pending = HashSet::<PathBuf>::new();
+    // when the oldest pending change was received
+    let mut pending_since = Instant::now();
+    let mut dirty = false;
+    let mut last_persist = Instant::now();
+
+    loop {
+        let timeout = if !pending.is_empty() {
+            timing.debounce
+        } else if dirty {
+            timing
+                .persist_interval
+                .saturating_sub(last_persist.elapsed())
+        } else {
+            timing.persist_interval
+        };
+
+        let stopped = match events.recv_timeout(timeout) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                if pending.is_empty() {
+                    pending_since = Instant::now();
+                }
+
+                pending.extend(event.paths);
+
+                // keeps collecting the burst of events
+                if pending_since.elapsed() < timing.max_delay {
+                    continue;
+                }
+
+                false
+            }
+            Ok(Err(err)) => {
+                eprintln!("ERROR: could not receive the changes of the folder: {err}");
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+
+        for path in pending.drain() {
+            dirty |= apply(&path);
+        }
+
+        // the changes not saved yet are not lost when the watcher stops
+        if dirty && (stopped || last_persist.elapsed() >= timing.persist_interval) {
+            persist();
+
+            dirty = false;
+            last_persist = Instant::now();
+        }
+
+        if stopped {
+            return;
+        }
+    }
+}
+
+/// Updates the model for a path that has changed
+/// Returns whether the model was modified
+fn apply_change(
+    path: &Path,
+    model: &Arc<Mutex<Model>>,
+    parsers: &ParserRegistry,
+    jobs: usize,
+) -> bool {
+    if path.is_dir() {
+        // a folder created or moved into the watched folder
+        // the documents indexed before an error still have to be saved
+        let processed = add_folder_to_model(path, Arc::clone(model), parsers, jobs)
+            .unwrap_or_else(|processed| processed);
+
+        return processed > 0;
+    }
+
+    if path.is_file() {
+        return reindex_file(path, model, parsers);
+    }
+
+    // the path does not exist anymore, it may have been a file or a whole folder
+    let mut model = model.lock().unwrap();
+
+    let removed = model
+        .paths()
+        .filter(|p| p.starts_with(path))
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+
+    for file_path in &removed {
+        println!("Removing {file_path:?} from the index...");
+        model.remove_document(file_path);
+    }
+
+    !removed.is_empty()
+}
+
+/// Indexes the file again if it was modified after being indexed
+fn reindex_file(file_path: &Path, model: &Arc<Mutex<Model>>, parsers: &ParserRegistry) -> bool {
+    let Ok(stamp) = file_path
+        .metadata()
+        .and_then(|metadata| FileStamp::from_metadata(&metadata))
+    else {
+        return false;
+    };
+
+    if parsers.skips(file_path) {
+        return false;
+    }
+
+    let (indexed_hash, lexer) = {
+        let model = model.lock().unwrap();
+
+        if model.is_rejected(file_path, stamp) || !model.requires_reindexing(file_path, stamp) {
+            return false;
+        }
+
+        (model.indexed_hash(file_path), model.lexer.clone())
+    };
+
+    let Ok(head) = sniff::read_head(file_path) else {
+        return false;
+    };
+
+    let Some(parser) = parsers.detect(file_path, &head) else {
+        model
+            .lock()
+            .unwrap()
+            .rejected
+            .insert(file_path.to_path_buf(), stamp);
+        return false;
+    };
+
+    let Ok(hash) = content_hash(file_path) else {
+        return false;
+    };
+
+    if indexed_hash == Some(hash) {
+        model.lock().unwrap().touch_document(file_path, stamp);
+        return true;
+    }
+
+    println!("Indexing {file_path:?}... ");
+
+    // parsed and tokenized without holding the lock so that searches are not blocked
+    let Ok(parsed) = parser.parse(file_path) else {
+        return false;
+    };
+
+    let doc = Doc::new(file_path, &parsed, stamp, hash, &lexer);
+
+    model
+        .lock()
+        .unwrap()
+        .insert_document(file_path.to_path_buf(), doc);
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    use notify::event::{AccessKind, ModifyKind};
+
+    use super::*;
+
+    const TEST_TIMING: Timing = Timing {
+        debounce: Duration::from_millis(50),
+        max_delay: Duration::from_secs(5),
+        persist_interval: Duration::from_millis(300),
+    };
+
+    fn send(events: &Sender<notify::Result<Event>>, kind: EventKind, path: &str) {
+        events
+            .send(Ok(Event::new(kind).add_path(PathBuf::from(path))))
+            .unwrap();
+    }
+
+    /// Runs `process_events` until `send_events` returns, `apply` reports whether
+    /// a path modified the model
+    /// Returns the batches of applied paths and how many times the model was persisted
+    fn process<F>(apply: fn(&Path) -> bool, send_events: F) -> (Vec<Vec<PathBuf>>, usize)
+    where
+        F: FnOnce(&Sender<notify::Result<Event>>),
+    {
+        let (sender, events) = mpsc::channel();
+
+        let processing = thread::spawn(move || {
+            let mut batches = Vec::<Vec<PathBuf>>::new();
+            let mut applied_at = Instant::now();
+            let mut persisted = 0;
+
+            process_events(
+                &events,
+                &TEST_TIMING,
+                |path| {
+                    // paths applied together belong to the same batch
+                    if batches.is_empty() || applied_at.elapsed() > TEST_TIMING.debounce / 2 {
+                        batches.push(Vec::new());
+                    }
+                    batches.last_mut().unwrap().push(path.to_path_buf());
+                    applied_at = Instant::now();
+
+                    apply(path)
+                },
+                || persisted += 1,
+            );
+
+            for batch in &mut batches {
+                batch.sort();
+            }
+
+            (batches, persisted)
+        });
+
+        send_events(&sender);
+        drop(sender);
+
+        processing.join().unwrap()
+    }
+
+    #[test]
+    fn bursts_of_changes_are_applied_once_the_folder_is_quiet() {
+        let modify = EventKind::Modify(ModifyKind::Any);
+
+        let (batches, _) = process(
+            |_| true,
+            |events| {
+                send(events, modify, "/d/a");
+                thread::sleep(Duration::from_millis(10));
+                send(events, modify, "/d/a");
+                send(events, EventKind::Access(AccessKind::Any), "/d/c");
+                send(events, modify, "/d/b");
+                thread::sleep(Duration::from_millis(200));
+
+                send(events, modify, "/d/a");
+                thread::sleep(Duration::from_millis(200));
+            },
+        );
+
+        // each path once per burst, accesses are ignored
+        assert_eq!(
+            batches,
+            [
+                vec![PathBuf::from("/d/a"), PathBuf::from("/d/b")],
+                vec![PathBuf::from("/d/a")],
+            ]
+        );
+    }
+
+    #[test]
+    fn the_model_is_persisted_only_after_it_changed() {
+        let modify = EventKind::Modify(ModifyKind::Any);
+
+        // the changes do not modify the model
+        let (batches, persisted) = process(
+            |_| false,
+            |events| {
+                send(events, modify, "/d/a");
+                thread::sleep(Duration::from_millis(400));
+            },
+        );
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(persisted, 0);
+
+        // the batches modifying the model within the interval are saved at once
+        let (batches, persisted) = process(
+            |path| path != Path::new("/d/same"),
+            |events| {
+                send(events, modify, "/d/same");
+                thread::sleep(Duration::from_millis(100));
+                send(events, modify, "/d/a");
+                thread::sleep(Duration::from_millis(100));
+                send(events, modify, "/d/b");
+                thread::sleep(Duration::from_millis(400));
+            },
+        );
+
+        assert_eq!(batches.len(), 3);
+        assert_eq!(persisted, 1);
+
+        // stopping applies the pending changes and saves them
+        let (batches, persisted) = process(|_| true, |events| send(events, modify, "/d/a"));
+
+        assert_eq!(batches, [vec![PathBuf::from("/d/a")]]);
+        assert_eq!(persisted, 1);
+
+        // and saves the changes applied since the last save
+        let (batches, persisted) = process(
+            |_| true,
+            |events| {
+                send(events, modify, "/d/a");
+                thread::sleep(Duration::from_millis(100));
+            },
+        );
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(persisted, 1);
+
+        // and nothing when nothing changed
+        let (batches, persisted) = process(|_| true, |_| {});
+
+        assert!(batches.is_empty());
+        assert_eq!(persisted, 0);
+    }
+}